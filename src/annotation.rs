@@ -0,0 +1,102 @@
+//! Notes that the surveyor adds to a running session, like "microwave on" or "door closed".
+
+use crate::gps::Position;
+use crate::record::{Event, RecordWriter};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::info;
+
+/// A note on the conditions of a run, written as an `annotation` event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// time at which the note was added, in seconds since the start of the run
+    pub time: f64,
+    pub text: String,
+    pub position: Position,
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:3.1} Note \"{}\" at {}",
+            self.time, self.text, self.position
+        )
+    }
+}
+
+/// Writes notes with the time and the current position to the session.
+/// Clones count the time from the same start, so the notes of stdin, the web and the terminal dashboard line up.
+#[derive(Clone)]
+pub struct Annotator {
+    output: RecordWriter,
+    position: Arc<Mutex<Position>>,
+    started: Instant,
+}
+
+impl fmt::Debug for Annotator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Annotator")
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Annotator {
+    /// Starts the clock of the run, so it should be created along with the header.
+    pub fn new(output: RecordWriter, position: Arc<Mutex<Position>>) -> Self {
+        Annotator {
+            output,
+            position,
+            started: Instant::now(),
+        }
+    }
+
+    /// Writes `text` without surrounding whitespace as an annotation, unless it is blank.
+    pub fn annotate(&self, text: &str) -> io::Result<Option<Annotation>> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(None);
+        }
+        let annotation = Annotation {
+            time: self.started.elapsed().as_secs_f64(),
+            text: text.to_string(),
+            position: self.position.lock().unwrap().clone(),
+        };
+        info!("{}", annotation);
+        self.output.write(Event::Annotation(annotation.clone()))?;
+        Ok(Some(annotation))
+    }
+
+    /// Start of the run that the notes count from, for the Senders to time their intervals from as well.
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn writes_trimmed_notes_at_the_position() {
+        let output = RecordWriter::default();
+        let records = output.subscribe();
+        let position = Position::new("2023-04-01T12:00:00.000Z".to_string(), 52.0, 13.0);
+        let annotator = Annotator::new(output, Arc::new(Mutex::new(position.clone())));
+
+        assert_eq!(annotator.annotate(" \n").unwrap(), None);
+        let annotation = annotator.annotate("microwave on\n").unwrap().unwrap();
+        assert_eq!(annotation.text, "microwave on");
+        assert_eq!(annotation.position, position);
+        assert_eq!(
+            records.try_recv().unwrap(),
+            Record::Event(Event::Annotation(annotation))
+        );
+        assert!(records.try_recv().is_err());
+    }
+}
@@ -1,18 +1,39 @@
+pub mod config;
 pub mod modes;
 
+use crate::error::NetMapError;
 use clap::Parser;
 use modes::RunMode;
+use std::env;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(
     author = "Tuan-Dat Tran <tuan-dat.tran@tudattr.dev>",
     version = "0.1.0",
     about = "NetMap is a network performance testing tool that allows users to record bandwidths at different physical locations of their wireless network using customized UDP traffic and GPS tracking.",
-    long_about
+    long_about,
+    args_override_self = true
 )]
-
 pub struct Cli {
+    /// TOML file with defaults and profiles [default: netmap.toml, else ~/.config/netmap/config.toml]
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// run the named profile of the config file; flags on the command line override its values
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
     /// set whether to run as a sender or receiver
     #[command(subcommand)]
     pub mode: RunMode,
 }
+
+impl Cli {
+    /// Parses the command line on top of the defaults and the chosen profile of the config file.
+    /// Like `Cli::parse`, exits with the usage on invalid arguments.
+    pub fn parse_with_config() -> Result<Self, NetMapError> {
+        let args = config::expand(env::args_os().collect())?;
+        Ok(Self::parse_from(args))
+    }
+}
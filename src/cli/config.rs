@@ -0,0 +1,408 @@
+use super::Cli;
+use crate::error::NetMapError;
+use clap::{Arg, Command, CommandFactory};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file looked for in the working directory when no `--config` is given.
+const LOCAL_CONFIG: &str = "netmap.toml";
+
+/// Option values keyed by their long flag, e.g. `data_rate` or `data-rate` for `--data-rate`.
+type Options = toml::Table;
+
+/// Defaults and named profiles for the command line, read from a TOML file.
+///
+/// ```toml
+/// [defaults]
+/// target_ip = "192.168.1.10"
+///
+/// [profiles.voip-survey]
+/// mode = "sender"
+/// data_rate = 0.1
+/// packet_size = 200
+/// dscp = 46
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Options for every mode that has them.
+    #[serde(default)]
+    pub defaults: Options,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Named set of options for one mode.
+#[derive(Deserialize, Debug)]
+pub struct Profile {
+    /// Subcommand the profile runs, e.g. `sender` or `probe`.
+    pub mode: String,
+    #[serde(flatten)]
+    pub options: Options,
+}
+
+/// Global options and the position of the subcommand on a command line.
+struct GlobalArgs {
+    config: Option<PathBuf>,
+    profile: Option<String>,
+    subcommand: usize,
+}
+
+impl GlobalArgs {
+    fn scan(args: &[OsString]) -> Self {
+        let mut global = GlobalArgs {
+            config: None,
+            profile: None,
+            subcommand: 1,
+        };
+        while let Some(arg) = args.get(global.subcommand).and_then(|arg| arg.to_str()) {
+            if !arg.starts_with('-') {
+                break;
+            }
+            let (flag, value, used) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string()), 1),
+                None => {
+                    let value = args.get(global.subcommand + 1).and_then(|v| v.to_str());
+                    (arg, value.map(str::to_string), 2)
+                }
+            };
+            global.subcommand += match flag {
+                "--config" => {
+                    global.config = value.map(PathBuf::from);
+                    used
+                }
+                "--profile" => {
+                    global.profile = value;
+                    used
+                }
+                _ => 1,
+            };
+        }
+        global
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, NetMapError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| NetMapError::Config(format!("couldn't read {}: {}", path.display(), e)))?;
+        toml::from_str(&text).map_err(|e| NetMapError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The config file used without `--config`: netmap.toml in the working directory,
+    /// else netmap/config.toml in the user's config directory, if either exists.
+    pub fn default_path() -> Option<PathBuf> {
+        let local = PathBuf::from(LOCAL_CONFIG);
+        if local.is_file() {
+            return Some(local);
+        }
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(dir.join("netmap").join("config.toml")).filter(|path| path.is_file())
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, NetMapError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| NetMapError::Config(format!("no profile named {}", name)))
+    }
+
+    /// Inserts the defaults and the profile chosen with `--profile` into `args` right after the subcommand,
+    /// so the flags given on the command line come later and override them.
+    /// Without a subcommand, the one of the profile is run.
+    pub fn apply(&self, mut args: Vec<OsString>) -> Result<Vec<OsString>, NetMapError> {
+        let global = GlobalArgs::scan(&args);
+        let profile = global
+            .profile
+            .as_deref()
+            .map(|name| self.profile(name).map(|profile| (name, profile)))
+            .transpose()?;
+        let mode = match (args.get(global.subcommand), profile) {
+            (Some(mode), _) => mode.to_string_lossy().into_owned(),
+            (None, Some((_, profile))) => profile.mode.clone(),
+            (None, None) => return Ok(args),
+        };
+        // Leave unknown subcommands and `help` to clap.
+        let command = Cli::command();
+        let Some(subcommand) = command.find_subcommand(&mode) else {
+            return Ok(args);
+        };
+        let find = |name: &str| {
+            subcommand
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name))
+        };
+        let given = given_options(
+            subcommand,
+            args.get(global.subcommand + 1..).unwrap_or_default(),
+        );
+
+        // A profile option gives way to the command line, a default to both.
+        let mut chosen = Vec::new();
+        if let Some((name, profile)) = profile {
+            if profile.mode != mode {
+                return Err(NetMapError::Config(format!(
+                    "profile {} is for the {} mode, not {}",
+                    name, profile.mode, mode
+                )));
+            }
+            for (key, value) in &profile.options {
+                let option = key.replace('_', "-");
+                let Some(arg) = find(&option) else {
+                    return Err(NetMapError::Config(format!(
+                        "profile {}: the {} mode has no option --{}",
+                        name, mode, option
+                    )));
+                };
+                if !given.iter().any(|given| conflict(subcommand, arg, given)) {
+                    chosen.push((arg, value));
+                }
+            }
+        }
+        let mut options = Vec::new();
+        for (key, value) in &self.defaults {
+            let Some(arg) = find(&key.replace('_', "-")) else {
+                continue;
+            };
+            let overridden = given
+                .iter()
+                .chain(chosen.iter().map(|(arg, _)| arg))
+                .any(|other| conflict(subcommand, arg, other));
+            if !overridden {
+                push_option(&mut options, subcommand, arg, value)?;
+            }
+        }
+        for (arg, value) in chosen {
+            push_option(&mut options, subcommand, arg, value)?;
+        }
+
+        let rest = args.split_off(global.subcommand);
+        args.push(mode.into());
+        args.extend(options);
+        args.extend(rest.into_iter().skip(1));
+        Ok(args)
+    }
+}
+
+/// Options given on the command line after the subcommand, by their long or short flag.
+fn given_options<'a>(subcommand: &'a Command, args: &[OsString]) -> Vec<&'a Arg> {
+    args.iter()
+        .filter_map(|arg| arg.to_str())
+        .take_while(|arg| *arg != "--")
+        .filter_map(|arg| match arg.strip_prefix("--") {
+            Some(long) => {
+                let long = long.split('=').next()?;
+                subcommand
+                    .get_arguments()
+                    .find(|arg| arg.get_long() == Some(long))
+            }
+            None => {
+                let short = arg.strip_prefix('-')?.chars().next()?;
+                subcommand
+                    .get_arguments()
+                    .find(|arg| arg.get_short() == Some(short))
+            }
+        })
+        .collect()
+}
+
+/// Whether `a` and `b` set the same option or options that exclude each other, like `--dscp` and `--tos`.
+fn conflict(subcommand: &Command, a: &Arg, b: &Arg) -> bool {
+    let conflicts = |a: &Arg, b: &Arg| {
+        subcommand
+            .get_arg_conflicts_with(a)
+            .iter()
+            .any(|arg| arg.get_id() == b.get_id())
+    };
+    a.get_id() == b.get_id() || conflicts(a, b) || conflicts(b, a)
+}
+
+/// Adds `--name=value` for `arg` to `args`, with arrays as comma separated values, only the flag for `true`
+/// and the `--no-` flag for `false`, if the mode has one.
+fn push_option(
+    args: &mut Vec<OsString>,
+    subcommand: &Command,
+    arg: &Arg,
+    value: &toml::Value,
+) -> Result<(), NetMapError> {
+    let name = arg
+        .get_long()
+        .expect("options are found by their long flag");
+    let value = match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Datetime(d) => d.to_string(),
+        toml::Value::Boolean(true) => {
+            args.push(format!("--{}", name).into());
+            return Ok(());
+        }
+        toml::Value::Boolean(false) => {
+            let negated = format!("no-{}", name);
+            if subcommand
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some(negated.as_str()))
+            {
+                args.push(format!("--{}", negated).into());
+            }
+            return Ok(());
+        }
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+        toml::Value::Table(_) => {
+            return Err(NetMapError::Config(format!("--{} can't be a table", name)));
+        }
+    };
+    args.push(format!("--{}={}", name, value).into());
+    Ok(())
+}
+
+/// Reads the config file named with `--config`, or the default one, and applies it to `args`.
+pub fn expand(args: Vec<OsString>) -> Result<Vec<OsString>, NetMapError> {
+    let global = GlobalArgs::scan(&args);
+    let config = match global.config {
+        Some(path) => Config::load(&path)?,
+        None => match Config::default_path() {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        },
+    };
+    config.apply(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::modes::RunMode;
+    use clap::Parser;
+
+    const CONFIG: &str = r#"
+        [defaults]
+        target_ip = "10.0.0.2"
+        data_rate = 5
+        tos = 32
+        tiles = true
+
+        [profiles.voip-survey]
+        mode = "sender"
+        data_rate = 0.1
+        packet_size = 200
+        dscp = 46
+        tui = true
+
+        [profiles.plain-report]
+        mode = "report"
+        tiles = false
+
+        [profiles.max-capacity]
+        mode = "probe"
+        max_rate = 500
+    "#;
+
+    fn parse(args: &[&str]) -> Result<Cli, NetMapError> {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let args = config.apply(args.iter().map(OsString::from).collect())?;
+        Ok(Cli::try_parse_from(args).unwrap())
+    }
+
+    #[test]
+    fn command_line_overrides_profile_and_defaults() {
+        let cli = parse(&["netmap", "--profile", "voip-survey", "sender", "-s", "300"]).unwrap();
+        let RunMode::Sender {
+            target,
+            data_rate,
+            packet_size,
+            marking,
+            ..
+        } = cli.mode
+        else {
+            panic!("not a sender: {:?}", cli.mode);
+        };
+        assert_eq!(target.target_ip.to_string(), "10.0.0.2");
+        assert_eq!(data_rate, 0.1);
+        assert_eq!(packet_size, 300);
+        assert_eq!(marking.dscp, Some(46));
+    }
+
+    #[test]
+    fn profile_picks_mode_and_skips_foreign_defaults() {
+        let cli = parse(&["netmap", "--profile=max-capacity"]).unwrap();
+        assert!(matches!(cli.mode, RunMode::Probe { probe, .. } if probe.max_rate == 500.0));
+
+        let cli = parse(&["netmap", "receiver"]).unwrap();
+        assert!(matches!(cli.mode, RunMode::Receiver { .. }));
+
+        assert!(parse(&["netmap", "--profile", "voip-survey", "probe"]).is_err());
+        assert!(parse(&["netmap", "--profile", "unknown", "sender"]).is_err());
+    }
+
+    #[test]
+    fn command_line_drops_conflicting_options() {
+        let marking = |args: &[&str]| match parse(args).unwrap().mode {
+            RunMode::Sender { marking, .. } => (marking.dscp, marking.tos),
+            mode => panic!("not a sender: {:?}", mode),
+        };
+        assert_eq!(marking(&["netmap", "sender"]), (None, Some(32)));
+        assert_eq!(
+            marking(&["netmap", "--profile", "voip-survey", "sender"]),
+            (Some(46), None)
+        );
+        assert_eq!(
+            marking(&[
+                "netmap",
+                "--profile",
+                "voip-survey",
+                "sender",
+                "--tos",
+                "184"
+            ]),
+            (None, Some(184))
+        );
+        assert_eq!(
+            marking(&["netmap", "--profile=voip-survey", "sender", "--dscp=10"]),
+            (Some(10), None)
+        );
+    }
+
+    #[test]
+    fn bool_options_can_be_turned_off() {
+        let tui = |args: &[&str]| match parse(args).unwrap().mode {
+            RunMode::Sender { tui, .. } => tui,
+            mode => panic!("not a sender: {:?}", mode),
+        };
+        assert!(tui(&["netmap", "--profile", "voip-survey", "sender"]));
+        assert!(!tui(&[
+            "netmap",
+            "--profile",
+            "voip-survey",
+            "sender",
+            "--no-tui"
+        ]));
+        assert!(tui(&["netmap", "sender", "--no-tui", "--tui"]));
+
+        let tiles = |args: &[&str]| match parse(args).unwrap().mode {
+            RunMode::Report { tiles, .. } => tiles,
+            mode => panic!("not a report: {:?}", mode),
+        };
+        assert!(tiles(&["netmap", "report", "run.jsonl"]));
+        assert!(!tiles(&["netmap", "report", "run.jsonl", "--no-tiles"]));
+        assert!(!tiles(&[
+            "netmap",
+            "--profile",
+            "plain-report",
+            "report",
+            "run.jsonl"
+        ]));
+    }
+}
@@ -1,55 +1,258 @@
-use clap::{Subcommand, ValueEnum};
+use crate::clients::{ProbeConfig, ProbeStrategy, Protocol, SurveyStep};
+use crate::session::GpsSource;
+use clap::{ArgAction, Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 const DEFAULT_TARGET_IP: &str = "127.0.0.1";
 const DEFAULT_SENDER_PORT: u16 = 1234;
 const DEFAULT_RECEIVER_PORT: u16 = 4321;
 const DEFAULT_DATA_RATE: f64 = 1.0; // in mbps
 const DEFAULT_PACKET_SIZE: usize = 1500; // in bytes
-const DEFAULT_SLEEP_ADJUST: u32 = 10;
+const DEFAULT_PROBE_START_RATE: f64 = 1.0; // in mbps
+const DEFAULT_PROBE_MAX_RATE: f64 = 100.0; // in mbps
+const DEFAULT_PROBE_RATE_STEP: f64 = 1.0; // in mbps
+const DEFAULT_PROBE_STEP_DURATION: u64 = 5; // in seconds
+const DEFAULT_PROBE_MAX_LOSS: f64 = 1.0; // in percent
+const DEFAULT_SWEEP_MIN_SIZE: usize = 576; // in bytes
+const DEFAULT_SWEEP_MAX_SIZE: usize = 1500; // in bytes
+const DEFAULT_SWEEP_SIZE_STEP: usize = 100; // in bytes
+const DEFAULT_SWEEP_STEP_DURATION: u64 = 3; // in seconds
+const DEFAULT_WMM_DURATION: u64 = 10; // in seconds per access category
+const DEFAULT_COMPARE_RADIUS: f64 = 10.0; // in meters
+const DEFAULT_COMPARE_THROUGHPUT_DROP: f64 = 10.0; // in percent
+const DEFAULT_COMPARE_LOSS_INCREASE: f64 = 1.0; // in percentage points
+const DEFAULT_COMPARE_RTT_INCREASE: f64 = 5.0; // in milliseconds
+const DEFAULT_COMPARE_MIN_INTERVALS: usize = 3;
+const DEFAULT_SURVEY_PLAN: [SurveyStep; 2] = [SurveyStep::Udp(10), SurveyStep::Latency(10)];
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Subcommand)]
+/// The options are stored in the header of every session file, so options added later need `#[serde(default)]`
+/// for [`SessionHeader::run_mode`](crate::session::SessionHeader::run_mode) to decode older files.
+/// Options shared by several modes are flattened into them, both on the command line and in the header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Subcommand)]
+#[serde(rename_all = "snake_case")]
 pub enum RunMode {
     /// Run as the Sender.
     Sender {
-        /// port to bind to for sending data
-        #[arg(short = 'p', long, default_value_t = DEFAULT_SENDER_PORT)]
-        port: u16,
+        #[command(flatten)]
+        #[serde(flatten)]
+        target: TargetArgs,
+
+        /// data rate in mbps
+        #[arg(short = 'b', long, default_value_t = DEFAULT_DATA_RATE)]
+        data_rate: f64,
+
+        /// IP packet size in bytes, IP and UDP header included
+        #[arg(short = 's', long, default_value_t = DEFAULT_PACKET_SIZE)]
+        packet_size: usize,
 
-        /// target IP address to send data to
-        #[arg(short = 'T', long, default_value_t = IpAddr::from_str(DEFAULT_TARGET_IP).unwrap())]
-        target_ip: IpAddr,
+        /// transport protocol; TCP saturates the link and ignores the data rate
+        #[arg(long, default_value_t = Protocol::Udp)]
+        protocol: Protocol,
+
+        /// deprecated and ignored, the packets are paced against the clock
+        #[arg(short = 'f', long, hide = true)]
+        #[serde(skip)]
+        sleep_adjust: Option<u32>,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        marking: MarkingArgs,
+
+        /// scan for access points every this many seconds; scanning briefly takes the radio off channel
+        #[arg(long)]
+        scan_interval: Option<u64>,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        recording: RecordingArgs,
+
+        /// show a live dashboard in the terminal instead of printing the records
+        #[arg(long, overrides_with = "no_tui")]
+        #[serde(default)]
+        tui: bool,
+
+        /// print the records, even if the config file asks for the dashboard
+        #[arg(long, overrides_with = "tui")]
+        #[serde(skip)]
+        no_tui: bool,
+
+        /// serve a live dashboard with start, stop and mark controls on this address, e.g. 0.0.0.0:8080
+        #[arg(long)]
+        #[serde(default)]
+        web: Option<SocketAddr>,
+    },
+    /// Run as the Sender and search for the highest sustainable data rate at the current location.
+    Probe {
+        #[command(flatten)]
+        #[serde(flatten)]
+        target: TargetArgs,
 
-        /// target port to send data to
-        #[arg(short = 'P', long, default_value_t = DEFAULT_RECEIVER_PORT)]
-        target_port: u16,
+        /// IP packet size in bytes, IP and UDP header included
+        #[arg(short = 's', long, default_value_t = DEFAULT_PACKET_SIZE)]
+        packet_size: usize,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        probe: ProbeArgs,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        recording: RecordingArgs,
+    },
+    /// Run as the Sender and measure throughput and loss for a range of packet sizes to find the path MTU.
+    Sweep {
+        #[command(flatten)]
+        #[serde(flatten)]
+        target: TargetArgs,
+
+        /// data rate in mbps
+        #[arg(short = 'b', long, default_value_t = DEFAULT_DATA_RATE)]
+        data_rate: f64,
+
+        /// smallest IP packet size in bytes
+        #[arg(long, default_value_t = DEFAULT_SWEEP_MIN_SIZE)]
+        min_size: usize,
+
+        /// largest IP packet size in bytes
+        #[arg(long, default_value_t = DEFAULT_SWEEP_MAX_SIZE)]
+        max_size: usize,
+
+        /// increase of the packet size between steps in bytes
+        #[arg(long, default_value_t = DEFAULT_SWEEP_SIZE_STEP)]
+        size_step: usize,
+
+        /// duration of each step in seconds
+        #[arg(long, default_value_t = DEFAULT_SWEEP_STEP_DURATION)]
+        step_duration: u64,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        recording: RecordingArgs,
+    },
+    /// Run as the Sender once for each WMM access category (voice, video, best effort, background).
+    Wmm {
+        #[command(flatten)]
+        #[serde(flatten)]
+        target: TargetArgs,
 
         /// data rate in mbps
         #[arg(short = 'b', long, default_value_t = DEFAULT_DATA_RATE)]
         data_rate: f64,
 
-        /// packet size in bytes
+        /// IP packet size in bytes, IP and UDP header included
         #[arg(short = 's', long, default_value_t = DEFAULT_PACKET_SIZE)]
         packet_size: usize,
 
-        /// adjust the sleep by this factor
-        #[arg(short = 'f', long, default_value_t = DEFAULT_SLEEP_ADJUST)]
-        sleep_adjust: u32,
+        /// duration of the test per access category in seconds
+        #[arg(long, default_value_t = DEFAULT_WMM_DURATION)]
+        duration: u64,
 
-        /// file to write output to
-        #[arg(short = 'o', long)]
+        #[command(flatten)]
+        #[serde(flatten)]
+        recording: RecordingArgs,
+    },
+    /// Run as the Sender and repeat a plan of tests at every waypoint of a site survey.
+    Survey {
+        #[command(flatten)]
+        #[serde(flatten)]
+        target: TargetArgs,
+
+        /// tests to run at every waypoint: udp:SECONDS, latency:SECONDS, probe or wmm:SECONDS
+        #[arg(long, value_delimiter = ',', action = ArgAction::Set, default_values_t = DEFAULT_SURVEY_PLAN)]
+        plan: Vec<SurveyStep>,
+
+        /// data rate of the udp and wmm tests in mbps
+        #[arg(short = 'b', long, default_value_t = DEFAULT_DATA_RATE)]
+        data_rate: f64,
+
+        /// IP packet size of the udp, wmm and probe tests in bytes, IP and UDP header included
+        #[arg(short = 's', long, default_value_t = DEFAULT_PACKET_SIZE)]
+        packet_size: usize,
+
+        /// start the next waypoint after moving this many meters; Enter always starts it
+        #[arg(long)]
+        every_meters: Option<f64>,
+
+        /// start the next waypoint this many seconds after the tests of the last one
+        #[arg(long)]
+        every_seconds: Option<u64>,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        probe: ProbeArgs,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        recording: RecordingArgs,
+    },
+    /// Compare two session files of the same path location by location and flag the regressions.
+    Compare {
+        /// session file to compare against, e.g. from before an upgrade
+        baseline: PathBuf,
+
+        /// session file to compare
+        current: PathBuf,
+
+        /// match the intervals of both sessions that are at most this many meters apart
+        #[arg(short, long, default_value_t = DEFAULT_COMPARE_RADIUS)]
+        radius: f64,
+
+        /// GeoJSON file with zone polygons to match the intervals by zone instead of by position
+        #[arg(long)]
+        #[serde(default)]
+        zones: Option<PathBuf>,
+
+        /// drop of the median throughput in percent that counts as a regression
+        #[arg(long, default_value_t = DEFAULT_COMPARE_THROUGHPUT_DROP)]
+        throughput_drop: f64,
+
+        /// increase of the loss in percentage points that counts as a regression
+        #[arg(long, default_value_t = DEFAULT_COMPARE_LOSS_INCREASE)]
+        loss_increase: f64,
+
+        /// increase of the 95th percentile round trip time in milliseconds that counts as a regression
+        #[arg(long, default_value_t = DEFAULT_COMPARE_RTT_INCREASE)]
+        rtt_increase: f64,
+
+        /// intervals both sessions need at a location to compare it
+        #[arg(long, default_value_t = DEFAULT_COMPARE_MIN_INTERVALS)]
+        min_intervals: usize,
+
+        /// file to write the locations with their changes to as GeoJSON
+        #[arg(long)]
+        geojson: Option<PathBuf>,
+
+        /// file to write a table of the locations to as HTML
+        #[arg(long)]
+        html: Option<PathBuf>,
+    },
+    /// Write a session file as a single HTML report with a map, charts and summary.
+    Report {
+        /// session file to report on
+        session: PathBuf,
+
+        /// file to write the report to, the session file with an .html extension by default
+        #[arg(short, long)]
         output_file: Option<PathBuf>,
 
-        /// set the method by which the GPS information is provided
-        #[arg(short, long, default_value_t = GpsMode::Phone)]
-        gps_mode: GpsMode,
+        /// title of the report, the name of the session file by default
+        #[arg(long)]
+        title: Option<String>,
+
+        /// draw OpenStreetMap tiles behind the map, which needs a connection when opening the report
+        #[arg(long, overrides_with = "no_tiles")]
+        #[serde(default)]
+        tiles: bool,
 
-        /// Path to the GPS device
-        #[arg(short = 'd', long, default_value = "/dev/USB0")]
-        gps_device: String,
+        /// leave the tiles out, even if the config file asks for them
+        #[arg(long, overrides_with = "tiles")]
+        #[serde(skip)]
+        no_tiles: bool,
     },
     /// Run as the Receiver.
     Receiver {
@@ -57,44 +260,258 @@ pub enum RunMode {
         #[arg(short, long, default_value_t = DEFAULT_RECEIVER_PORT)]
         port: u16,
 
+        /// transport protocol to accept
+        #[arg(long, default_value_t = Protocol::Udp)]
+        protocol: Protocol,
+
+        /// network interface to receive on, e.g. wlan0
+        #[arg(short = 'i', long)]
+        interface: Option<String>,
+
         /// file to write output to
         #[arg(short, long)]
         output_file: Option<PathBuf>,
     },
 }
 
+/// Where a Sender sends its traffic to and from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Args)]
+pub struct TargetArgs {
+    /// port to bind to for sending data
+    #[arg(short = 'p', long, default_value_t = DEFAULT_SENDER_PORT)]
+    pub port: u16,
+
+    /// target IP address to send data to
+    #[arg(short = 'T', long, default_value_t = IpAddr::from_str(DEFAULT_TARGET_IP).unwrap())]
+    pub target_ip: IpAddr,
+
+    /// target port to send data to
+    #[arg(short = 'P', long, default_value_t = DEFAULT_RECEIVER_PORT)]
+    pub target_port: u16,
+
+    /// network interface to send over, e.g. wlan0
+    #[arg(short = 'i', long)]
+    pub interface: Option<String>,
+}
+
+impl TargetArgs {
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.target_ip, self.target_port)
+    }
+}
+
+/// QoS marking of the packets of a Sender.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Args)]
+pub struct MarkingArgs {
+    /// DSCP to mark the packets with
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..64), conflicts_with = "tos")]
+    pub dscp: Option<u8>,
+
+    /// TOS byte (IPv4) or traffic class (IPv6) to mark the packets with
+    #[arg(long)]
+    pub tos: Option<u8>,
+}
+
+impl MarkingArgs {
+    /// TOS byte to mark the packets with, from the DSCP or as given.
+    pub fn tos(&self) -> Option<u8> {
+        self.dscp.map(|dscp| dscp << 2).or(self.tos)
+    }
+}
+
+/// Where the records of a Sender are written and where their positions come from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Args)]
+pub struct RecordingArgs {
+    /// file to write the session to
+    #[arg(short = 'o', long)]
+    pub output_file: Option<PathBuf>,
+
+    /// set the method by which the GPS information is provided
+    #[arg(short, long, default_value_t = GpsMode::Phone)]
+    pub gps_mode: GpsMode,
+
+    /// Path to the GPS device, or to the floor plan in indoor mode
+    #[arg(short = 'd', long, default_value = "/dev/USB0")]
+    pub gps_device: String,
+
+    /// place every interval between the GPS fixes around it, holding it back at most this many seconds for the next fix
+    #[arg(long)]
+    #[serde(default)]
+    pub interpolate: Option<u64>,
+
+    /// GeoJSON file with zone polygons to label the intervals with and aggregate them by
+    #[arg(long)]
+    #[serde(default)]
+    pub zones: Option<PathBuf>,
+}
+
+impl RecordingArgs {
+    pub fn gps(&self) -> GpsSource {
+        GpsSource {
+            mode: self.gps_mode.clone(),
+            device: self.gps_device.clone(),
+        }
+    }
+}
+
+/// Search for the highest sustainable data rate, by the probe mode and the probe test of a survey.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Args)]
+pub struct ProbeArgs {
+    /// how the data rate is increased between steps
+    #[arg(short = 'S', long, default_value_t = ProbeStrategy::Binary)]
+    pub strategy: ProbeStrategy,
+
+    /// data rate of the first step in mbps
+    #[arg(long, default_value_t = DEFAULT_PROBE_START_RATE)]
+    pub start_rate: f64,
+
+    /// highest data rate to test in mbps
+    #[arg(long, default_value_t = DEFAULT_PROBE_MAX_RATE)]
+    pub max_rate: f64,
+
+    /// rate increase per step, or precision of the binary search, in mbps
+    #[arg(long, default_value_t = DEFAULT_PROBE_RATE_STEP)]
+    pub rate_step: f64,
+
+    /// duration of each step in seconds
+    #[arg(long, default_value_t = DEFAULT_PROBE_STEP_DURATION)]
+    pub step_duration: u64,
+
+    /// highest loss in percent that is still considered sustainable
+    #[arg(long, default_value_t = DEFAULT_PROBE_MAX_LOSS)]
+    pub max_loss: f64,
+
+    /// highest average round trip time in milliseconds that is still considered sustainable
+    #[arg(long)]
+    pub max_rtt: Option<u64>,
+}
+
+impl ProbeArgs {
+    /// Configuration of a probe with packets of `packet_size` bytes.
+    pub fn config(&self, packet_size: usize) -> ProbeConfig {
+        ProbeConfig {
+            strategy: self.strategy,
+            start_rate: self.start_rate,
+            max_rate: self.max_rate,
+            rate_step: self.rate_step,
+            step_duration: Duration::from_secs(self.step_duration),
+            max_loss: self.max_loss,
+            max_rtt: self.max_rtt.map(Duration::from_millis),
+            packet_size,
+        }
+    }
+}
+
 impl fmt::Display for RunMode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self {
             RunMode::Sender {
-                port: _,
-                target_ip,
-                target_port,
+                target,
+                data_rate,
+                packet_size,
+                protocol,
+                recording,
+                ..
+            } => write!(
+                f,
+                "Sender -> {}://{}@{}/{}\n{}",
+                protocol,
+                target.address(),
+                data_rate,
+                packet_size,
+                recording
+            ),
+            RunMode::Probe {
+                target,
+                packet_size,
+                probe,
+                recording,
+            } => write!(
+                f,
+                "Probe({}) -> {}@{}-{}/{}\n{}",
+                probe.strategy,
+                target.address(),
+                probe.start_rate,
+                probe.max_rate,
+                packet_size,
+                recording
+            ),
+            RunMode::Sweep {
+                target,
+                data_rate,
+                min_size,
+                max_size,
+                recording,
+                ..
+            } => write!(
+                f,
+                "Sweep -> {}@{}/{}-{}\n{}",
+                target.address(),
+                data_rate,
+                min_size,
+                max_size,
+                recording
+            ),
+            RunMode::Wmm {
+                target,
                 data_rate,
                 packet_size,
-                sleep_adjust,
-                gps_mode,
-                gps_device,
-                output_file: _,
+                duration,
+                recording,
             } => write!(
                 f,
-                "Sender({}) -> {}:{}@{}/{}\nGPS: {} {}",
-                sleep_adjust, target_ip, target_port, data_rate, packet_size, gps_mode, gps_device
+                "WMM({}s) -> {}@{}/{}\n{}",
+                duration,
+                target.address(),
+                data_rate,
+                packet_size,
+                recording
             ),
-            RunMode::Receiver {
-                port,
-                output_file: _,
-            } => write!(f, "Receiver ({})", port),
+            RunMode::Survey {
+                target,
+                plan,
+                data_rate,
+                packet_size,
+                recording,
+                ..
+            } => {
+                let plan: Vec<String> = plan.iter().map(SurveyStep::to_string).collect();
+                write!(
+                    f,
+                    "Survey({}) -> {}@{}/{}\n{}",
+                    plan.join(","),
+                    target.address(),
+                    data_rate,
+                    packet_size,
+                    recording
+                )
+            }
+            RunMode::Compare {
+                baseline, current, ..
+            } => write!(f, "Compare {} -> {}", baseline.display(), current.display()),
+            RunMode::Report { session, .. } => write!(f, "Report {}", session.display()),
+            RunMode::Receiver { port, protocol, .. } => {
+                write!(f, "Receiver ({}/{})", port, protocol)
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+impl fmt::Display for RecordingArgs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GPS: {} {}", self.gps_mode, self.gps_device)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum GpsMode {
     /// Run in a mode that takes GPS information from a GPS receiver attached to the host.
     Host,
     /// Run in a mode that takes GPS information from a phone that forwards the GPS information to the host.
     Phone,
+    /// Run without GPS and take positions entered on the floor plan that the GPS device names, for indoor surveys.
+    Indoor,
 }
 
 impl fmt::Display for GpsMode {
@@ -102,6 +519,7 @@ impl fmt::Display for GpsMode {
         match &self {
             GpsMode::Host => write!(f, "host"),
             GpsMode::Phone => write!(f, "phone"),
+            GpsMode::Indoor => write!(f, "indoor"),
         }
     }
 }
@@ -1,46 +1,267 @@
-use std::net::{SocketAddr, UdpSocket};
-use std::thread;
+mod builder;
+mod probe;
+mod roam;
+mod sockopt;
+mod survey;
+mod sweep;
+mod tcp;
+mod wmm;
+
+pub use builder::{ReceiverBuilder, SenderBuilder};
+pub use probe::{ProbeConfig, ProbeResult, ProbeStrategy};
+pub use roam::RoamEvent;
+pub use survey::{SurveyPlan, SurveyStep, Waypoint, WaypointTrigger};
+pub use sweep::{SweepConfig, SweepResult};
+pub use tcp::{TcpReceiver, TcpSender};
+pub use wmm::{AccessCategory, WmmCategory, WmmResult};
+
+use crate::error::{ErrorCounts, NetMapError};
+use crate::gps::Position;
+use crate::link::LinkInfo;
+use crate::record::{IntervalRecord, RecordWriter};
+use crate::shutdown::{Pause, Shutdown};
+use crate::stats::Welford;
+use crate::wireless::WirelessStats;
+use clap::ValueEnum;
+use roam::{EchoGap, RoamDetector};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::time::{self, MissedTickBehavior};
+
+use tracing::{debug, info, trace, warn};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// Paced UDP traffic that is echoed by the Receiver.
+    Udp,
+    /// A saturated TCP stream.
+    Tcp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            Protocol::Udp => write!(f, "udp"),
+            Protocol::Tcp => write!(f, "tcp"),
+        }
+    }
+}
+
+/// Number of bytes at the head of each payload used for the send timestamp and sequence number.
+const STAMP_SIZE: usize = 16;
+
+/// Shortest time without echoes that counts as an outage.
+const MIN_ECHO_GAP: Duration = Duration::from_millis(50);
+/// Number of consecutive missing echoes that counts as an outage at low data rates.
+const ECHO_GAP_PACKETS: u32 = 10;
+
+/// Size of the buffer the Sender receives echoes into, and thereby the largest payload it sends.
+const BUF_SIZE: usize = 9000;
+
+/// How far the pacer may fall behind before it skips packets instead of sending them in a burst.
+const MAX_PACER_LAG: Duration = Duration::from_millis(10);
 
-use tracing::{debug, info, trace};
+/// IPv4 (20 bytes) and UDP (8 bytes) header.
+const UDP_IPV4_HEADER_SIZE: usize = 28;
+/// IPv6 (40 bytes) and UDP (8 bytes) header.
+const UDP_IPV6_HEADER_SIZE: usize = 48;
 
 pub struct Sender {
     recv_socket: UdpSocket,
     target_address: SocketAddr,
+    position: Arc<Mutex<Position>>,
+    // start of the run, reference point for the times of the intervals and the timestamps written into the payloads
+    epoch: Instant,
+    // TOS byte of the outgoing packets
+    tos: Option<u8>,
+    wireless: Arc<Mutex<Option<WirelessStats>>>,
+    shutdown: Shutdown,
+    pause: Pause,
+    // survey waypoint that the measurements are tagged with
+    waypoint: Option<u32>,
 }
 
 impl Sender {
-    pub fn new(recv_socket: UdpSocket, target_address: SocketAddr) -> Self {
+    pub fn builder(target_address: SocketAddr) -> SenderBuilder {
+        SenderBuilder::new(target_address)
+    }
+
+    fn new(
+        recv_socket: UdpSocket,
+        target_address: SocketAddr,
+        position: Arc<Mutex<Position>>,
+    ) -> Self {
         Self {
             recv_socket,
             target_address,
+            position,
+            epoch: Instant::now(),
+            tos: None,
+            wireless: Arc::new(Mutex::new(None)),
+            shutdown: Shutdown::new(),
+            pause: Pause::new(),
+            waypoint: None,
+        }
+    }
+
+    /// Binds the socket for sending to `target_address`.
+    /// With an interface, the socket uses its address as source and is bound to the device, so traffic can't leave over another link.
+    /// Without one, the routing table picks the source address.
+    fn bind(
+        port: u16,
+        target_address: &SocketAddr,
+        interface: Option<&LinkInfo>,
+    ) -> Result<UdpSocket, NetMapError> {
+        let address = match interface {
+            Some(interface) => {
+                let source = interface
+                    .source_address(&target_address.ip())
+                    .ok_or_else(|| NetMapError::NoSourceAddress {
+                        interface: interface.name.clone(),
+                        target: *target_address,
+                    })?;
+                SocketAddr::new(source, port)
+            }
+            None => SocketAddr::new(unspecified(target_address), port),
+        };
+        let socket = std::net::UdpSocket::bind(address)
+            .map_err(|source| NetMapError::Bind { address, source })?;
+        if let Some(interface) = interface {
+            sockopt::bind_to_device(&socket, &interface.name)?;
         }
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket)?)
+    }
+
+    /// Marks all outgoing packets with the given TOS byte (IPv4) or traffic class (IPv6).
+    fn set_tos(&mut self, tos: u8) -> io::Result<()> {
+        sockopt::set_tos(&self.recv_socket, self.target_address.is_ipv6(), tos)?;
+        self.tos = Some(tos);
+        Ok(())
+    }
+
+    /// Marks all outgoing packets with the given DSCP.
+    fn set_dscp(&mut self, dscp: u8) -> Result<(), NetMapError> {
+        Ok(self.set_tos(tos_from_dscp(dscp)?)?)
     }
-    pub fn run(
+
+    /// Sends paced traffic until the shutdown is triggered and records every interval.
+    /// Fails without sending if `packet_size` leaves no room for the headers or exceeds the buffer.
+    pub async fn run(
         self,
         data_rate: f64,
         packet_size: usize,
-        sleep_adjust_factor: u32,
-    ) -> thread::JoinHandle<()> {
-        // const BUF_SIZE: usize =  65536;
-        const BUF_SIZE: usize = 9000;
-        let mut buf = [0; BUF_SIZE]; // Increase the buffer size to hold at least 9000 bytes
+        output: RecordWriter,
+    ) -> Result<(), NetMapError> {
+        Self::payload_size(packet_size, &self.target_address)?;
+        let mut roam_detector = RoamDetector::new();
+
+        self.transmit(data_rate, packet_size, None, |interval| {
+            let record = self.record(interval);
+            let event = roam_detector.update(
+                interval.to,
+                &interval.gaps,
+                interval.silent_since,
+                record.wireless.as_ref(),
+                &record.position,
+            );
+            if let Err(e) = output.write(record) {
+                warn!("Couldn't write interval record: {}", e);
+            }
+
+            if let Some(event) = event {
+                if let Err(e) = output.write(event) {
+                    warn!("Couldn't write roam event: {}", e);
+                }
+            }
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Size of the IP and UDP headers in front of each payload sent to `target_address`.
+    pub fn header_size(target_address: &SocketAddr) -> usize {
+        match target_address {
+            SocketAddr::V4(_) => UDP_IPV4_HEADER_SIZE,
+            SocketAddr::V6(_) => UDP_IPV6_HEADER_SIZE,
+        }
+    }
+
+    /// Payload size of an IP packet of `packet_size` bytes sent to `target_address`.
+    pub fn payload_size(
+        packet_size: usize,
+        target_address: &SocketAddr,
+    ) -> Result<usize, NetMapError> {
+        let header_size = Self::header_size(target_address);
+        match packet_size.checked_sub(header_size) {
+            Some(payload_size) if payload_size <= BUF_SIZE => Ok(payload_size),
+            _ => Err(NetMapError::InvalidPacketSize {
+                packet_size,
+                min: header_size,
+                max: BUF_SIZE + header_size,
+            }),
+        }
+    }
 
-        const UDP_HEADER_SIZE: usize = 42;
-        let payload_size: usize = packet_size - UDP_HEADER_SIZE; // size of packets to send in bytes
+    /// Tags the interval with the current position.
+    fn record(&self, interval: &Interval) -> IntervalRecord {
+        IntervalRecord {
+            protocol: Protocol::Udp,
+            from: interval.from.as_secs_f64(),
+            to: interval.to.as_secs_f64(),
+            position: self.position.lock().unwrap().clone(),
+            bytes: interval.bytes,
+            throughput: interval.throughput / 1000000.0,
+            packets_sent: Some(interval.packets_sent),
+            packets_received: Some(interval.packets_received),
+            loss: Some(interval.loss),
+            rtt: interval.rtt.mean(),
+            rtt_stddev: interval.rtt.std_dev(),
+            errors: Some(interval.errors),
+            retransmits: None,
+            cwnd: None,
+            congestion_control: None,
+            dscp: self.tos.map(|tos| tos >> 2),
+            access_category: self.tos.map(|tos| AccessCategory::from_dscp(tos >> 2)),
+            wireless: self.wireless.lock().unwrap().clone(),
+            waypoint: self.waypoint,
+            zones: Vec::new(),
+        }
+    }
 
-        let payload: Vec<u8> = vec![0; payload_size]; // create a vector of 0s with the specified packet size
+    /// Sends paced traffic at `data_rate` Mbit/s and hands every one second interval to `on_interval`.
+    /// Runs until the shutdown is triggered if no `duration` is given.
+    ///
+    /// The pacer, the echo receiver and the interval reporter run concurrently and only wake up for a due packet,
+    /// an arriving echo or the end of an interval.
+    async fn transmit<F: FnMut(&Interval)>(
+        &self,
+        data_rate: f64,
+        packet_size: usize,
+        duration: Option<Duration>,
+        mut on_interval: F,
+    ) {
+        // size of packets to send in bytes, the public methods already checked that it fits
+        let payload_size = match Self::payload_size(packet_size, &self.target_address) {
+            Ok(payload_size) => payload_size,
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        };
         let packet_size_bits = payload_size * 8; // size of packets in bits
 
-        // Initial interval at which to sleep
-        let total_iteration_duration = packet_size_bits as f64 / (data_rate * 1000000.0); // interval in seconds to pause the loop
+        // Interval between two packets
+        let total_iteration_duration = packet_size_bits as f64 / (data_rate * 1000000.0); // interval in seconds
         let total_iteration_duration = total_iteration_duration * 1_000_000_000.0; // convert to nanoseconds
-        let target_iteration_duration = Duration::from_nanos(total_iteration_duration as u64);
-
-        // Start a timer to measure the elapsed time.
-        let total_time = Instant::now();
-        let mut iteration_start = Instant::now();
-        let mut sleep_duration = target_iteration_duration;
+        let target_iteration_duration =
+            Duration::from_nanos((total_iteration_duration as u64).max(1));
 
         // Log the data rate, packet size, and interval.
         info!(
@@ -48,101 +269,165 @@ impl Sender {
             data_rate, payload_size, target_iteration_duration
         );
 
-        let mut loop_log_data = LoopLogData::new();
+        // The intervals are timed from the start of the run, the duration from now.
+        let began = self.epoch.elapsed();
+        let loop_log_data = Mutex::new(LoopLogData::new());
+        let gap_threshold = (target_iteration_duration * ECHO_GAP_PACKETS).max(MIN_ECHO_GAP);
 
-        println!("Interval  Transfered  Throughput  received/sent (loss)");
-
-        thread::spawn(move || {
+        let pacer = async {
+            let mut payload: Vec<u8> = vec![0; payload_size]; // create a vector of 0s with the specified packet size
+            let mut sequence: u64 = 0;
+            let mut next_send = time::Instant::now();
             loop {
-                // Send the data.
-                let _ = &self
-                    .recv_socket
-                    .send_to(&payload, self.target_address)
-                    .unwrap(); // send the data
-                trace!("Sent {} bytes to {}", payload_size, &self.target_address);
-
-                match &self.recv_socket.recv_from(&mut buf) {
-                    Ok((size, src)) => {
-                        trace!("received {} bytes from {:?}", size, src); // Use logging with tracing
-                        loop_log_data.packets_received += 1;
-
-                        // trace!("data: {:?}", &buf[..size]); // Use logging with tracing
+                if self.pause.is_paused() {
+                    self.pause.resumed().await;
+                    next_send = time::Instant::now();
+                }
+                time::sleep_until(next_send).await;
+                // The timer wakes up at a millisecond granularity, so send every packet that is due by now.
+                let now = time::Instant::now();
+                if now.saturating_duration_since(next_send) > MAX_PACER_LAG {
+                    next_send = now;
+                }
+                while next_send <= now {
+                    // Stamp and send the data.
+                    Self::stamp(&mut payload, sequence, self.epoch.elapsed());
+                    sequence += 1;
+                    let result = self
+                        .recv_socket
+                        .send_to(&payload, self.target_address)
+                        .await;
+
+                    let mut loop_log_data = loop_log_data.lock().unwrap();
+                    loop_log_data.iteration_counter += 1;
+                    match result {
+                        Ok(_) => trace!("Sent {} bytes to {}", payload_size, &self.target_address),
+                        Err(e) => {
+                            // e.g. EMSGSIZE if the packet doesn't fit the path MTU and may not be fragmented
+                            trace!("Failed to send {} bytes: {}", payload_size, e);
+                            loop_log_data.send_errors += 1;
+                            loop_log_data.errors.count(&e);
+                        }
                     }
-                    Err(_e) => {}
+                    next_send += target_iteration_duration;
                 }
+            }
+        };
 
-                // Pause the loop for the specified interval
-                Self::busy_sleep(sleep_duration);
+        let echo_receiver = async {
+            let mut buf = vec![0; BUF_SIZE];
+            loop {
+                let result = self.recv_socket.recv_from(&mut buf).await;
+
+                let mut loop_log_data = loop_log_data.lock().unwrap();
+                let (size, src) = match result {
+                    Ok(received) => received,
+                    // e.g. ECONNREFUSED after an ICMP port unreachable
+                    Err(e) => {
+                        loop_log_data.errors.count(&e);
+                        continue;
+                    }
+                };
+                trace!("received {} bytes from {:?}", size, src); // Use logging with tracing
+                loop_log_data.packets_received += 1;
+
+                if let Some((echoed, sent)) = Self::read_stamp(&buf[..size]) {
+                    let rtt = self.epoch.elapsed().saturating_sub(sent);
+                    loop_log_data.rtt.add(rtt.as_secs_f64() * 1000.0);
+
+                    let now = self.epoch.elapsed();
+                    match loop_log_data.last_echo {
+                        // Late or duplicated echoes don't end an outage.
+                        Some((_, last_sequence)) if echoed <= last_sequence => {}
+                        Some((last_time, last_sequence)) => {
+                            if now - last_time >= gap_threshold {
+                                loop_log_data.gaps.push(EchoGap {
+                                    start: last_time,
+                                    end: now,
+                                    packets_lost: echoed - last_sequence - 1,
+                                });
+                            }
+                            loop_log_data.last_echo = Some((now, echoed));
+                        }
+                        None => loop_log_data.last_echo = Some((now, echoed)),
+                    }
+                }
+            }
+        };
 
-                // Increment iteration counter and iteration_timer
-                loop_log_data.iteration_counter += 1;
-                loop_log_data.sleep_timer += sleep_duration;
+        let reporter = async {
+            let mut ticks = time::interval_at(
+                time::Instant::now() + Duration::from_secs(1),
+                Duration::from_secs(1),
+            );
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = self.shutdown.wait() => break,
+                }
 
-                // Check if one second has passed.
-                let second_elapsed = loop_log_data.second_timer.elapsed();
-                if second_elapsed >= Duration::from_secs(1) {
-                    let _ = &self.loop_log(
+                let interval = {
+                    let mut loop_log_data = loop_log_data.lock().unwrap();
+                    let second_elapsed = loop_log_data.second_timer.elapsed();
+                    let mut interval = self.loop_log(
                         &loop_log_data,
                         second_elapsed,
-                        total_time.elapsed(),
+                        self.epoch.elapsed(),
                         packet_size,
                     );
+                    // An outage that is still going on at the end of the interval.
+                    let echo_time = loop_log_data.last_echo.map_or(began, |(time, _)| time);
+                    if interval.to.saturating_sub(echo_time) >= gap_threshold {
+                        interval.silent_since = Some(echo_time);
+                    }
 
                     // Reset the counters and timers.
                     loop_log_data.reset();
+                    interval
+                };
+                if !self.pause.is_paused() {
+                    on_interval(&interval);
                 }
 
-                buf = unsafe { std::mem::zeroed() };
-
-                // Calculate the elapsed time and recalculate necessary sleep_duration.
-                let real_iteration_duration = iteration_start.elapsed();
-                let former_sleep_duration = sleep_duration;
-                sleep_duration = Self::adjust_sleep(
-                    sleep_duration,
-                    real_iteration_duration,
-                    target_iteration_duration,
-                    sleep_adjust_factor,
-                );
-
-                if former_sleep_duration < sleep_duration {
-                    loop_log_data.adjustments += 1;
-                } else {
-                    loop_log_data.adjustments -= 1;
+                if duration.is_some_and(|d| self.epoch.elapsed() - began >= d) {
+                    break;
                 }
-                iteration_start = Instant::now();
             }
-        })
-    }
-
-    fn busy_sleep(duration: Duration) {
-        let start = Instant::now();
+        };
 
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed >= duration {
-                break;
-            }
+        // Only the reporter ends, at the end of the duration or on shutdown.
+        tokio::select! {
+            _ = pacer => {}
+            _ = echo_receiver => {}
+            _ = reporter => {}
         }
     }
 
-    fn adjust_sleep(
-        current: Duration,
-        actual: Duration,
-        target: Duration,
-        factor: u32,
-    ) -> Duration {
-        Self::aimd(current, actual, target, factor)
+    /// Discards all echoes that are still queued on the socket.
+    fn drain(&self) {
+        let mut buf = vec![0; 65536];
+        while self.recv_socket.try_recv_from(&mut buf).is_ok() {}
+    }
+
+    /// Writes the sequence number and the send time into the head of the payload.
+    /// The Receiver echoes the payload back, which lets the Sender compute the round trip time.
+    fn stamp(payload: &mut [u8], sequence: u64, sent: Duration) {
+        if payload.len() < STAMP_SIZE {
+            return;
+        }
+        payload[..8].copy_from_slice(&sequence.to_be_bytes());
+        payload[8..STAMP_SIZE].copy_from_slice(&(sent.as_nanos() as u64).to_be_bytes());
     }
 
-    fn aimd(current: Duration, actual: Duration, target: Duration, factor: u32) -> Duration {
-        trace!("{:?}, {:?}\n", actual, current);
-        if actual >= target {
-            // Multiplicative Decrease
-            current / 2 // Half sleeptime
-        } else {
-            // Addative Increase
-            current + (target / factor) // Add 20% of target time
+    /// Reads the sequence number and the send time from an echoed payload.
+    fn read_stamp(payload: &[u8]) -> Option<(u64, Duration)> {
+        if payload.len() < STAMP_SIZE {
+            return None;
         }
+        let sequence = u64::from_be_bytes(payload[..8].try_into().ok()?);
+        let sent = u64::from_be_bytes(payload[8..STAMP_SIZE].try_into().ok()?);
+        Some((sequence, Duration::from_nanos(sent)))
     }
 
     fn loop_log(
@@ -151,115 +436,211 @@ impl Sender {
         second_elapsed: Duration,
         total_time: Duration,
         packet_size: usize,
-    ) {
-        // The average time that each iteration of the loop took to run.
-        let avg_pass_time = second_elapsed / loop_log_data.iteration_counter;
-        // The average time that the loop slept between iterations.
-        let avg_sleep_duration = loop_log_data.sleep_timer / loop_log_data.iteration_counter;
-
-        // The average time that each iteration of the loop took to run, minus the average time that the loop slept between iterations.
-        let avg_runtime = match avg_pass_time > avg_sleep_duration {
-            true => avg_pass_time - avg_sleep_duration,
-            false => Duration::from_micros(0),
-        };
-
-        let interval_from = if total_time > Duration::from_secs(1) {
-            // Lower boundary for this interval
-            total_time - Duration::from_secs(1)
-        } else {
-            Duration::from_secs(0)
-        };
-
+    ) -> Interval {
+        let interval_from = total_time.saturating_sub(second_elapsed); // Lower boundary for this interval
         let interval_to = total_time; // Upper boundary for this interval
-        let transfered_mbytes = (loop_log_data.iteration_counter * (packet_size as u32)) as f64; // Bytes transfered in this interval
+        let packets_sent = loop_log_data.iteration_counter - loop_log_data.send_errors; // As count for this interval
+        let transfered_mbytes = (packets_sent * (packet_size as u32)) as f64; // Bytes transfered in this interval
         let throughput = (transfered_mbytes * 8.0) / second_elapsed.as_secs_f64(); // Bit-Throughput in this interval
-        let packets_sent = loop_log_data.iteration_counter; // As count for this interval
         let packets_received = loop_log_data.packets_received; // As count for this interval
-        let loss = packets_received as f64 / packets_sent as f64 * 100.0; // As percentage
+        let loss = Interval::loss(packets_sent, packets_received); // As percentage
+        let rtt = loop_log_data.rtt; // Round trip times of the echoes in ms
 
-        println!(
-            "{:3.1}-{:3.1} {:.2} Mbytes {:.2} Mbit/s {}/{} ({:3.2}%)",
+        debug!(
+            "{:3.1}-{:3.1} {:.2} Mbytes {:.2} Mbit/s {}/{} ({:3.2}%) {:.3}ms",
             interval_from.as_secs_f64(),
             interval_to.as_secs_f64(),
             transfered_mbytes / 1000000.0,
             throughput / 1000000.0,
             packets_received,
             packets_sent,
-            loss
+            loss,
+            rtt.mean().unwrap_or_default()
         );
 
-        info!(
-            "Average pass time: {:?}, Average sleep duration: {:?}, Average runtime: {:?}, Adjustments(optimal: 0): {}",
-            avg_pass_time, avg_sleep_duration, avg_runtime, loop_log_data.adjustments
-        );
+        if loop_log_data.errors.total() > 0 {
+            warn!(
+                "{} packets couldn't be sent, socket errors: {:?}",
+                loop_log_data.send_errors, loop_log_data.errors
+            );
+        }
+
+        Interval {
+            from: interval_from,
+            to: interval_to,
+            bytes: transfered_mbytes as u64,
+            throughput,
+            packets_sent,
+            packets_received,
+            send_errors: loop_log_data.send_errors,
+            errors: loop_log_data.errors,
+            loss,
+            rtt,
+            gaps: loop_log_data.gaps.clone(),
+            silent_since: None,
+        }
+    }
+}
+
+/// Measurements of one logging interval of the Sender.
+#[derive(Debug, Clone)]
+struct Interval {
+    from: Duration,
+    to: Duration,
+    // Bytes transfered in this interval
+    bytes: u64,
+    // Bit-Throughput in bit/s
+    throughput: f64,
+    packets_sent: u32,
+    packets_received: u32,
+    // Packets the socket refused to send
+    send_errors: u32,
+    // Transient socket errors while sending and receiving
+    errors: ErrorCounts,
+    // As percentage
+    loss: f64,
+    // Round trip times of the echoes in ms
+    rtt: Welford,
+    // Outages that ended in this interval
+    gaps: Vec<EchoGap>,
+    // Start of an outage that is still going on at the end of this interval
+    silent_since: Option<Duration>,
+}
+
+impl Interval {
+    /// Share of the sent packets that weren't echoed, in percent.
+    fn loss(packets_sent: u32, packets_received: u32) -> f64 {
+        match packets_sent {
+            0 => 100.0,
+            sent => sent.saturating_sub(packets_received) as f64 / sent as f64 * 100.0,
+        }
     }
 }
 
+/// Totals of several consecutive intervals.
+#[derive(Debug, Clone)]
+struct Summary {
+    // Mean throughput in Mbit/s
+    throughput: f64,
+    packets_sent: u32,
+    packets_received: u32,
+    send_errors: u32,
+    // As percentage
+    loss: f64,
+    // Mean round trip time of all echoes
+    rtt: Option<Duration>,
+}
+
+impl Summary {
+    fn new(intervals: &[Interval]) -> Self {
+        let packets_sent: u32 = intervals.iter().map(|i| i.packets_sent).sum();
+        let packets_received: u32 = intervals.iter().map(|i| i.packets_received).sum();
+        let mut throughput = Welford::new();
+        let mut rtt = Welford::new();
+        for interval in intervals {
+            throughput.add(interval.throughput / 1_000_000.0);
+            rtt.merge(&interval.rtt);
+        }
+
+        Summary {
+            throughput: throughput.mean().unwrap_or_default(),
+            packets_sent,
+            packets_received,
+            send_errors: intervals.iter().map(|i| i.send_errors).sum(),
+            loss: Interval::loss(packets_sent, packets_received),
+            rtt: rtt
+                .mean()
+                .map(|rtt| Duration::from_secs_f64(rtt.max(0.0) / 1000.0)),
+        }
+    }
+}
+
+/// Counters of the current interval, shared by the pacer, the echo receiver and the reporter.
 struct LoopLogData {
-    // debug variable to see how the congestion control adjusts the throughput
-    adjustments: i32,
     packets_received: u32,
+    // Number of packets the pacer tried to send.
     iteration_counter: u32,
-    // Add a counter variable to keep track of the number of iterations.
-    sleep_timer: Duration,
     second_timer: Instant,
+    // Round trip times in ms of all echoes with a valid stamp.
+    rtt: Welford,
+    send_errors: u32,
+    errors: ErrorCounts,
+    gaps: Vec<EchoGap>,
+    // Time since the start and sequence number of the most recent echo, to detect outages. Kept across intervals.
+    last_echo: Option<(Duration, u64)>,
 }
 
 impl LoopLogData {
     fn new() -> Self {
         LoopLogData {
-            adjustments: 0,
             packets_received: 0,
             iteration_counter: 0,
-            sleep_timer: Duration::from_micros(0),
             second_timer: Instant::now(),
+            rtt: Welford::new(),
+            send_errors: 0,
+            errors: ErrorCounts::default(),
+            gaps: Vec::new(),
+            last_echo: None,
         }
     }
     fn reset(&mut self) {
-        self.adjustments = 0;
         self.packets_received = 0;
         self.iteration_counter = 0;
-        self.sleep_timer = Duration::from_micros(0);
         self.second_timer = Instant::now();
+        self.rtt = Welford::new();
+        self.send_errors = 0;
+        self.errors = ErrorCounts::default();
+        self.gaps.clear();
     }
 }
 
 pub struct Receiver {
     socket: UdpSocket,
+    shutdown: Shutdown,
 }
 
 impl Receiver {
-    pub fn new(port: u16) -> Self {
-        let socket = UdpSocket::bind(format!("127.0.0.1:{}", port)).unwrap();
-        socket.set_nonblocking(true).unwrap();
-        Self { socket }
+    pub fn builder(port: u16) -> ReceiverBuilder {
+        ReceiverBuilder::new(port)
+    }
+
+    fn bind(port: u16, interface: Option<&LinkInfo>) -> Result<Self, NetMapError> {
+        let socket = listen(port, interface, std::net::UdpSocket::bind)?;
+        if let Some(interface) = interface {
+            sockopt::bind_to_device(&socket, &interface.name)?;
+        }
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: UdpSocket::from_std(socket)?,
+            shutdown: Shutdown::new(),
+        })
     }
 
-    pub fn run(self) -> thread::JoinHandle<()> {
+    /// Echoes every datagram back to its sender until the shutdown is triggered.
+    pub async fn run(self) -> Result<(), NetMapError> {
         let mut bytes_received = 0; // counter variable to keep track of bytes received
         let mut start_time = Instant::now(); // start time for measuring elapsed time
+        let mut errors = ErrorCounts::default();
+        let mut buf = vec![0; 65536];
+        let mut ticks = time::interval(Duration::from_secs(1));
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-        let _payload = "ACK";
-
-        thread::spawn(move || {
-            loop {
-                let mut buf = [0; 65536]; // Increase the buffer size to hold at least 9000 bytes
-                match self.socket.recv_from(&mut buf) {
+        loop {
+            tokio::select! {
+                result = self.socket.recv_from(&mut buf) => match result {
                     Ok((size, src)) => {
-                        let payload: Vec<u8> = vec![0; size];
-                        self.send(payload, src);
+                        // Echo the payload so the Sender can read its timestamp.
+                        if let Err(e) = self.socket.send_to(&buf[..size], src).await {
+                            debug!("Couldn't echo to {}: {}", src, e);
+                            errors.count(&e);
+                        }
                         bytes_received += size; // update the counter variable
                         debug!("received {} bytes from {:?}", size, src); // Use logging with tracing
-                                                                          // trace!("data: {:?}", &buf[..size]);  // Use logging with tracing
                     }
-                    Err(_e) => {
-                        // error!("Error receiving data");  // Use logging with tracing
-                    }
-                }
-
-                let elapsed_time = start_time.elapsed(); // measure elapsed time since start
-                if elapsed_time > Duration::from_secs(1) {
-                    // if elapsed time is greater than 1 second
+                    Err(e) => errors.count(&e),
+                },
+                _ = ticks.tick() => {
+                    let elapsed_time = start_time.elapsed(); // measure elapsed time since start
                     let bytes_per_second = bytes_received as f64 / elapsed_time.as_secs_f64(); // calculate bytes per second rate
                     if bytes_per_second > 0.0 {
                         info!(
@@ -267,14 +648,164 @@ impl Receiver {
                             bytes_per_second * 8.0 / 1_000_000.0
                         ); // print the rate with logging
                     }
+                    if errors.total() > 0 {
+                        warn!("Socket errors: {:?}", errors);
+                    }
                     bytes_received = 0; // reset the counter variable
+                    errors = ErrorCounts::default();
                     start_time = Instant::now(); // reset the start time
                 }
+                _ = self.shutdown.wait() => break,
             }
-        })
+        }
+        Ok(())
+    }
+}
+
+/// Binds the socket of a Receiver on `port`, on the first address of the interface if there is one.
+/// Otherwise on the unspecified address of IPv6, which takes IPv4 as well, or of IPv4 if the host has no IPv6.
+fn listen<T>(
+    port: u16,
+    interface: Option<&LinkInfo>,
+    bind: impl Fn(SocketAddr) -> io::Result<T>,
+) -> Result<T, NetMapError> {
+    let address = interface.and_then(|interface| {
+        interface
+            .source_address(&IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+            .or_else(|| interface.addresses.first().copied())
+    });
+    let address = match address {
+        Some(address) => SocketAddr::new(address, port),
+        None => {
+            let address = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+            match bind(address) {
+                Ok(socket) => return Ok(socket),
+                Err(e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
+                }
+                Err(source) => return Err(NetMapError::Bind { address, source }),
+            }
+        }
+    };
+    bind(address).map_err(|source| NetMapError::Bind { address, source })
+}
+
+/// Unspecified address of the family of `target_address`.
+fn unspecified(target_address: &SocketAddr) -> IpAddr {
+    match target_address {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// TOS byte (IPv4) or traffic class (IPv6) that carries `dscp` in its upper six bits.
+pub(crate) fn tos_from_dscp(dscp: u8) -> Result<u8, NetMapError> {
+    match dscp {
+        0..=63 => Ok(dscp << 2),
+        _ => Err(NetMapError::InvalidDscp(dscp)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dscp_fills_the_upper_six_bits_of_the_tos() {
+        assert_eq!(tos_from_dscp(0).unwrap(), 0);
+        assert_eq!(tos_from_dscp(46).unwrap(), 184);
+        assert_eq!(tos_from_dscp(63).unwrap(), 252);
+        assert!(matches!(
+            tos_from_dscp(64),
+            Err(NetMapError::InvalidDscp(64))
+        ));
+
+        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 4321);
+        assert!(matches!(
+            Sender::builder(target).dscp(64).build(),
+            Err(NetMapError::InvalidDscp(64))
+        ));
+    }
+
+    #[test]
+    fn payload_size_leaves_room_for_the_headers() {
+        let v4: SocketAddr = "192.168.1.10:4321".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::10]:4321".parse().unwrap();
+        assert_eq!(Sender::header_size(&v4), 28);
+        assert_eq!(Sender::header_size(&v6), 48);
+
+        for (target, header_size) in [(v4, 28), (v6, 48)] {
+            assert_eq!(
+                Sender::payload_size(1500, &target).unwrap(),
+                1500 - header_size
+            );
+            // Only the headers, the packets carry no timestamp then.
+            assert_eq!(Sender::payload_size(header_size, &target).unwrap(), 0);
+            assert_eq!(
+                Sender::payload_size(BUF_SIZE + header_size, &target).unwrap(),
+                BUF_SIZE
+            );
+            for packet_size in [0, header_size - 1, BUF_SIZE + header_size + 1] {
+                match Sender::payload_size(packet_size, &target) {
+                    Err(NetMapError::InvalidPacketSize { min, max, .. }) => {
+                        assert_eq!((min, max), (header_size, BUF_SIZE + header_size))
+                    }
+                    result => panic!("{} bytes to {}: {:?}", packet_size, target, result),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn binds_to_the_unspecified_address_without_an_interface() {
+        let receiver = Receiver::builder(0).build().unwrap();
+        assert!(receiver.socket.local_addr().unwrap().ip().is_unspecified());
+
+        for target in ["127.0.0.1:4321", "[::1]:4321"] {
+            let target: SocketAddr = target.parse().unwrap();
+            let sender = Sender::builder(target).build().unwrap();
+            let local = sender.recv_socket.local_addr().unwrap();
+            assert!(local.ip().is_unspecified());
+            assert_eq!(local.is_ipv6(), target.is_ipv6());
+        }
+    }
+
+    #[tokio::test]
+    async fn sending_fails_for_packets_without_room_for_the_headers() {
+        let target: SocketAddr = "127.0.0.1:4321".parse().unwrap();
+        let output = RecordWriter::default();
+        let records = output.subscribe();
+        let sender = Sender::builder(target).build().unwrap();
+        assert!(matches!(
+            sender.run(1.0, 20, output.clone()).await,
+            Err(NetMapError::InvalidPacketSize {
+                packet_size: 20,
+                ..
+            })
+        ));
+        let sender = Sender::builder(target).build().unwrap();
+        assert!(matches!(
+            sender
+                .wmm(1.0, BUF_SIZE + 29, Duration::from_secs(1), output)
+                .await,
+            Err(NetMapError::InvalidPacketSize { .. })
+        ));
+        assert_eq!(records.try_iter().count(), 0);
     }
 
-    fn send(&self, payload: Vec<u8>, src: SocketAddr) {
-        self.socket.send_to(&payload, src).unwrap(); // send the data
+    #[tokio::test]
+    async fn intervals_are_timed_from_the_start_of_the_run() {
+        let target: SocketAddr = "127.0.0.1:4321".parse().unwrap();
+        let started = Instant::now() - Duration::from_secs(5);
+        let sender = Sender::builder(target).started(started).build().unwrap();
+        let mut intervals = Vec::new();
+        sender
+            .transmit(1.0, 500, Some(Duration::from_secs(1)), |interval| {
+                intervals.push(interval.clone())
+            })
+            .await;
+        assert_eq!(intervals.len(), 1);
+        assert!(intervals[0].from >= Duration::from_secs(5));
+        assert!(intervals[0].to >= Duration::from_secs(6));
     }
 }
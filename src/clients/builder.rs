@@ -0,0 +1,181 @@
+use super::{tos_from_dscp, Receiver, Sender, TcpReceiver, TcpSender};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::link::LinkInfo;
+use crate::shutdown::{Pause, Shutdown};
+use crate::wireless::WirelessStats;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Configures a [`Sender`] (UDP) or a [`TcpSender`] towards a Receiver.
+pub struct SenderBuilder {
+    target_address: SocketAddr,
+    port: u16,
+    interface: Option<LinkInfo>,
+    tos: Option<u8>,
+    dscp: Option<u8>,
+    position: Arc<Mutex<Position>>,
+    wireless: Arc<Mutex<Option<WirelessStats>>>,
+    shutdown: Shutdown,
+    pause: Pause,
+    started: Option<Instant>,
+}
+
+impl SenderBuilder {
+    pub fn new(target_address: SocketAddr) -> Self {
+        SenderBuilder {
+            target_address,
+            port: 0,
+            interface: None,
+            tos: None,
+            dscp: None,
+            position: Arc::new(Mutex::new(Position::default())),
+            wireless: Arc::new(Mutex::new(None)),
+            shutdown: Shutdown::new(),
+            pause: Pause::new(),
+            started: None,
+        }
+    }
+
+    /// Local port of the UDP socket, an ephemeral one if not set.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Sends over the given interface only.
+    pub fn interface(mut self, interface: LinkInfo) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Marks all outgoing packets with the given TOS byte (IPv4) or traffic class (IPv6).
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = Some(tos);
+        self.dscp = None;
+        self
+    }
+
+    /// Marks all outgoing packets with the given DSCP, building fails for one above 63.
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.dscp = Some(dscp);
+        self.tos = None;
+        self
+    }
+
+    /// TOS byte of the marking, from the DSCP or as given.
+    fn marking(&self) -> Result<Option<u8>, NetMapError> {
+        match self.dscp {
+            Some(dscp) => tos_from_dscp(dscp).map(Some),
+            None => Ok(self.tos),
+        }
+    }
+
+    /// Tags the records with the position kept up to date by a GPS source.
+    pub fn position(mut self, position: Arc<Mutex<Position>>) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Tags the interval records with the statistics of the wireless link.
+    pub fn wireless(mut self, wireless: Arc<Mutex<Option<WirelessStats>>>) -> Self {
+        self.wireless = wireless;
+        self
+    }
+
+    /// Stops sending once `shutdown` is triggered.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Holds the traffic while `pause` is paused.
+    pub fn pause(mut self, pause: Pause) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Times the intervals from `started`, e.g. the start of the run that the annotations count from,
+    /// instead of from building the Sender.
+    pub fn started(mut self, started: Instant) -> Self {
+        self.started = Some(started);
+        self
+    }
+
+    /// Binds the UDP socket. Must be called from within a Tokio runtime.
+    pub fn build(self) -> Result<Sender, NetMapError> {
+        let tos = self.marking()?;
+        let recv_socket = Sender::bind(self.port, &self.target_address, self.interface.as_ref())?;
+        let mut sender = Sender::new(recv_socket, self.target_address, self.position);
+        sender.wireless = self.wireless;
+        sender.shutdown = self.shutdown;
+        sender.pause = self.pause;
+        if let Some(started) = self.started {
+            sender.epoch = started;
+        }
+        if let Some(tos) = tos {
+            sender.set_tos(tos)?;
+        }
+        Ok(sender)
+    }
+
+    /// Connects the TCP stream to the Receiver. Must be called from within a Tokio runtime.
+    pub fn build_tcp(self) -> Result<TcpSender, NetMapError> {
+        let mut sender = TcpSender::connect(
+            self.target_address,
+            self.interface.as_ref(),
+            self.marking()?,
+            self.position,
+            self.wireless,
+            self.shutdown,
+            self.pause,
+        )?;
+        if let Some(started) = self.started {
+            sender.started = started;
+        }
+        Ok(sender)
+    }
+}
+
+/// Configures a [`Receiver`] (UDP) or a [`TcpReceiver`].
+/// Like the Senders, they have to be built from within a Tokio runtime.
+pub struct ReceiverBuilder {
+    port: u16,
+    interface: Option<LinkInfo>,
+    shutdown: Shutdown,
+}
+
+impl ReceiverBuilder {
+    pub fn new(port: u16) -> Self {
+        ReceiverBuilder {
+            port,
+            interface: None,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Listens on the given interface only.
+    pub fn interface(mut self, interface: LinkInfo) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Stops listening once `shutdown` is triggered.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn build(self) -> Result<Receiver, NetMapError> {
+        let mut receiver = Receiver::bind(self.port, self.interface.as_ref())?;
+        receiver.shutdown = self.shutdown;
+        Ok(receiver)
+    }
+
+    pub fn build_tcp(self) -> Result<TcpReceiver, NetMapError> {
+        let mut receiver = TcpReceiver::bind(self.port, self.interface.as_ref())?;
+        receiver.shutdown = self.shutdown;
+        Ok(receiver)
+    }
+}
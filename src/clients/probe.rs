@@ -0,0 +1,354 @@
+use super::{Interval, Sender, Summary};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::record::RecordWriter;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio::time;
+
+use tracing::{info, warn};
+
+/// Time to wait after each step so that late echoes don't count towards the next step.
+const SETTLE_DURATION: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStrategy {
+    /// Increase the data rate by a fixed step until the thresholds are exceeded.
+    Step,
+    /// Binary search between the start and the maximum data rate.
+    Binary,
+}
+
+impl fmt::Display for ProbeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            ProbeStrategy::Step => write!(f, "step"),
+            ProbeStrategy::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+/// Parameters of a capacity search.
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    pub strategy: ProbeStrategy,
+    /// data rate of the first step in Mbit/s
+    pub start_rate: f64,
+    /// upper bound of the search in Mbit/s
+    pub max_rate: f64,
+    /// rate increase per step, or the precision of the binary search, in Mbit/s
+    pub rate_step: f64,
+    /// how long each rate is tested
+    pub step_duration: Duration,
+    /// highest loss in percent that still counts as sustainable
+    pub max_loss: f64,
+    /// highest average round trip time that still counts as sustainable
+    pub max_rtt: Option<Duration>,
+    pub packet_size: usize,
+}
+
+impl ProbeConfig {
+    /// Whether `loss` in percent and the average round trip time `rtt` stay within the thresholds.
+    /// Without any echo there is no round trip time, which only passes if no threshold is set for it.
+    pub fn sustainable(&self, loss: f64, rtt: Option<Duration>) -> bool {
+        loss <= self.max_loss
+            && match (rtt, self.max_rtt) {
+                (Some(rtt), Some(max_rtt)) => rtt <= max_rtt,
+                (None, Some(_)) => false,
+                (_, None) => true,
+            }
+    }
+}
+
+/// Picks the data rate of each step from the outcomes of the previous ones.
+#[derive(Debug, Clone)]
+struct Search {
+    strategy: ProbeStrategy,
+    max_rate: f64,
+    rate_step: f64,
+    /// rate to test next, `None` once the search is done
+    next: Option<f64>,
+    /// highest rate known to be sustainable
+    low: f64,
+    /// lowest rate known not to be sustainable, or the maximum rate
+    high: f64,
+    best: Option<f64>,
+}
+
+impl Search {
+    fn new(config: &ProbeConfig) -> Self {
+        let next = match config.strategy {
+            ProbeStrategy::Step => Some(config.start_rate).filter(|rate| *rate <= config.max_rate),
+            ProbeStrategy::Binary => Some(config.start_rate),
+        };
+        Search {
+            strategy: config.strategy,
+            max_rate: config.max_rate,
+            rate_step: config.rate_step,
+            next,
+            low: config.start_rate,
+            high: config.max_rate,
+            best: None,
+        }
+    }
+
+    fn next(&self) -> Option<f64> {
+        self.next
+    }
+
+    /// Takes the outcome of the step at `rate` into account.
+    fn record(&mut self, rate: f64, sustainable: bool) {
+        if sustainable {
+            self.best = Some(rate);
+            self.low = rate;
+        } else {
+            self.high = rate;
+        }
+        self.next = match self.strategy {
+            ProbeStrategy::Step => {
+                Some(rate + self.rate_step).filter(|rate| sustainable && *rate <= self.max_rate)
+            }
+            // The search only narrows down if the start rate is sustainable.
+            ProbeStrategy::Binary => Some((self.low + self.high) / 2.0)
+                .filter(|_| self.best.is_some() && self.high - self.low > self.rate_step),
+        };
+    }
+
+    /// Highest sustainable rate found so far.
+    fn best(&self) -> Option<f64> {
+        self.best
+    }
+}
+
+/// Aggregated measurements of a single tested data rate.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProbeStep {
+    /// data rate in Mbit/s
+    pub rate: f64,
+    /// measured throughput in Mbit/s
+    pub throughput: f64,
+    pub packets_sent: u32,
+    pub packets_received: u32,
+    /// loss in percent
+    pub loss: f64,
+    /// average round trip time in milliseconds
+    pub rtt: Option<f64>,
+    pub sustainable: bool,
+}
+
+/// Outcome of a capacity search at one location.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    pub position: Position,
+    /// highest sustainable data rate in Mbit/s, `None` if even the start rate was not sustainable
+    pub sustainable_rate: Option<f64>,
+    pub steps: Vec<ProbeStep>,
+    /// survey waypoint the search ran at
+    pub waypoint: Option<u32>,
+}
+
+impl fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Rate  Throughput  received/sent (loss)  RTT")?;
+        for step in &self.steps {
+            writeln!(
+                f,
+                "{:.2} Mbit/s {:.2} Mbit/s {}/{} ({:3.2}%) {:.3}ms{}",
+                step.rate,
+                step.throughput,
+                step.packets_received,
+                step.packets_sent,
+                step.loss,
+                step.rtt.unwrap_or_default(),
+                if step.sustainable {
+                    ""
+                } else {
+                    " (not sustainable)"
+                }
+            )?;
+        }
+        match self.sustainable_rate {
+            Some(rate) => write!(
+                f,
+                "Highest sustainable rate: {:.2} Mbit/s at {}",
+                rate, self.position
+            ),
+            None => write!(
+                f,
+                "No sustainable rate above {:.2} Mbit/s at {}",
+                self.steps.first().map_or(0.0, |step| step.rate),
+                self.position
+            ),
+        }
+    }
+}
+
+impl Sender {
+    /// Searches for the highest data rate at which loss and round trip time stay below the configured thresholds.
+    /// After a shutdown, the result only covers the steps that were completed.
+    pub async fn probe(self, config: ProbeConfig, output: RecordWriter) -> Result<(), NetMapError> {
+        Self::payload_size(config.packet_size, &self.target_address)?;
+        info!(
+            "Probing {} from {} to {} Mbit/s ({})",
+            self.target_address, config.start_rate, config.max_rate, config.strategy
+        );
+        let result = self.search(&config).await;
+        if let Err(e) = output.write(result) {
+            warn!("Couldn't write probe result: {}", e);
+        }
+        Ok(())
+    }
+
+    pub(super) async fn search(&self, config: &ProbeConfig) -> ProbeResult {
+        let mut search = Search::new(config);
+        let mut steps = Vec::new();
+        while let Some(rate) = search.next() {
+            let step = self.probe_rate(rate, config).await;
+            if self.shutdown.is_triggered() {
+                break;
+            }
+            search.record(rate, step.sustainable);
+            steps.push(step);
+        }
+
+        ProbeResult {
+            position: self.position.lock().unwrap().clone(),
+            sustainable_rate: search.best(),
+            steps,
+            waypoint: self.waypoint,
+        }
+    }
+
+    /// Sends at `rate` for the configured step duration and aggregates the measured intervals.
+    async fn probe_rate(&self, rate: f64, config: &ProbeConfig) -> ProbeStep {
+        let mut intervals: Vec<Interval> = Vec::new();
+        self.transmit(
+            rate,
+            config.packet_size,
+            Some(config.step_duration),
+            |interval| intervals.push(interval.clone()),
+        )
+        .await;
+        time::sleep(SETTLE_DURATION).await;
+        self.drain();
+
+        let Summary {
+            throughput,
+            packets_sent,
+            packets_received,
+            loss,
+            rtt,
+            ..
+        } = Summary::new(&intervals);
+
+        let sustainable = config.sustainable(loss, rtt);
+
+        info!(
+            "Probed {:.2} Mbit/s: {:.2} Mbit/s, {:.2}% loss, RTT {:?} -> {}",
+            rate,
+            throughput,
+            loss,
+            rtt,
+            if sustainable {
+                "sustainable"
+            } else {
+                "not sustainable"
+            }
+        );
+
+        ProbeStep {
+            rate,
+            throughput,
+            packets_sent,
+            packets_received,
+            loss,
+            rtt: rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            sustainable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(strategy: ProbeStrategy) -> ProbeConfig {
+        ProbeConfig {
+            strategy,
+            start_rate: 1.0,
+            max_rate: 10.0,
+            rate_step: 1.0,
+            step_duration: Duration::from_secs(1),
+            max_loss: 1.0,
+            max_rtt: Some(Duration::from_millis(50)),
+            packet_size: 1000,
+        }
+    }
+
+    /// Runs a search against a link that sustains rates up to `capacity`, returning the tested rates.
+    fn run(config: &ProbeConfig, capacity: f64) -> (Vec<f64>, Option<f64>) {
+        let mut search = Search::new(config);
+        let mut rates = Vec::new();
+        while let Some(rate) = search.next() {
+            rates.push(rate);
+            search.record(rate, rate <= capacity);
+        }
+        (rates, search.best())
+    }
+
+    #[test]
+    fn sustainable_within_the_thresholds() {
+        let config = config(ProbeStrategy::Step);
+        assert!(config.sustainable(1.0, Some(Duration::from_millis(50))));
+        assert!(!config.sustainable(1.5, Some(Duration::from_millis(10))));
+        assert!(!config.sustainable(0.0, Some(Duration::from_millis(51))));
+        assert!(!config.sustainable(0.0, None));
+
+        let config = ProbeConfig {
+            max_rtt: None,
+            ..config
+        };
+        assert!(config.sustainable(0.0, None));
+        assert!(config.sustainable(0.0, Some(Duration::from_secs(1))));
+    }
+
+    #[test]
+    fn step_search_stops_at_the_first_unsustainable_rate() {
+        let config = config(ProbeStrategy::Step);
+        assert_eq!(run(&config, 3.5), (vec![1.0, 2.0, 3.0, 4.0], Some(3.0)));
+        assert_eq!(run(&config, 0.5), (vec![1.0], None));
+        // The maximum rate is tested, but not exceeded.
+        assert_eq!(run(&config, 100.0).0.last(), Some(&10.0));
+        assert_eq!(run(&config, 100.0).1, Some(10.0));
+    }
+
+    #[test]
+    fn step_search_without_room_tests_nothing() {
+        let config = ProbeConfig {
+            start_rate: 20.0,
+            ..config(ProbeStrategy::Step)
+        };
+        assert_eq!(run(&config, 100.0), (Vec::new(), None));
+    }
+
+    #[test]
+    fn binary_search_narrows_down_to_the_step() {
+        let config = config(ProbeStrategy::Binary);
+        let (rates, best) = run(&config, 6.0);
+        assert_eq!(rates, vec![1.0, 5.5, 7.75, 6.625, 6.0625]);
+        assert_eq!(best, Some(5.5));
+
+        let (rates, best) = run(&config, 100.0);
+        assert_eq!(rates, vec![1.0, 5.5, 7.75, 8.875, 9.4375]);
+        assert_eq!(best, Some(9.4375));
+    }
+
+    #[test]
+    fn binary_search_gives_up_if_the_start_rate_is_not_sustainable() {
+        let config = config(ProbeStrategy::Binary);
+        assert_eq!(run(&config, 0.5), (vec![1.0], None));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::gps::Position;
+use crate::wireless::WirelessStats;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+/// How far around a detected roam outages are attributed to it.
+/// The link statistics are only sampled once per second, so the roam may have happened up to a few seconds earlier.
+const ROAM_WINDOW: Duration = Duration::from_secs(3);
+
+/// A period without echoes, as measured by the Sender.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EchoGap {
+    /// last echo before the gap, since the start of the run
+    pub start: Duration,
+    /// first echo after the gap, since the start of the run
+    pub end: Duration,
+    /// packets sent in between whose echoes never arrived
+    pub packets_lost: u64,
+}
+
+/// The client switched to another access point or channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoamEvent {
+    /// time at which the roam was detected, in seconds since the start of the run
+    pub time: f64,
+    pub position: Position,
+    pub from_bssid: String,
+    pub to_bssid: String,
+    /// in MHz
+    pub from_frequency: Option<u32>,
+    /// in MHz
+    pub to_frequency: Option<u32>,
+    /// longest time without echoes around the roam in milliseconds, `None` if there was no outage
+    pub outage: Option<f64>,
+    /// packets lost during that outage
+    pub packets_lost: u64,
+}
+
+impl fmt::Display for RoamEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:3.1} Roamed from {} ({} MHz) to {} ({} MHz), outage: {:.1}ms, {} packets lost",
+            self.time,
+            self.from_bssid,
+            self.from_frequency.unwrap_or_default(),
+            self.to_bssid,
+            self.to_frequency.unwrap_or_default(),
+            self.outage.unwrap_or_default(),
+            self.packets_lost
+        )
+    }
+}
+
+/// A roam whose outage may not be over yet.
+struct PendingRoam {
+    event: RoamEvent,
+    detected: Duration,
+}
+
+/// Detects association changes from the sampled link statistics and attributes the surrounding outage to them.
+pub struct RoamDetector {
+    // BSSID and frequency of the current association
+    current: Option<(String, Option<u32>)>,
+    pending: Option<PendingRoam>,
+    recent_gaps: VecDeque<EchoGap>,
+}
+
+impl RoamDetector {
+    pub fn new() -> Self {
+        RoamDetector {
+            current: None,
+            pending: None,
+            recent_gaps: VecDeque::new(),
+        }
+    }
+
+    /// Feeds the next interval, ending at `to` with the outages in `gaps` and, if the echoes still haven't come back,
+    /// the one going on `silent_since`. Returns a roam event once its outage is over,
+    /// or once the next roam is detected before that.
+    pub fn update(
+        &mut self,
+        to: Duration,
+        gaps: &[EchoGap],
+        silent_since: Option<Duration>,
+        wireless: Option<&WirelessStats>,
+        position: &Position,
+    ) -> Option<RoamEvent> {
+        self.recent_gaps.extend(gaps.iter().cloned());
+        while self
+            .recent_gaps
+            .front()
+            .is_some_and(|gap| gap.end + ROAM_WINDOW * 2 < to)
+        {
+            self.recent_gaps.pop_front();
+        }
+
+        let mut superseded = None;
+        if let Some(bssid) = wireless.and_then(|wireless| wireless.bssid.clone()) {
+            let frequency = wireless.and_then(|wireless| wireless.frequency);
+            if let Some((from_bssid, from_frequency)) = &self.current {
+                if *from_bssid != bssid || *from_frequency != frequency {
+                    let roam = PendingRoam {
+                        event: RoamEvent {
+                            time: to.as_secs_f64(),
+                            position: position.clone(),
+                            from_bssid: from_bssid.clone(),
+                            to_bssid: bssid.clone(),
+                            from_frequency: *from_frequency,
+                            to_frequency: frequency,
+                            outage: None,
+                            packets_lost: 0,
+                        },
+                        detected: to,
+                    };
+                    superseded = self.pending.replace(roam);
+                }
+            }
+            self.current = Some((bssid, frequency));
+        }
+        // The roam before is reported with the outage so far, the new one waits for its own.
+        if let Some(roam) = superseded {
+            return Some(self.attribute(roam, to, silent_since));
+        }
+
+        let pending = self.pending.as_ref()?;
+        if silent_since.is_some() && to < pending.detected + ROAM_WINDOW {
+            return None;
+        }
+        let roam = self.pending.take()?;
+        Some(self.attribute(roam, to, silent_since))
+    }
+
+    /// Sets the outage of `roam`: the longest gap around it, or the one still going on at `to`.
+    fn attribute(
+        &self,
+        roam: PendingRoam,
+        to: Duration,
+        silent_since: Option<Duration>,
+    ) -> RoamEvent {
+        let PendingRoam {
+            mut event,
+            detected,
+        } = roam;
+        let longest_gap = self
+            .recent_gaps
+            .iter()
+            .filter(|gap| gap.end + ROAM_WINDOW >= detected)
+            .max_by_key(|gap| gap.end - gap.start);
+        match (longest_gap, silent_since) {
+            // Still no echoes, report the outage so far.
+            (_, Some(silent_since)) => {
+                event.outage = Some((to - silent_since).as_secs_f64() * 1000.0);
+            }
+            (Some(gap), None) => {
+                event.outage = Some((gap.end - gap.start).as_secs_f64() * 1000.0);
+                event.packets_lost = gap.packets_lost;
+            }
+            (None, None) => {}
+        }
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(bssid: &str, frequency: u32) -> WirelessStats {
+        WirelessStats {
+            bssid: Some(bssid.to_string()),
+            frequency: Some(frequency),
+            ..WirelessStats::default()
+        }
+    }
+
+    fn gap(start_ms: u64, end_ms: u64, packets_lost: u64) -> EchoGap {
+        EchoGap {
+            start: Duration::from_millis(start_ms),
+            end: Duration::from_millis(end_ms),
+            packets_lost,
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn attributes_the_gap_around_a_roam() {
+        let position = Position::default();
+        let mut detector = RoamDetector::new();
+        let (ap1, ap2) = (link("aa", 2412), link("bb", 5180));
+
+        assert_eq!(
+            detector.update(secs(1), &[], None, Some(&ap1), &position),
+            None
+        );
+        // The gap ended before the statistics showed the new access point.
+        let gaps = [gap(1500, 1800, 30)];
+        let event = detector.update(secs(2), &gaps, None, Some(&ap2), &position);
+        let event = event.unwrap();
+        assert_eq!(
+            (event.from_bssid.as_str(), event.to_bssid.as_str()),
+            ("aa", "bb")
+        );
+        assert_eq!(
+            (event.from_frequency, event.to_frequency),
+            (Some(2412), Some(5180))
+        );
+        assert_eq!(event.time, 2.0);
+        assert!((event.outage.unwrap() - 300.0).abs() < 1e-9);
+        assert_eq!(event.packets_lost, 30);
+
+        // Staying on the access point is no roam.
+        assert_eq!(
+            detector.update(secs(3), &[], None, Some(&ap2), &position),
+            None
+        );
+    }
+
+    #[test]
+    fn waits_for_an_ongoing_outage() {
+        let position = Position::default();
+        let mut detector = RoamDetector::new();
+        let (ap1, ap2) = (link("aa", 2412), link("bb", 2412));
+
+        detector.update(secs(1), &[], None, Some(&ap1), &position);
+        let silent_since = Some(Duration::from_millis(1500));
+        assert_eq!(
+            detector.update(secs(2), &[], silent_since, Some(&ap2), &position),
+            None
+        );
+        assert_eq!(
+            detector.update(secs(3), &[], silent_since, Some(&ap2), &position),
+            None
+        );
+        // The echoes are back, the gap they close is the outage.
+        let gaps = [gap(1500, 3200, 170)];
+        let event = detector.update(secs(4), &gaps, None, Some(&ap2), &position);
+        let event = event.unwrap();
+        assert!((event.outage.unwrap() - 1700.0).abs() < 1e-9);
+        assert_eq!(event.packets_lost, 170);
+
+        // An outage that outlasts the window is reported as far as it went.
+        let ap3 = link("cc", 2412);
+        assert_eq!(
+            detector.update(secs(5), &[], Some(secs(5)), Some(&ap3), &position),
+            None
+        );
+        assert_eq!(
+            detector.update(secs(7), &[], Some(secs(5)), Some(&ap3), &position),
+            None
+        );
+        let event = detector.update(secs(8), &[], Some(secs(5)), Some(&ap3), &position);
+        let event = event.unwrap();
+        assert_eq!(event.to_bssid, "cc");
+        assert!((event.outage.unwrap() - 3000.0).abs() < 1e-9);
+        assert_eq!(event.packets_lost, 0);
+    }
+
+    #[test]
+    fn reports_a_pending_roam_when_the_next_one_comes() {
+        let position = Position::default();
+        let mut detector = RoamDetector::new();
+        let (ap1, ap2, ap3) = (link("aa", 2412), link("bb", 2412), link("cc", 2412));
+
+        detector.update(secs(1), &[], None, Some(&ap1), &position);
+        let silent_since = Some(Duration::from_millis(1800));
+        assert_eq!(
+            detector.update(secs(2), &[], silent_since, Some(&ap2), &position),
+            None
+        );
+        let first = detector.update(secs(3), &[], silent_since, Some(&ap3), &position);
+        let first = first.unwrap();
+        assert_eq!(
+            (first.from_bssid.as_str(), first.to_bssid.as_str()),
+            ("aa", "bb")
+        );
+        assert!((first.outage.unwrap() - 1200.0).abs() < 1e-9);
+
+        let gaps = [gap(1800, 3300, 150)];
+        let second = detector.update(secs(4), &gaps, None, Some(&ap3), &position);
+        let second = second.unwrap();
+        assert_eq!(
+            (second.from_bssid.as_str(), second.to_bssid.as_str()),
+            ("bb", "cc")
+        );
+        assert_eq!(second.time, 3.0);
+        assert_eq!(second.packets_lost, 150);
+        assert_eq!(
+            detector.update(secs(5), &[], None, Some(&ap3), &position),
+            None
+        );
+    }
+
+    #[test]
+    fn reports_roams_without_echoes_right_away() {
+        // The TCP Sender has no echoes, so it never sees an outage.
+        let position = Position::default();
+        let mut detector = RoamDetector::new();
+        detector.update(secs(1), &[], None, Some(&link("aa", 2412)), &position);
+        let event = detector.update(secs(2), &[], None, Some(&link("aa", 5180)), &position);
+        assert_eq!(event.unwrap().outage, None);
+        assert_eq!(detector.update(secs(3), &[], None, None, &position), None);
+    }
+}
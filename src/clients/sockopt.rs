@@ -0,0 +1,135 @@
+use crate::error::NetMapError;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+fn set_int<S: AsRawFd>(
+    socket: &S,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Sets the don't fragment bit, so packets larger than the path MTU are dropped instead of fragmented.
+pub fn set_dont_fragment<S: AsRawFd>(socket: &S, ipv6: bool) -> io::Result<()> {
+    match ipv6 {
+        false => set_int(
+            socket,
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        ),
+        true => set_int(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+    }
+}
+
+/// Sets the TOS byte (IPv4) or traffic class (IPv6) of outgoing packets. The DSCP occupies the upper six bits.
+pub fn set_tos<S: AsRawFd>(socket: &S, ipv6: bool, tos: u8) -> io::Result<()> {
+    match ipv6 {
+        false => set_int(socket, libc::IPPROTO_IP, libc::IP_TOS, tos as libc::c_int),
+        true => set_int(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            tos as libc::c_int,
+        ),
+    }
+}
+
+/// Restricts the socket to the given network interface (SO_BINDTODEVICE), requires CAP_NET_RAW.
+/// Fails rather than leaving the traffic free to take another link.
+pub fn bind_to_device<S: AsRawFd>(socket: &S, interface: &str) -> Result<(), NetMapError> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(NetMapError::BindDevice {
+            interface: interface.to_string(),
+            source: io::Error::last_os_error(),
+        }),
+    }
+}
+
+/// Connects a TCP stream from `source` to `target`, bound to `interface` before the handshake.
+/// The standard library can't bind a stream before connecting it.
+pub fn connect_tcp(
+    target: SocketAddr,
+    source: IpAddr,
+    interface: &str,
+) -> Result<TcpStream, NetMapError> {
+    let failed = |source| NetMapError::Connect {
+        address: target,
+        source,
+    };
+    let family = match target {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(failed(io::Error::last_os_error()));
+    }
+    // Owning the descriptor right away closes it on every error path.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    bind_to_device(&stream, interface)?;
+
+    let (source, source_len) = sockaddr(SocketAddr::new(source, 0));
+    if unsafe { libc::bind(fd, &source as *const _ as *const libc::sockaddr, source_len) } != 0 {
+        return Err(failed(io::Error::last_os_error()));
+    }
+    let (target, target_len) = sockaddr(target);
+    if unsafe { libc::connect(fd, &target as *const _ as *const libc::sockaddr, target_len) } != 0 {
+        return Err(failed(io::Error::last_os_error()));
+    }
+    Ok(stream)
+}
+
+fn sockaddr(address: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match address {
+        SocketAddr::V4(address) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = address.port().to_be();
+            sockaddr.sin_addr.s_addr = u32::from(*address.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(address) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = address.port().to_be();
+            sockaddr.sin6_addr.s6_addr = address.ip().octets();
+            sockaddr.sin6_scope_id = address.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
@@ -0,0 +1,455 @@
+use super::{ProbeConfig, Sender};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::record::RecordWriter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{self, MissedTickBehavior};
+
+use tracing::{info, warn};
+
+/// Time to wait after each test so that late echoes don't count towards the next one.
+const SETTLE_DURATION: Duration = Duration::from_millis(200);
+/// How often the position is compared to the one of the last waypoint.
+const POSITION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Data rate of the latency test in Mbit/s, low enough not to load the link.
+const LATENCY_DATA_RATE: f64 = 0.1;
+/// IP packet size of the latency test in bytes, about the size of a VoIP packet.
+const LATENCY_PACKET_SIZE: usize = 200;
+/// Duration of a test that doesn't name one, in seconds.
+const DEFAULT_STEP_DURATION: u64 = 10;
+
+/// One test of a survey plan, written as `udp:10`, `latency:10`, `probe` or `wmm:5`.
+/// Every test measures the round trip through the echoes of the Receiver,
+/// so the loss and the throughput can't be told apart by direction.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum SurveyStep {
+    /// Paced UDP traffic at the data rate of the survey, for the given seconds.
+    Udp(u64),
+    /// Light UDP traffic that measures the round trip time, for the given seconds.
+    Latency(u64),
+    /// Search for the highest sustainable data rate.
+    Probe,
+    /// Paced UDP traffic in every WMM access category, for the given seconds each.
+    Wmm(u64),
+}
+
+impl FromStr for SurveyStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (test, duration) = match s.split_once(':') {
+            Some((test, duration)) => {
+                let duration = duration
+                    .parse()
+                    .map_err(|e| format!("invalid duration {:?}: {}", duration, e))?;
+                (test, Some(duration))
+            }
+            None => (s, None),
+        };
+        let duration = duration.unwrap_or(DEFAULT_STEP_DURATION);
+        match test {
+            "udp" => Ok(SurveyStep::Udp(duration)),
+            "latency" => Ok(SurveyStep::Latency(duration)),
+            "probe" => Ok(SurveyStep::Probe),
+            "wmm" => Ok(SurveyStep::Wmm(duration)),
+            _ => Err(format!(
+                "unknown test {:?}, expected udp, latency, probe or wmm",
+                test
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SurveyStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SurveyStep::Udp(duration) => write!(f, "udp:{}", duration),
+            SurveyStep::Latency(duration) => write!(f, "latency:{}", duration),
+            SurveyStep::Probe => write!(f, "probe"),
+            SurveyStep::Wmm(duration) => write!(f, "wmm:{}", duration),
+        }
+    }
+}
+
+/// Tests of a survey and when to run them.
+#[derive(Debug, Clone)]
+pub struct SurveyPlan {
+    /// tests run one after the other at every waypoint
+    pub steps: Vec<SurveyStep>,
+    /// data rate of the UDP and WMM tests in Mbit/s
+    pub data_rate: f64,
+    pub packet_size: usize,
+    /// capacity search of the probe tests
+    pub probe: ProbeConfig,
+    /// the next waypoint is reached once the position is this many meters away from the last one
+    pub min_distance: Option<f64>,
+    /// the next waypoint is reached this long after the tests of the last one
+    pub interval: Option<Duration>,
+}
+
+/// What started the tests at a waypoint.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WaypointTrigger {
+    /// The survey started.
+    Start,
+    /// The position moved far enough from the last waypoint.
+    Moved,
+    /// The time between two waypoints elapsed.
+    Timer,
+    /// The surveyor marked the location, e.g. with a key press.
+    Mark,
+}
+
+impl fmt::Display for WaypointTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaypointTrigger::Start => write!(f, "start"),
+            WaypointTrigger::Moved => write!(f, "moved"),
+            WaypointTrigger::Timer => write!(f, "timer"),
+            WaypointTrigger::Mark => write!(f, "mark"),
+        }
+    }
+}
+
+/// Location at which the tests of a survey plan ran.
+/// The records of these tests follow it in the session file and carry its `id`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Waypoint {
+    pub id: u32,
+    pub trigger: WaypointTrigger,
+    pub position: Position,
+}
+
+impl fmt::Display for Waypoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Waypoint {} ({}) at {}",
+            self.id, self.trigger, self.position
+        )
+    }
+}
+
+impl Sender {
+    /// Runs the tests of `plan` at the starting point and again at every following waypoint until the shutdown is triggered.
+    /// The next waypoint is reached when the position moved far enough, the interval elapsed or a mark arrives on `marks`.
+    pub async fn survey(
+        mut self,
+        plan: SurveyPlan,
+        mut marks: mpsc::Receiver<()>,
+        output: RecordWriter,
+    ) -> Result<(), NetMapError> {
+        for packet_size in [plan.packet_size, plan.probe.packet_size] {
+            Self::payload_size(packet_size, &self.target_address)?;
+        }
+        let mut id = 0;
+        let mut trigger = WaypointTrigger::Start;
+        loop {
+            id += 1;
+            let waypoint = Waypoint {
+                id,
+                trigger,
+                position: self.position.lock().unwrap().clone(),
+            };
+            if let Err(e) = output.write(waypoint.clone()) {
+                warn!("Couldn't write waypoint: {}", e);
+            }
+
+            self.waypoint = Some(id);
+            for step in &plan.steps {
+                info!("Running {} at waypoint {}", step, id);
+                self.run_step(*step, &plan, &output).await;
+                if self.shutdown.is_triggered() {
+                    return Ok(());
+                }
+            }
+            self.waypoint = None;
+
+            info!("Waypoint {} done, waiting for the next one", id);
+            trigger = match self
+                .next_waypoint(&plan, waypoint.position, &mut marks)
+                .await
+            {
+                Some(trigger) => trigger,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    async fn run_step(&mut self, step: SurveyStep, plan: &SurveyPlan, output: &RecordWriter) {
+        match step {
+            SurveyStep::Udp(duration) => {
+                self.send_for(plan.data_rate, plan.packet_size, duration, output)
+                    .await
+            }
+            SurveyStep::Latency(duration) => {
+                self.send_for(LATENCY_DATA_RATE, LATENCY_PACKET_SIZE, duration, output)
+                    .await
+            }
+            SurveyStep::Probe => {
+                let result = self.search(&plan.probe).await;
+                if let Err(e) = output.write(result) {
+                    warn!("Couldn't write probe result: {}", e);
+                }
+            }
+            SurveyStep::Wmm(duration) => {
+                let tos = self.tos;
+                let result = self
+                    .test_categories(
+                        plan.data_rate,
+                        plan.packet_size,
+                        Duration::from_secs(duration),
+                        output,
+                    )
+                    .await;
+                if let Err(e) = output.write(result) {
+                    warn!("Couldn't write WMM result: {}", e);
+                }
+                // The other tests go out unmarked again.
+                if let Err(e) = self.set_tos(tos.unwrap_or_default()) {
+                    warn!("Couldn't reset the packet marking: {}", e);
+                }
+                self.tos = tos;
+            }
+        }
+    }
+
+    /// Sends paced traffic for `duration` seconds and records every interval.
+    async fn send_for(
+        &self,
+        data_rate: f64,
+        packet_size: usize,
+        duration: u64,
+        output: &RecordWriter,
+    ) {
+        self.transmit(
+            data_rate,
+            packet_size,
+            Some(Duration::from_secs(duration)),
+            |interval| {
+                if let Err(e) = output.write(self.record(interval)) {
+                    warn!("Couldn't write interval record: {}", e);
+                }
+            },
+        )
+        .await;
+        time::sleep(SETTLE_DURATION).await;
+        self.drain();
+    }
+
+    /// Waits until the next waypoint is reached, `None` if the shutdown is triggered first.
+    async fn next_waypoint(
+        &self,
+        plan: &SurveyPlan,
+        mut last: Position,
+        marks: &mut mpsc::Receiver<()>,
+    ) -> Option<WaypointTrigger> {
+        // Marks made during the tests don't count, the surveyor is still at the last waypoint.
+        while marks.try_recv().is_ok() {}
+        let mut marks_open = true;
+
+        let timer = time::sleep(plan.interval.unwrap_or_default());
+        tokio::pin!(timer);
+        let mut position_checks = time::interval(POSITION_CHECK_INTERVAL);
+        position_checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.wait() => return None,
+                _ = &mut timer, if plan.interval.is_some() => return Some(WaypointTrigger::Timer),
+                mark = marks.recv(), if marks_open => match mark {
+                    Some(()) => return Some(WaypointTrigger::Mark),
+                    None => marks_open = false,
+                },
+                _ = position_checks.tick(), if plan.min_distance.is_some() => {
+                    let position = self.position.lock().unwrap().clone();
+                    if !last.has_fix() {
+                        // Without a fix at the last waypoint, measure from the first fix after it.
+                        last = position;
+                    } else if position.has_fix()
+                        && last.distance(&position) >= plan.min_distance.unwrap_or_default()
+                    {
+                        return Some(WaypointTrigger::Moved);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{AccessCategory, ProbeStrategy, WmmResult};
+    use crate::record::{IntervalRecord, Record};
+    use std::sync::{mpsc as std_mpsc, Arc};
+
+    #[test]
+    fn parses_plan_steps() {
+        for step in ["udp:5", "latency:10", "probe", "wmm:3"] {
+            assert_eq!(step.parse::<SurveyStep>().unwrap().to_string(), step);
+        }
+        assert_eq!("udp".parse(), Ok(SurveyStep::Udp(DEFAULT_STEP_DURATION)));
+        assert!("udp:fast".parse::<SurveyStep>().is_err());
+        assert!("tcp:10".parse::<SurveyStep>().is_err());
+    }
+
+    /// A Sender towards a local socket that echoes every packet, like a Receiver.
+    async fn echoed_sender() -> Sender {
+        let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 9000];
+            while let Ok((len, from)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..len], from).await;
+            }
+        });
+        Sender::builder(target).tos(32).build().unwrap()
+    }
+
+    fn plan() -> SurveyPlan {
+        SurveyPlan {
+            steps: Vec::new(),
+            data_rate: 1.0,
+            packet_size: 500,
+            probe: ProbeConfig {
+                strategy: ProbeStrategy::Step,
+                start_rate: 1.0,
+                max_rate: 2.0,
+                rate_step: 1.0,
+                step_duration: Duration::from_millis(300),
+                max_loss: 50.0,
+                max_rtt: None,
+                packet_size: 500,
+            },
+            min_distance: None,
+            interval: None,
+        }
+    }
+
+    fn intervals(records: &std_mpsc::Receiver<Record>) -> Vec<IntervalRecord> {
+        records
+            .try_iter()
+            .filter_map(|record| match record {
+                Record::Interval(interval) => Some(interval),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn steps_record_their_tests_at_the_waypoint() {
+        let mut sender = echoed_sender().await;
+        sender.waypoint = Some(3);
+        let output = RecordWriter::default();
+        let records = output.subscribe();
+        let plan = plan();
+
+        sender.run_step(SurveyStep::Udp(1), &plan, &output).await;
+        let udp = intervals(&records);
+        sender
+            .run_step(SurveyStep::Latency(1), &plan, &output)
+            .await;
+        let latency = intervals(&records);
+        assert!(!udp.is_empty() && !latency.is_empty());
+        assert!(udp
+            .iter()
+            .chain(&latency)
+            .all(|interval| interval.waypoint == Some(3)));
+        // 1 Mbit/s in 500 byte packets against 0.1 Mbit/s in 200 byte packets
+        let sent = |intervals: &[IntervalRecord]| {
+            intervals
+                .iter()
+                .map(|interval| interval.packets_sent.unwrap_or_default())
+                .sum::<u32>()
+        };
+        assert!(sent(&udp) > 2 * sent(&latency));
+
+        sender.run_step(SurveyStep::Probe, &plan, &output).await;
+        let probe = records.try_iter().find_map(|record| match record {
+            Record::Probe(result) => Some(result),
+            _ => None,
+        });
+        assert_eq!(probe.unwrap().waypoint, Some(3));
+    }
+
+    #[tokio::test]
+    async fn wmm_step_restores_the_marking() {
+        let mut sender = echoed_sender().await;
+        let output = RecordWriter::default();
+        let records = output.subscribe();
+
+        sender.run_step(SurveyStep::Wmm(1), &plan(), &output).await;
+        let result: Option<WmmResult> = records.try_iter().find_map(|record| match record {
+            Record::Wmm(result) => Some(result),
+            _ => None,
+        });
+        assert_eq!(result.unwrap().categories.len(), AccessCategory::ALL.len());
+        assert_eq!(sender.tos, Some(32));
+    }
+
+    #[tokio::test]
+    async fn next_waypoint_on_timer_or_mark() {
+        let sender = echoed_sender().await;
+        let (mark, mut marks) = mpsc::channel(1);
+        let mut plan = plan();
+        let here = Position::default();
+
+        // The mark was made during the tests, so the timer ends the wait.
+        mark.try_send(()).unwrap();
+        plan.interval = Some(Duration::from_millis(100));
+        let trigger = sender.next_waypoint(&plan, here.clone(), &mut marks).await;
+        assert_eq!(trigger, Some(WaypointTrigger::Timer));
+
+        plan.interval = None;
+        let marking = tokio::spawn({
+            let mark = mark.clone();
+            async move {
+                time::sleep(Duration::from_millis(50)).await;
+                mark.send(()).await.unwrap();
+            }
+        });
+        let trigger = sender.next_waypoint(&plan, here.clone(), &mut marks).await;
+        assert_eq!(trigger, Some(WaypointTrigger::Mark));
+        marking.await.unwrap();
+
+        // Without marks, a timer or a distance, only the shutdown ends the wait.
+        drop(mark);
+        let shutdown = sender.shutdown.clone();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(50)).await;
+            shutdown.trigger();
+        });
+        assert_eq!(sender.next_waypoint(&plan, here, &mut marks).await, None);
+    }
+
+    #[tokio::test]
+    async fn next_waypoint_once_moved_far_enough() {
+        let sender = echoed_sender().await;
+        let (_mark, mut marks) = mpsc::channel(1);
+        let mut plan = plan();
+        plan.min_distance = Some(5.0);
+        let time = "2023-04-01T12:00:00.000Z".to_string();
+        let last = Position::new(time.clone(), 52.0, 13.0);
+        *sender.position.lock().unwrap() = last.clone();
+
+        let position = Arc::clone(&sender.position);
+        tokio::spawn(async move {
+            // About 3 m, then about 11 m away from the last waypoint
+            for lat in [52.00003, 52.0001] {
+                time::sleep(Duration::from_millis(300)).await;
+                *position.lock().unwrap() = Position::new(time.clone(), lat, 13.0);
+            }
+        });
+        let waiting = time::Instant::now();
+        let trigger = sender.next_waypoint(&plan, last, &mut marks).await;
+        assert_eq!(trigger, Some(WaypointTrigger::Moved));
+        assert!(waiting.elapsed() >= Duration::from_millis(600));
+    }
+}
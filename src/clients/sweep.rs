@@ -0,0 +1,265 @@
+use super::{sockopt, Interval, Sender, Summary};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::record::RecordWriter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio::time;
+
+use tracing::{debug, info, warn};
+
+/// Time to wait after each step so that late echoes don't count towards the next step.
+const SETTLE_DURATION: Duration = Duration::from_millis(200);
+/// Number of packets sent when checking whether a single packet size gets through.
+const FIT_PROBES: usize = 3;
+/// How long to wait for the echo of such a packet.
+const FIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Parameters of a packet size sweep.
+#[derive(Debug, Clone)]
+pub struct SweepConfig {
+    /// data rate of every step in Mbit/s
+    pub data_rate: f64,
+    /// smallest packet size (IP header included) in bytes
+    pub min_size: usize,
+    /// largest packet size (IP header included) in bytes
+    pub max_size: usize,
+    /// increase of the packet size between steps in bytes
+    pub size_step: usize,
+    /// how long each packet size is tested
+    pub step_duration: Duration,
+}
+
+/// Aggregated measurements of a single tested packet size.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SweepStep {
+    /// packet size including the IP and UDP header in bytes
+    pub packet_size: usize,
+    pub payload_size: usize,
+    /// measured throughput in Mbit/s
+    pub throughput: f64,
+    pub packets_sent: u32,
+    pub packets_received: u32,
+    /// packets that the socket refused to send, e.g. because they exceed the known path MTU
+    pub send_errors: u32,
+    /// loss in percent
+    pub loss: f64,
+    /// average round trip time in milliseconds
+    pub rtt: Option<f64>,
+}
+
+/// Outcome of a packet size sweep at one location.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SweepResult {
+    pub position: Position,
+    /// size of the IP and UDP header in bytes
+    pub header_size: usize,
+    /// largest packet size that reached the Receiver without fragmentation, `None` if no size did
+    pub path_mtu: Option<usize>,
+    pub steps: Vec<SweepStep>,
+    /// survey waypoint the sweep ran at
+    pub waypoint: Option<u32>,
+}
+
+impl fmt::Display for SweepResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Size  Throughput  received/sent (loss)  RTT")?;
+        for step in &self.steps {
+            writeln!(
+                f,
+                "{} {:.2} Mbit/s {}/{} ({:3.2}%) {:.3}ms",
+                step.packet_size,
+                step.throughput,
+                step.packets_received,
+                step.packets_sent,
+                step.loss,
+                step.rtt.unwrap_or_default()
+            )?;
+        }
+        match self.path_mtu {
+            Some(mtu) => write!(f, "Path MTU: {} bytes at {}", mtu, self.position),
+            None => write!(
+                f,
+                "No packet size reached the receiver at {}",
+                self.position
+            ),
+        }
+    }
+}
+
+impl Sender {
+    /// Measures throughput and loss for a range of packet sizes with the don't fragment bit set and determines the path MTU.
+    /// After a shutdown, the result only covers the completed sizes and has no path MTU.
+    pub async fn sweep(self, config: SweepConfig, output: RecordWriter) -> Result<(), NetMapError> {
+        for packet_size in [config.min_size, config.max_size] {
+            Self::payload_size(packet_size, &self.target_address)?;
+        }
+        if let Err(e) = sockopt::set_dont_fragment(&self.recv_socket, self.target_address.is_ipv6())
+        {
+            warn!("Couldn't set the don't fragment bit: {}", e);
+        }
+        let header_size = Self::header_size(&self.target_address);
+        info!(
+            "Sweeping {} from {} to {} bytes at {} Mbit/s",
+            self.target_address, config.min_size, config.max_size, config.data_rate
+        );
+
+        let mut steps = Vec::new();
+        let mut packet_size = config.min_size;
+        while packet_size <= config.max_size {
+            let step = self.sweep_size(packet_size, &config).await;
+            if self.shutdown.is_triggered() {
+                break;
+            }
+            debug!(
+                "{} {:.2} Mbit/s {}/{} ({:3.2}%) {:.3}ms",
+                step.packet_size,
+                step.throughput,
+                step.packets_received,
+                step.packets_sent,
+                step.loss,
+                step.rtt.unwrap_or_default()
+            );
+            steps.push(step);
+            packet_size += config.size_step.max(1);
+        }
+
+        let path_mtu = match self.shutdown.is_triggered() {
+            true => None,
+            false => self.path_mtu(&steps).await,
+        };
+
+        let result = SweepResult {
+            position: self.position.lock().unwrap().clone(),
+            header_size,
+            path_mtu,
+            steps,
+            waypoint: self.waypoint,
+        };
+        if let Err(e) = output.write(result) {
+            warn!("Couldn't write sweep result: {}", e);
+        }
+        Ok(())
+    }
+
+    async fn sweep_size(&self, packet_size: usize, config: &SweepConfig) -> SweepStep {
+        let mut intervals: Vec<Interval> = Vec::new();
+        self.transmit(
+            config.data_rate,
+            packet_size,
+            Some(config.step_duration),
+            |interval| intervals.push(interval.clone()),
+        )
+        .await;
+        time::sleep(SETTLE_DURATION).await;
+        self.drain();
+
+        let summary = Summary::new(&intervals);
+        SweepStep {
+            packet_size,
+            payload_size: packet_size.saturating_sub(Self::header_size(&self.target_address)),
+            throughput: summary.throughput,
+            packets_sent: summary.packets_sent,
+            packets_received: summary.packets_received,
+            send_errors: summary.send_errors,
+            loss: summary.loss,
+            rtt: summary.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+        }
+    }
+
+    /// Narrows the boundary between the largest size that got through and the next larger one down to a single byte.
+    async fn path_mtu(&self, steps: &[SweepStep]) -> Option<usize> {
+        let mut low = steps
+            .iter()
+            .filter(|step| step.packets_received > 0)
+            .map(|step| step.packet_size)
+            .max()?;
+        let mut high = match steps.iter().find(|step| step.packet_size > low) {
+            Some(step) => step.packet_size,
+            None => return Some(low),
+        };
+
+        while high - low > 1 {
+            let packet_size = (low + high) / 2;
+            if self.fits(packet_size).await {
+                low = packet_size;
+            } else {
+                high = packet_size;
+            }
+        }
+        Some(low)
+    }
+
+    /// Checks whether a packet of `packet_size` bytes gets echoed by the Receiver.
+    async fn fits(&self, packet_size: usize) -> bool {
+        let payload_size = match Self::payload_size(packet_size, &self.target_address) {
+            Ok(payload_size) => payload_size,
+            Err(_) => return false,
+        };
+        let payload = vec![0; payload_size];
+
+        self.drain();
+        for _ in 0..FIT_PROBES {
+            if let Err(e) = self
+                .recv_socket
+                .send_to(&payload, self.target_address)
+                .await
+            {
+                debug!("{} bytes don't fit: {}", packet_size, e);
+                return false;
+            }
+        }
+
+        let mut buf = vec![0; 65536];
+        let echoed = async {
+            loop {
+                if let Ok((size, _)) = self.recv_socket.recv_from(&mut buf).await {
+                    if size == payload_size {
+                        break;
+                    }
+                }
+            }
+        };
+        time::timeout(FIT_TIMEOUT, echoed).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[tokio::test]
+    async fn result_carries_the_waypoint() {
+        let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 9000];
+            while let Ok((len, from)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..len], from).await;
+            }
+        });
+        let mut sender = Sender::builder(target).build().unwrap();
+        sender.waypoint = Some(2);
+        let output = RecordWriter::default();
+        let records = output.subscribe();
+        let config = SweepConfig {
+            data_rate: 0.5,
+            min_size: 500,
+            max_size: 500,
+            size_step: 100,
+            step_duration: Duration::from_secs(1),
+        };
+        sender.sweep(config, output).await.unwrap();
+
+        let result = records.try_iter().find_map(|record| match record {
+            Record::Sweep(result) => Some(result),
+            _ => None,
+        });
+        let result = result.unwrap();
+        assert_eq!(result.waypoint, Some(2));
+        assert_eq!(result.steps.len(), 1);
+        assert_eq!(result.path_mtu, Some(500));
+    }
+}
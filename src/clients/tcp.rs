@@ -0,0 +1,511 @@
+use super::roam::RoamDetector;
+use super::{listen, sockopt, AccessCategory, Protocol, ReceiverBuilder, SenderBuilder};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::link::LinkInfo;
+use crate::record::{IntervalRecord, RecordWriter};
+use crate::shutdown::{Pause, Shutdown};
+use crate::wireless::WirelessStats;
+use std::ffi::CStr;
+use std::io::ErrorKind;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+use tokio::time::{self, MissedTickBehavior};
+
+use tracing::{debug, info, warn};
+
+/// Longest time the Sender writes without giving other tasks of the runtime a turn.
+const YIELD_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Leading part of the Linux `struct tcp_info` (see `linux/tcp.h`).
+/// The kernel copies as much of it as it knows, older kernels leave the tail zeroed.
+#[repr(C)]
+#[derive(Default)]
+struct TcpInfo {
+    tcpi_state: u8,
+    tcpi_ca_state: u8,
+    tcpi_retransmits: u8,
+    tcpi_probes: u8,
+    tcpi_backoff: u8,
+    tcpi_options: u8,
+    tcpi_wscale: u8,
+    tcpi_flags: u8,
+    tcpi_rto: u32,
+    tcpi_ato: u32,
+    tcpi_snd_mss: u32,
+    tcpi_rcv_mss: u32,
+    tcpi_unacked: u32,
+    tcpi_sacked: u32,
+    tcpi_lost: u32,
+    tcpi_retrans: u32,
+    tcpi_fackets: u32,
+    tcpi_last_data_sent: u32,
+    tcpi_last_ack_sent: u32,
+    tcpi_last_data_recv: u32,
+    tcpi_last_ack_recv: u32,
+    tcpi_pmtu: u32,
+    tcpi_rcv_ssthresh: u32,
+    tcpi_rtt: u32,
+    tcpi_rttvar: u32,
+    tcpi_snd_ssthresh: u32,
+    tcpi_snd_cwnd: u32,
+    tcpi_advmss: u32,
+    tcpi_reordering: u32,
+    tcpi_rcv_rtt: u32,
+    tcpi_rcv_space: u32,
+    tcpi_total_retrans: u32,
+    tcpi_pacing_rate: u64,
+    tcpi_max_pacing_rate: u64,
+    tcpi_bytes_acked: u64,
+    tcpi_bytes_received: u64,
+}
+
+/// Snapshot of the kernel's view of the connection.
+struct TcpStats {
+    rtt: Duration,
+    cwnd: u32,
+    total_retransmits: u32,
+    /// `None` on kernels that don't report acknowledged bytes
+    bytes_acked: Option<u64>,
+}
+
+impl TcpStats {
+    fn read(stream: &TcpStream) -> std::io::Result<Self> {
+        let mut info = TcpInfo::default();
+        let mut len = mem::size_of::<TcpInfo>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                &mut info as *mut TcpInfo as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(TcpStats::from_info(&info, len as usize))
+    }
+
+    /// Picks the fields out of `info`, of which the kernel filled in the first `len` bytes.
+    fn from_info(info: &TcpInfo, len: usize) -> Self {
+        let acked_end = mem::size_of::<TcpInfo>() - mem::size_of::<u64>();
+        TcpStats {
+            rtt: Duration::from_micros(info.tcpi_rtt as u64),
+            cwnd: info.tcpi_snd_cwnd,
+            total_retransmits: info.tcpi_total_retrans,
+            bytes_acked: (len >= acked_end).then_some(info.tcpi_bytes_acked),
+        }
+    }
+
+    fn congestion_control(stream: &TcpStream) -> Option<String> {
+        let mut name = [0_u8; 16];
+        let mut len = name.len() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_CONGESTION,
+                name.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return None;
+        }
+        let end = name.iter().position(|&b| b == 0).unwrap_or(name.len() - 1);
+        CStr::from_bytes_with_nul(&name[..=end])
+            .ok()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+/// Saturates a TCP stream to the Receiver and reports goodput and the kernel's TCP statistics.
+pub struct TcpSender {
+    stream: TcpStream,
+    target_address: SocketAddr,
+    position: Arc<Mutex<Position>>,
+    // TOS byte of the outgoing packets
+    tos: Option<u8>,
+    wireless: Arc<Mutex<Option<WirelessStats>>>,
+    shutdown: Shutdown,
+    pause: Pause,
+    // start of the run that the intervals are timed from
+    pub(super) started: Instant,
+}
+
+impl TcpSender {
+    pub fn builder(target_address: SocketAddr) -> SenderBuilder {
+        SenderBuilder::new(target_address)
+    }
+
+    /// Connects to the Receiver, over the given interface only if there is one.
+    pub(super) fn connect(
+        target_address: SocketAddr,
+        interface: Option<&LinkInfo>,
+        tos: Option<u8>,
+        position: Arc<Mutex<Position>>,
+        wireless: Arc<Mutex<Option<WirelessStats>>>,
+        shutdown: Shutdown,
+        pause: Pause,
+    ) -> Result<Self, NetMapError> {
+        let stream = match interface {
+            Some(interface) => {
+                let source = interface
+                    .source_address(&target_address.ip())
+                    .ok_or_else(|| NetMapError::NoSourceAddress {
+                        interface: interface.name.clone(),
+                        target: target_address,
+                    })?;
+                sockopt::connect_tcp(target_address, source, &interface.name)?
+            }
+            None => std::net::TcpStream::connect(target_address).map_err(|source| {
+                NetMapError::Connect {
+                    address: target_address,
+                    source,
+                }
+            })?,
+        };
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        if let Some(tos) = tos {
+            if let Err(e) = sockopt::set_tos(&stream, target_address.is_ipv6(), tos) {
+                warn!("Couldn't set TOS {}: {}", tos, e);
+            }
+        }
+        Ok(Self {
+            stream: TcpStream::from_std(stream)?,
+            target_address,
+            position,
+            tos,
+            wireless,
+            shutdown,
+            pause,
+            started: Instant::now(),
+        })
+    }
+
+    /// Writes to the stream as fast as it accepts data until the shutdown is triggered and records every interval.
+    pub async fn run(self, packet_size: usize, output: RecordWriter) -> Result<(), NetMapError> {
+        let stream = &self.stream;
+        let congestion_control = TcpStats::congestion_control(stream);
+        info!(
+            "Connected to {}, write size: {} bytes, congestion control: {:?}",
+            self.target_address, packet_size, congestion_control
+        );
+
+        let payload: Vec<u8> = vec![0; packet_size];
+        let mut second_timer = Instant::now();
+        let mut bytes_written: u64 = 0;
+        let mut last_stats = TcpStats::read(stream)?;
+        let mut roam_detector = RoamDetector::new();
+        let mut last_yield = Instant::now();
+        let mut ticks = time::interval_at(
+            time::Instant::now() + Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let paused = self.pause.is_paused();
+            tokio::select! {
+                writable = stream.writable(), if !paused => {
+                    writable?;
+                    match stream.try_write(&payload) {
+                        Ok(size) => bytes_written += size as u64,
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                        Err(source) => {
+                            return Err(NetMapError::Connect {
+                                address: self.target_address,
+                                source,
+                            })
+                        }
+                    }
+                }
+                // Wakes the loop up while the stream is blocked.
+                _ = ticks.tick() => {}
+                _ = self.shutdown.wait() => break,
+            }
+            // The stream is writable most of the time, let the runtime handle timers and signals in between.
+            if last_yield.elapsed() >= YIELD_INTERVAL {
+                task::yield_now().await;
+                last_yield = Instant::now();
+            }
+
+            let second_elapsed = second_timer.elapsed();
+            if second_elapsed >= Duration::from_secs(1) {
+                let stats = TcpStats::read(stream)?;
+                let record = self.loop_log(
+                    &stats,
+                    &last_stats,
+                    bytes_written,
+                    second_elapsed,
+                    self.started.elapsed(),
+                    &congestion_control,
+                );
+                // TCP retransmits through the outage of a roam, so its roams come without one.
+                let event = roam_detector.update(
+                    self.started.elapsed(),
+                    &[],
+                    None,
+                    record.wireless.as_ref(),
+                    &record.position,
+                );
+                // Intervals during a pause only hold the acknowledgements of the data written before it.
+                if !paused {
+                    if let Err(e) = output.write(record) {
+                        warn!("Couldn't write interval record: {}", e);
+                    }
+                }
+                if let Some(event) = event {
+                    if let Err(e) = output.write(event) {
+                        warn!("Couldn't write roam event: {}", e);
+                    }
+                }
+
+                last_stats = stats;
+                bytes_written = 0;
+                second_timer = Instant::now();
+                ticks.reset();
+            }
+        }
+        Ok(())
+    }
+
+    fn loop_log(
+        &self,
+        stats: &TcpStats,
+        last_stats: &TcpStats,
+        bytes_written: u64,
+        second_elapsed: Duration,
+        total_time: Duration,
+        congestion_control: &Option<String>,
+    ) -> IntervalRecord {
+        let interval_from = total_time.saturating_sub(second_elapsed); // Lower boundary for this interval
+        let interval_to = total_time; // Upper boundary for this interval
+
+        // Goodput only counts what the Receiver acknowledged, fall back to the written bytes otherwise.
+        let bytes = match (stats.bytes_acked, last_stats.bytes_acked) {
+            (Some(acked), Some(last_acked)) => acked.saturating_sub(last_acked),
+            _ => bytes_written,
+        };
+        let goodput = (bytes * 8) as f64 / second_elapsed.as_secs_f64(); // Bit-Goodput in this interval
+        let retransmits = stats
+            .total_retransmits
+            .saturating_sub(last_stats.total_retransmits);
+
+        debug!(
+            "{:3.1}-{:3.1} {:.2} Mbytes {:.2} Mbit/s {} {} {:?}",
+            interval_from.as_secs_f64(),
+            interval_to.as_secs_f64(),
+            bytes as f64 / 1000000.0,
+            goodput / 1000000.0,
+            retransmits,
+            stats.cwnd,
+            stats.rtt
+        );
+
+        IntervalRecord {
+            protocol: Protocol::Tcp,
+            from: interval_from.as_secs_f64(),
+            to: interval_to.as_secs_f64(),
+            position: self.position.lock().unwrap().clone(),
+            bytes,
+            throughput: goodput / 1000000.0,
+            packets_sent: None,
+            packets_received: None,
+            loss: None,
+            rtt: Some(stats.rtt.as_secs_f64() * 1000.0),
+            rtt_stddev: None,
+            errors: None,
+            retransmits: Some(retransmits),
+            cwnd: Some(stats.cwnd),
+            congestion_control: congestion_control.clone(),
+            dscp: self.tos.map(|tos| tos >> 2),
+            access_category: self.tos.map(|tos| AccessCategory::from_dscp(tos >> 2)),
+            wireless: self.wireless.lock().unwrap().clone(),
+            waypoint: None,
+            zones: Vec::new(),
+        }
+    }
+}
+
+/// Accepts TCP streams and discards the received data.
+pub struct TcpReceiver {
+    listener: TcpListener,
+    pub(super) shutdown: Shutdown,
+}
+
+impl TcpReceiver {
+    pub fn builder(port: u16) -> ReceiverBuilder {
+        ReceiverBuilder::new(port)
+    }
+
+    pub(super) fn bind(port: u16, interface: Option<&LinkInfo>) -> Result<Self, NetMapError> {
+        let listener = listen(port, interface, std::net::TcpListener::bind)?;
+        if let Some(interface) = interface {
+            sockopt::bind_to_device(&listener, &interface.name)?;
+        }
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// Accepts connections until the shutdown is triggered.
+    pub async fn run(self) -> Result<(), NetMapError> {
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(Self::receive(stream, self.shutdown.clone()));
+                    }
+                    Err(e) => warn!("Couldn't accept connection: {}", e),
+                },
+                _ = self.shutdown.wait() => break,
+            }
+        }
+        Ok(())
+    }
+
+    async fn receive(mut stream: TcpStream, shutdown: Shutdown) {
+        let peer = stream.peer_addr().ok();
+        info!("Accepted connection from {:?}", peer);
+
+        let mut buf = vec![0; 65536];
+        let mut bytes_received = 0; // counter variable to keep track of bytes received
+        let mut start_time = Instant::now(); // start time for measuring elapsed time
+
+        loop {
+            tokio::select! {
+                result = stream.read(&mut buf) => match result {
+                    Ok(0) => break,
+                    Ok(size) => bytes_received += size,
+                    Err(e) => {
+                        debug!("Connection from {:?} failed: {}", peer, e);
+                        break;
+                    }
+                },
+                _ = shutdown.wait() => break,
+            }
+
+            let elapsed_time = start_time.elapsed();
+            if elapsed_time > Duration::from_secs(1) {
+                let bytes_per_second = bytes_received as f64 / elapsed_time.as_secs_f64();
+                info!(
+                    "Data Rate: {:.2} Mbps",
+                    bytes_per_second * 8.0 / 1_000_000.0
+                );
+                bytes_received = 0;
+                start_time = Instant::now();
+            }
+        }
+        info!("Connection from {:?} closed", peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(total_retransmits: u32, bytes_acked: Option<u64>) -> TcpStats {
+        TcpStats {
+            rtt: Duration::from_millis(20),
+            cwnd: 10,
+            total_retransmits,
+            bytes_acked,
+        }
+    }
+
+    #[test]
+    fn bytes_acked_only_if_the_kernel_reports_them() {
+        let info = TcpInfo {
+            tcpi_rtt: 1500,
+            tcpi_snd_cwnd: 10,
+            tcpi_total_retrans: 3,
+            tcpi_bytes_acked: 4000,
+            ..TcpInfo::default()
+        };
+        let full = mem::size_of::<TcpInfo>();
+        let stats = TcpStats::from_info(&info, full);
+        assert_eq!(stats.rtt, Duration::from_micros(1500));
+        assert_eq!(stats.cwnd, 10);
+        assert_eq!(stats.total_retransmits, 3);
+        assert_eq!(stats.bytes_acked, Some(4000));
+        // Up to the end of bytes_acked, without bytes_received.
+        assert_eq!(TcpStats::from_info(&info, full - 8).bytes_acked, Some(4000));
+        assert_eq!(TcpStats::from_info(&info, full - 9).bytes_acked, None);
+        assert_eq!(TcpStats::from_info(&info, full - 16).bytes_acked, None);
+    }
+
+    #[tokio::test]
+    async fn interval_counts_acknowledged_bytes_and_new_retransmits() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpSender::builder(listener.local_addr().unwrap())
+            .build_tcp()
+            .unwrap();
+        let second = Duration::from_secs(1);
+
+        let record = sender.loop_log(
+            &stats(5, Some(3_000_000)),
+            &stats(2, Some(1_750_000)),
+            4_000_000,
+            second,
+            Duration::from_secs(3),
+            &Some("cubic".to_string()),
+        );
+        assert_eq!(record.bytes, 1_250_000);
+        assert_eq!(record.throughput, 10.0);
+        assert_eq!(record.retransmits, Some(3));
+        assert_eq!(record.from, 2.0);
+        assert_eq!(record.to, 3.0);
+        assert_eq!(record.rtt, Some(20.0));
+        assert_eq!(record.congestion_control.as_deref(), Some("cubic"));
+
+        // Without acknowledged bytes, the written bytes count.
+        for (current, last) in [(None, None), (Some(3_000_000), None), (None, Some(0))] {
+            let record = sender.loop_log(
+                &stats(2, current),
+                &stats(2, last),
+                500_000,
+                Duration::from_millis(500),
+                second,
+                &None,
+            );
+            assert_eq!(record.bytes, 500_000);
+            assert_eq!(record.throughput, 8.0);
+            assert_eq!(record.retransmits, Some(0));
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_the_stats_of_a_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpSender::builder(listener.local_addr().unwrap())
+            .build_tcp()
+            .unwrap();
+        let stats = TcpStats::read(&sender.stream).unwrap();
+        assert!(stats.cwnd > 0);
+        assert_eq!(stats.total_retransmits, 0);
+    }
+
+    #[tokio::test]
+    async fn listens_on_the_unspecified_address_without_an_interface() {
+        let receiver = TcpReceiver::bind(0, None).unwrap();
+        assert!(receiver
+            .listener
+            .local_addr()
+            .unwrap()
+            .ip()
+            .is_unspecified());
+    }
+}
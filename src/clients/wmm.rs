@@ -0,0 +1,221 @@
+use super::{Interval, Sender, Summary};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::record::RecordWriter;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio::time;
+
+use tracing::{info, warn};
+
+/// Time to wait after each category so that late echoes don't count towards the next one.
+const SETTLE_DURATION: Duration = Duration::from_millis(200);
+
+/// Wi-Fi multimedia (WMM) access categories.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessCategory {
+    /// AC_VO, marked as EF (DSCP 46)
+    Voice,
+    /// AC_VI, marked as AF41 (DSCP 34)
+    Video,
+    /// AC_BE, unmarked (DSCP 0)
+    BestEffort,
+    /// AC_BK, marked as CS1 (DSCP 8)
+    Background,
+}
+
+impl AccessCategory {
+    pub const ALL: [AccessCategory; 4] = [
+        AccessCategory::Voice,
+        AccessCategory::Video,
+        AccessCategory::BestEffort,
+        AccessCategory::Background,
+    ];
+
+    /// DSCP that maps to this access category (RFC 8325).
+    pub fn dscp(&self) -> u8 {
+        match self {
+            AccessCategory::Voice => 46,
+            AccessCategory::Video => 34,
+            AccessCategory::BestEffort => 0,
+            AccessCategory::Background => 8,
+        }
+    }
+
+    /// Access category that a DSCP is mapped to by RFC 8325, with the code points it leaves out as best effort.
+    /// Stations and access points that map by the precedence (the upper three bits) instead
+    /// put AF1x and CS2 into background and EF into video.
+    pub fn from_dscp(dscp: u8) -> Self {
+        match dscp {
+            // LE and CS1, low-priority data
+            1 | 8 => AccessCategory::Background,
+            // CS3, AF3x, CS4, AF4x and CS5, from broadcast video to signaling
+            24 | 26 | 28 | 30 | 32 | 34 | 36 | 38 | 40 => AccessCategory::Video,
+            // VOICE-ADMIT, EF and CS6, telephony and network control
+            44 | 46 | 48 => AccessCategory::Voice,
+            _ => AccessCategory::BestEffort,
+        }
+    }
+}
+
+impl fmt::Display for AccessCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            AccessCategory::Voice => write!(f, "voice"),
+            AccessCategory::Video => write!(f, "video"),
+            AccessCategory::BestEffort => write!(f, "best-effort"),
+            AccessCategory::Background => write!(f, "background"),
+        }
+    }
+}
+
+/// Aggregated measurements of a single access category.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WmmCategory {
+    pub access_category: AccessCategory,
+    pub dscp: u8,
+    /// measured throughput in Mbit/s
+    pub throughput: f64,
+    pub packets_sent: u32,
+    pub packets_received: u32,
+    /// loss in percent
+    pub loss: f64,
+    /// average round trip time in milliseconds
+    pub rtt: Option<f64>,
+}
+
+/// Outcome of a WMM test at one location.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WmmResult {
+    pub position: Position,
+    pub categories: Vec<WmmCategory>,
+    /// survey waypoint the test ran at
+    pub waypoint: Option<u32>,
+}
+
+impl fmt::Display for WmmResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Category  Throughput  received/sent (loss)  RTT")?;
+        for category in &self.categories {
+            writeln!(
+                f,
+                "{} {:.2} Mbit/s {}/{} ({:3.2}%) {:.3}ms",
+                category.access_category,
+                category.throughput,
+                category.packets_received,
+                category.packets_sent,
+                category.loss,
+                category.rtt.unwrap_or_default()
+            )?;
+        }
+        write!(f, "at {}", self.position)
+    }
+}
+
+impl Sender {
+    /// Runs the same test once per WMM access category, each for `duration`.
+    pub async fn wmm(
+        mut self,
+        data_rate: f64,
+        packet_size: usize,
+        duration: Duration,
+        output: RecordWriter,
+    ) -> Result<(), NetMapError> {
+        Self::payload_size(packet_size, &self.target_address)?;
+        let result = self
+            .test_categories(data_rate, packet_size, duration, &output)
+            .await;
+        if let Err(e) = output.write(result) {
+            warn!("Couldn't write WMM result: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Tests every access category in turn and writes the intervals to `output`.
+    /// Leaves the packets marked with the DSCP of the last tested category.
+    pub(super) async fn test_categories(
+        &mut self,
+        data_rate: f64,
+        packet_size: usize,
+        duration: Duration,
+        output: &RecordWriter,
+    ) -> WmmResult {
+        let mut categories = Vec::new();
+        for category in AccessCategory::ALL {
+            if let Err(e) = self.set_dscp(category.dscp()) {
+                warn!("Couldn't mark packets for {}: {}", category, e);
+                continue;
+            }
+            info!(
+                "Testing access category {} (DSCP {})",
+                category,
+                category.dscp()
+            );
+
+            let mut intervals: Vec<Interval> = Vec::new();
+            self.transmit(data_rate, packet_size, Some(duration), |interval| {
+                if let Err(e) = output.write(self.record(interval)) {
+                    warn!("Couldn't write interval record: {}", e);
+                }
+                intervals.push(interval.clone());
+            })
+            .await;
+            if self.shutdown.is_triggered() {
+                break;
+            }
+            time::sleep(SETTLE_DURATION).await;
+            self.drain();
+
+            let summary = Summary::new(&intervals);
+            categories.push(WmmCategory {
+                access_category: category,
+                dscp: category.dscp(),
+                throughput: summary.throughput,
+                packets_sent: summary.packets_sent,
+                packets_received: summary.packets_received,
+                loss: summary.loss,
+                rtt: summary.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            });
+        }
+
+        WmmResult {
+            position: self.position.lock().unwrap().clone(),
+            categories,
+            waypoint: self.waypoint,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dscp_maps_to_access_category_by_rfc_8325() {
+        for category in AccessCategory::ALL {
+            assert_eq!(AccessCategory::from_dscp(category.dscp()), category);
+        }
+        let category = |dscps: &[u8]| {
+            dscps
+                .iter()
+                .map(|dscp| AccessCategory::from_dscp(*dscp))
+                .collect::<Vec<_>>()
+        };
+        // AF1x, CS2 and AF2x stay best effort, unlike with the precedence.
+        assert_eq!(
+            category(&[0, 10, 12, 14, 16, 18, 20, 22, 56]),
+            [AccessCategory::BestEffort; 9]
+        );
+        assert_eq!(category(&[1, 8]), [AccessCategory::Background; 2]);
+        assert_eq!(
+            category(&[24, 26, 32, 34, 38, 40]),
+            [AccessCategory::Video; 6]
+        );
+        assert_eq!(category(&[44, 46, 48]), [AccessCategory::Voice; 3]);
+        // Code points without a mapping, even within a class
+        assert_eq!(category(&[25, 47, 63]), [AccessCategory::BestEffort; 3]);
+    }
+}
@@ -0,0 +1,585 @@
+//! Comparison of two sessions of the same path, e.g. before and after an access point upgrade.
+//!
+//! The intervals of both sessions are grouped into locations, either around the positions of the first session
+//! or by zone, and the throughput, loss and latency at every location are compared.
+
+use crate::annotation::Annotation;
+use crate::gps::{IndoorPosition, Position};
+use crate::record::{IntervalRecord, Record};
+use crate::session::Session;
+use crate::stats::{IntervalMetrics, CONFIDENCE};
+use crate::zones::{Zone, Zones};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::{self, Write};
+
+/// How the intervals of the two sessions are matched.
+#[derive(Debug, Clone)]
+pub enum Alignment {
+    /// Locations around the positions of the first session, within this many meters.
+    Nearest(f64),
+    /// One location per zone.
+    Zones(Zones),
+}
+
+/// Changes beyond which a location counts as a regression.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// drop of the median throughput in percent
+    pub throughput_drop: f64,
+    /// increase of the loss in percentage points
+    pub loss_increase: f64,
+    /// increase of the 95th percentile round trip time in milliseconds
+    pub rtt_increase: f64,
+    /// intervals both sessions need at a location for a change to count
+    pub min_intervals: usize,
+}
+
+/// Aggregates of the intervals of one session at one location.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub intervals: usize,
+    /// median throughput in Mbit/s
+    pub throughput: f64,
+    /// 95% confidence interval of the mean throughput in Mbit/s, `None` below two intervals
+    pub throughput_ci: Option<(f64, f64)>,
+    /// loss over all packets in percent
+    pub loss: Option<f64>,
+    /// 95th percentile round trip time in milliseconds
+    pub rtt_p95: Option<f64>,
+}
+
+impl Metrics {
+    fn of(intervals: &[&IntervalRecord]) -> Option<Self> {
+        let mut metrics = IntervalMetrics::default();
+        intervals.iter().for_each(|interval| metrics.add(interval));
+        Some(Metrics {
+            intervals: intervals.len(),
+            throughput: metrics.throughput.percentile(50.0)?,
+            throughput_ci: metrics.throughput.moments().confidence_interval(CONFIDENCE),
+            loss: metrics.total_loss(),
+            rtt_p95: metrics.rtt.percentile(95.0),
+        })
+    }
+}
+
+/// Metric that got significantly worse at a location.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Regression {
+    Throughput,
+    Loss,
+    Rtt,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Regression::Throughput => write!(f, "throughput"),
+            Regression::Loss => write!(f, "loss"),
+            Regression::Rtt => write!(f, "rtt"),
+        }
+    }
+}
+
+/// The two sessions at one location.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LocationDiff {
+    pub name: String,
+    /// where the first session measured, the middle of its positions for a zone
+    pub position: Position,
+    pub before: Metrics,
+    /// `None` if the second session didn't measure here
+    pub after: Option<Metrics>,
+    pub regressions: Vec<Regression>,
+}
+
+impl LocationDiff {
+    /// Change of the median throughput in percent.
+    pub fn throughput_change(&self) -> Option<f64> {
+        let after = self.after.as_ref()?;
+        (self.before.throughput > 0.0)
+            .then(|| (after.throughput / self.before.throughput - 1.0) * 100.0)
+    }
+
+    /// Change of the loss in percentage points.
+    pub fn loss_change(&self) -> Option<f64> {
+        Some(self.after.as_ref()?.loss? - self.before.loss?)
+    }
+
+    /// Change of the 95th percentile round trip time in milliseconds.
+    pub fn rtt_change(&self) -> Option<f64> {
+        Some(self.after.as_ref()?.rtt_p95? - self.before.rtt_p95?)
+    }
+
+    fn check(&mut self, thresholds: &Thresholds) {
+        let enough = |metrics: &Metrics| metrics.intervals >= thresholds.min_intervals.max(1);
+        if !enough(&self.before) || !self.after.as_ref().is_some_and(enough) {
+            return;
+        }
+        // a drop within the noise of the measurements, where the confidence intervals overlap, isn't one
+        let significant = match (
+            self.before.throughput_ci,
+            self.after.as_ref().and_then(|after| after.throughput_ci),
+        ) {
+            (Some((low, _)), Some((_, high))) => high < low,
+            _ => true,
+        };
+        if significant
+            && self
+                .throughput_change()
+                .is_some_and(|change| -change > thresholds.throughput_drop)
+        {
+            self.regressions.push(Regression::Throughput);
+        }
+        if self
+            .loss_change()
+            .is_some_and(|change| change > thresholds.loss_increase)
+        {
+            self.regressions.push(Regression::Loss);
+        }
+        if self
+            .rtt_change()
+            .is_some_and(|change| change > thresholds.rtt_increase)
+        {
+            self.regressions.push(Regression::Rtt);
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match (&self.after, self.regressions.is_empty()) {
+            (None, _) => "unmatched",
+            (Some(_), true) => "ok",
+            (Some(_), false) => "regression",
+        }
+    }
+}
+
+impl fmt::Display for LocationDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}: ", self.name, self.position)?;
+        let Some(after) = &self.after else {
+            return write!(
+                f,
+                "{:.2} Mbit/s, not measured in the second session",
+                self.before.throughput
+            );
+        };
+        write!(
+            f,
+            "{:.2} -> {:.2} Mbit/s",
+            self.before.throughput, after.throughput
+        )?;
+        if let Some(change) = self.throughput_change() {
+            write!(f, " ({:+.1}%)", change)?;
+        }
+        if let (Some(before), Some(after)) = (self.before.loss, after.loss) {
+            write!(f, " loss {:.2} -> {:.2}%", before, after)?;
+        }
+        if let (Some(before), Some(after)) = (self.before.rtt_p95, after.rtt_p95) {
+            write!(f, " p95 {:.3} -> {:.3}ms", before, after)?;
+        }
+        if !self.regressions.is_empty() {
+            let regressions: Vec<String> = self.regressions.iter().map(|r| r.to_string()).collect();
+            write!(f, " REGRESSION {}", regressions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Locations of two sessions side by side.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub locations: Vec<LocationDiff>,
+    /// intervals of the second session that matched no location
+    pub unmatched: usize,
+    /// notes added during the first session
+    #[serde(default)]
+    pub annotations_before: Vec<Annotation>,
+    /// notes added during the second session
+    #[serde(default)]
+    pub annotations_after: Vec<Annotation>,
+}
+
+impl Comparison {
+    /// Aligns the geotagged intervals of `after` with the ones of `before` and flags the regressions.
+    pub fn new(
+        before: &Session,
+        after: &Session,
+        alignment: &Alignment,
+        thresholds: &Thresholds,
+    ) -> Self {
+        let annotations_before = before.annotations().cloned().collect();
+        let annotations_after = after.annotations().cloned().collect();
+        let (before, after) = (intervals(before), intervals(after));
+        let (groups, unmatched) = match alignment {
+            Alignment::Nearest(radius) => by_position(&before, &after, *radius),
+            Alignment::Zones(zones) => by_zone(&before, &after, zones),
+        };
+        let locations = groups
+            .into_iter()
+            .filter_map(|(name, position, before, after)| {
+                let mut location = LocationDiff {
+                    name,
+                    position,
+                    before: Metrics::of(&before)?,
+                    after: Metrics::of(&after),
+                    regressions: Vec::new(),
+                };
+                location.check(thresholds);
+                Some(location)
+            })
+            .collect();
+        Comparison {
+            locations,
+            unmatched,
+            annotations_before,
+            annotations_after,
+        }
+    }
+
+    pub fn regressions(&self) -> usize {
+        self.locations
+            .iter()
+            .filter(|location| !location.regressions.is_empty())
+            .count()
+    }
+
+    /// Points at the locations with the metrics of both sessions, their changes and a `status`
+    /// of `ok`, `regression` or `unmatched`, followed by the annotations of both sessions with the `status`
+    /// `annotation`. Floor plan positions are `x` and `y` with a `floor` property.
+    pub fn to_geojson(&self) -> Value {
+        let mut features: Vec<Value> = self
+            .locations
+            .iter()
+            .map(|location| {
+                let (coordinates, floor) = coordinates(&location.position);
+                json!({
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": coordinates},
+                    "properties": {
+                        "name": location.name,
+                        "floor": floor,
+                        "status": location.status(),
+                        "before": location.before,
+                        "after": location.after,
+                        "throughput_change": location.throughput_change(),
+                        "loss_change": location.loss_change(),
+                        "rtt_change": location.rtt_change(),
+                        "regressions": location.regressions,
+                    }
+                })
+            })
+            .collect();
+        let annotations = self
+            .annotations_before
+            .iter()
+            .map(|annotation| ("before", annotation))
+            .chain(
+                self.annotations_after
+                    .iter()
+                    .map(|annotation| ("after", annotation)),
+            );
+        for (session, annotation) in annotations {
+            if !annotation.position.has_fix() {
+                continue;
+            }
+            let (coordinates, floor) = coordinates(&annotation.position);
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": coordinates},
+                "properties": {
+                    "name": annotation.text,
+                    "floor": floor,
+                    "status": "annotation",
+                    "session": session,
+                    "time": annotation.time,
+                }
+            }));
+        }
+        json!({"type": "FeatureCollection", "features": features})
+    }
+
+    /// Standalone HTML page with a table of the locations, regressions highlighted.
+    pub fn to_html(&self, before: &str, after: &str) -> String {
+        let mut html = String::new();
+        let cell = |value: Option<f64>, precision: usize| match value {
+            Some(value) => format!("<td>{:.*}</td>", precision, value),
+            None => "<td></td>".to_string(),
+        };
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>NetMap comparison</title>\n\
+             <style>\nbody {{ font-family: sans-serif; }}\ntable {{ border-collapse: collapse; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: right; }}\n\
+             td:first-child, td:nth-child(2) {{ text-align: left; }}\n\
+             tr.regression {{ background: #fdd; }}\ntr.unmatched {{ color: #888; }}\n</style>\n</head>\n<body>\n\
+             <h1>{} &rarr; {}</h1>\n<p>{}</p>\n<table>\n\
+             <tr><th>Location</th><th>Position</th><th>Intervals</th>\
+             <th>Throughput (Mbit/s)</th><th></th><th>Change (%)</th>\
+             <th>Loss (%)</th><th></th><th>Change (pp)</th>\
+             <th>RTT p95 (ms)</th><th></th><th>Change (ms)</th><th>Regressions</th></tr>\n",
+            escape(before),
+            escape(after),
+            escape(&self.to_string())
+        );
+        for location in &self.locations {
+            let after = location.after.as_ref();
+            let regressions: Vec<String> =
+                location.regressions.iter().map(|r| r.to_string()).collect();
+            let _ = writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}/{}</td>{}{}{}{}{}{}{}{}{}<td>{}</td></tr>",
+                location.status(),
+                escape(&location.name),
+                escape(&location.position.to_string()),
+                location.before.intervals,
+                after.map_or(0, |after| after.intervals),
+                cell(Some(location.before.throughput), 2),
+                cell(after.map(|after| after.throughput), 2),
+                cell(location.throughput_change(), 1),
+                cell(location.before.loss, 2),
+                cell(after.and_then(|after| after.loss), 2),
+                cell(location.loss_change(), 2),
+                cell(location.before.rtt_p95, 3),
+                cell(after.and_then(|after| after.rtt_p95), 3),
+                cell(location.rtt_change(), 3),
+                regressions.join(", ")
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compared = self.locations.iter().filter(|l| l.after.is_some()).count();
+        write!(
+            f,
+            "{} locations, {} compared, {} regressions, {} intervals of the second session unmatched",
+            self.locations.len(),
+            compared,
+            self.regressions(),
+            self.unmatched
+        )
+    }
+}
+
+/// Location name and position with the intervals of both sessions there.
+type Group<'a> = (
+    String,
+    Position,
+    Vec<&'a IntervalRecord>,
+    Vec<&'a IntervalRecord>,
+);
+
+/// Intervals of `session` that have a position.
+fn intervals(session: &Session) -> Vec<&IntervalRecord> {
+    session
+        .records
+        .iter()
+        .filter_map(|record| match record {
+            Record::Interval(interval) if interval.position.has_fix() => Some(interval),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Groups `before` into locations around the first interval more than `radius` meters from the others,
+/// and adds each interval of `after` to the nearest location within `radius`.
+fn by_position<'a>(
+    before: &[&'a IntervalRecord],
+    after: &[&'a IntervalRecord],
+    radius: f64,
+) -> (Vec<Group<'a>>, usize) {
+    let mut groups: Vec<Group> = Vec::new();
+    let nearest = |groups: &[Group], position: &Position| {
+        groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| (i, group.1.distance(position)))
+            .filter(|(_, distance)| *distance <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    };
+    for interval in before {
+        match nearest(&groups, &interval.position) {
+            Some(i) => groups[i].2.push(interval),
+            None => groups.push((
+                format!("location {}", groups.len() + 1),
+                interval.position.clone(),
+                vec![interval],
+                Vec::new(),
+            )),
+        }
+    }
+    let mut unmatched = 0;
+    for interval in after {
+        match nearest(&groups, &interval.position) {
+            Some(i) => groups[i].3.push(interval),
+            None => unmatched += 1,
+        }
+    }
+    (groups, unmatched)
+}
+
+/// One location per zone that `before` measured in, the intervals of `after` outside of these don't match.
+fn by_zone<'a>(
+    before: &[&'a IntervalRecord],
+    after: &[&'a IntervalRecord],
+    zones: &Zones,
+) -> (Vec<Group<'a>>, usize) {
+    let inside = |intervals: &[&'a IntervalRecord], zone: &Zone| -> Vec<&'a IntervalRecord> {
+        intervals
+            .iter()
+            .copied()
+            .filter(|interval| zone.contains(&interval.position))
+            .collect()
+    };
+    let mut groups = Vec::new();
+    let mut measured = Vec::new();
+    for zone in &zones.zones {
+        let before = inside(before, zone);
+        if before.is_empty() {
+            continue;
+        }
+        let position = middle(before.iter().map(|interval| &interval.position));
+        groups.push((zone.name.clone(), position, before, inside(after, zone)));
+        measured.push(zone);
+    }
+    let unmatched = after
+        .iter()
+        .filter(|interval| {
+            !measured
+                .iter()
+                .any(|zone| zone.contains(&interval.position))
+        })
+        .count();
+    (groups, unmatched)
+}
+
+/// Mean of the coordinates of `positions`, on the floor plan of the first one if it has one.
+fn middle<'a>(positions: impl Iterator<Item = &'a Position>) -> Position {
+    let positions: Vec<&Position> = positions.collect();
+    let Some(first) = positions.first() else {
+        return Position::default();
+    };
+    let count = positions.len() as f64;
+    let mean =
+        |value: fn(&Position) -> f64| positions.iter().map(|p| value(p)).sum::<f64>() / count;
+    let indoor = first.indoor.map(|indoor| IndoorPosition {
+        x: mean(|p| p.indoor.map_or(0.0, |indoor| indoor.x)),
+        y: mean(|p| p.indoor.map_or(0.0, |indoor| indoor.y)),
+        floor: indoor.floor,
+    });
+    Position {
+        lat: mean(|p| p.lat),
+        lon: mean(|p| p.lon),
+        indoor,
+        ..Position::new(first.time.clone(), 0.0, 0.0)
+    }
+}
+
+/// GeoJSON coordinates of `position`, with the floor for a position on a floor plan.
+fn coordinates(position: &Position) -> ([f64; 2], Option<i32>) {
+    match position.indoor {
+        Some(indoor) => ([indoor.x, indoor.y], Some(indoor.floor)),
+        None => ([position.lon, position.lat], None),
+    }
+}
+
+/// `text` with the characters that have a meaning in HTML escaped.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::modes::RunMode;
+    use crate::clients::Protocol;
+    use crate::record::Event;
+    use crate::session::SessionHeader;
+
+    fn session(intervals: &[(f64, f64, f64, u32)]) -> Session {
+        let header = SessionHeader::new(
+            RunMode::Receiver {
+                port: 4321,
+                protocol: Protocol::Udp,
+                interface: None,
+                output_file: None,
+            },
+            None,
+            None,
+        );
+        let records = intervals
+            .iter()
+            .map(|&(lat, throughput, rtt, received)| {
+                Record::Interval(IntervalRecord {
+                    position: Position::new("2023-04-01T12:00:00.000Z".to_string(), lat, 13.0),
+                    throughput,
+                    packets_sent: Some(100),
+                    packets_received: Some(received),
+                    rtt: Some(rtt),
+                    ..IntervalRecord::empty(0.0)
+                })
+            })
+            .collect();
+        Session {
+            header,
+            records,
+            footer: None,
+        }
+    }
+
+    #[test]
+    fn flags_regressions_at_nearby_positions() {
+        // two locations about 1 km apart, 0.00002° is about 2 m
+        let before = session(&[
+            (52.0, 10.0, 2.0, 100),
+            (52.00002, 10.0, 2.0, 100),
+            (52.01, 10.0, 2.0, 100),
+            (52.01002, 10.0, 2.0, 100),
+        ]);
+        let mut after = session(&[
+            (52.00001, 10.0, 2.5, 100),
+            (52.00003, 10.0, 2.0, 100),
+            (52.01001, 5.0, 20.0, 90),
+            (52.01001, 5.0, 20.0, 90),
+            (53.0, 10.0, 2.0, 100),
+        ]);
+        after
+            .records
+            .push(Record::Event(Event::Annotation(Annotation {
+                time: 3.0,
+                text: "door closed".to_string(),
+                position: Position::new("2023-04-01T12:00:03.000Z".to_string(), 52.01, 13.0),
+            })));
+        let thresholds = Thresholds {
+            throughput_drop: 10.0,
+            loss_increase: 1.0,
+            rtt_increase: 5.0,
+            min_intervals: 2,
+        };
+        let comparison = Comparison::new(&before, &after, &Alignment::Nearest(10.0), &thresholds);
+        assert_eq!(comparison.locations.len(), 2);
+        assert_eq!(comparison.unmatched, 1);
+        assert!(comparison.locations[0].regressions.is_empty());
+        assert_eq!(comparison.locations[0].throughput_change(), Some(0.0));
+        assert_eq!(
+            comparison.locations[1].regressions,
+            [Regression::Throughput, Regression::Loss, Regression::Rtt]
+        );
+        assert_eq!(comparison.regressions(), 1);
+
+        let geojson = comparison.to_geojson();
+        assert_eq!(geojson["features"][1]["properties"]["status"], "regression");
+        assert_eq!(geojson["features"][1]["geometry"]["coordinates"][0], 13.0);
+        assert_eq!(geojson["features"][2]["properties"]["name"], "door closed");
+        assert_eq!(geojson["features"][2]["properties"]["session"], "after");
+    }
+}
@@ -0,0 +1,503 @@
+//! Live terminal dashboard of a run, drawn from the records as they are written.
+
+use crate::annotation::Annotator;
+use crate::gps::Position;
+use crate::record::{Event, IntervalRecord, Record};
+use crate::shutdown::Shutdown;
+use crate::stats::Ewma;
+use ratatui::crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::{self, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// Intervals that the sparklines go back.
+const HISTORY: usize = 300;
+/// How often the dashboard is redrawn and checks for key presses.
+const REFRESH: Duration = Duration::from_millis(250);
+/// Loss of the last interval in percent above which it is flagged.
+const LOSS_WARNING: f64 = 5.0;
+/// Estimated error of the GPS fix in meters above which it is flagged.
+const ACCURACY_WARNING: f64 = 25.0;
+/// Time without GPS fix after which the position is flagged as stale, gpsd reports once per second.
+const FIX_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time without intervals after which the traffic is flagged as stalled.
+const INTERVAL_TIMEOUT: Duration = Duration::from_secs(3);
+/// Number of intervals after which an interval counts half in the smoothed throughput.
+const THROUGHPUT_HALF_LIFE: f64 = 5.0;
+
+/// State of the dashboard, updated with every record.
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    title: String,
+    started: Instant,
+    throughput: VecDeque<f64>,
+    rtt: VecDeque<f64>,
+    loss: VecDeque<f64>,
+    smoothed: Ewma,
+    last_interval: Option<(IntervalRecord, Instant)>,
+    position: Position,
+    last_fix: Option<Instant>,
+    intervals: u64,
+    bytes: u64,
+    packets_sent: u64,
+    packets_received: u64,
+    errors: u64,
+    roams: u64,
+    gps_fixes: u64,
+    scans: u64,
+    annotations: u64,
+    /// last note added during the run
+    last_note: Option<String>,
+    annotator: Option<Annotator>,
+    /// note being typed, after n was pressed
+    note: Option<String>,
+    /// address of the web dashboard of the run
+    web: Option<String>,
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        Dashboard::new()
+    }
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Dashboard {
+            title: "NetMap".to_string(),
+            started: Instant::now(),
+            throughput: VecDeque::with_capacity(HISTORY),
+            rtt: VecDeque::with_capacity(HISTORY),
+            loss: VecDeque::with_capacity(HISTORY),
+            smoothed: Ewma::with_half_life(THROUGHPUT_HALF_LIFE),
+            last_interval: None,
+            position: Position::default(),
+            last_fix: None,
+            intervals: 0,
+            bytes: 0,
+            packets_sent: 0,
+            packets_received: 0,
+            errors: 0,
+            roams: 0,
+            gps_fixes: 0,
+            scans: 0,
+            annotations: 0,
+            last_note: None,
+            annotator: None,
+            note: None,
+            web: None,
+        }
+    }
+
+    /// Shows where the web dashboard of the run is served, with its token, as the terminal is taken.
+    pub fn web(mut self, url: String) -> Self {
+        self.web = Some(url);
+        self
+    }
+
+    /// Lets the surveyor type notes after pressing n, which `annotator` writes to the session.
+    pub fn annotator(mut self, annotator: Annotator) -> Self {
+        self.annotator = Some(annotator);
+        self
+    }
+
+    pub fn update(&mut self, record: &Record) {
+        let now = Instant::now();
+        match record {
+            Record::Header(header) => self.title = format!("NetMap {}", header),
+            Record::Interval(interval) => {
+                for (history, value) in [
+                    (&mut self.throughput, Some(interval.throughput)),
+                    (&mut self.rtt, interval.rtt),
+                    (&mut self.loss, interval.loss),
+                ] {
+                    if history.len() == HISTORY {
+                        history.pop_front();
+                    }
+                    history.push_back(value.unwrap_or_default());
+                }
+                self.smoothed.add(interval.throughput);
+                self.intervals += 1;
+                self.bytes += interval.bytes;
+                self.packets_sent += interval.packets_sent.unwrap_or_default() as u64;
+                self.packets_received += interval.packets_received.unwrap_or_default() as u64;
+                self.errors += interval.errors.map_or(0, |errors| errors.total()) as u64;
+                self.last_interval = Some((interval.clone(), now));
+            }
+            Record::GpsFix(position) => {
+                self.position = position.clone();
+                self.last_fix = Some(now);
+                self.gps_fixes += 1;
+            }
+            Record::Event(Event::Roam(_)) => self.roams += 1,
+            Record::Event(Event::Annotation(annotation)) => {
+                self.annotations += 1;
+                self.last_note = Some(format!("{:.1}s {}", annotation.time, annotation.text));
+            }
+            Record::Scan(_) => self.scans += 1,
+            _ => {}
+        }
+    }
+
+    /// Problems the surveyor should notice at `now`.
+    pub fn warnings(&self, now: Instant) -> Vec<String> {
+        let mut warnings = Vec::new();
+        match self.last_fix {
+            None => warnings.push("no GPS fix".to_string()),
+            // Positions on a floor plan are only entered when the surveyor moves on.
+            Some(fix) if self.position.indoor.is_none() && now - fix > FIX_TIMEOUT => {
+                warnings.push(format!("no GPS fix for {:.0}s", (now - fix).as_secs_f64()))
+            }
+            Some(_) => {}
+        }
+        if let Some(accuracy) = self.position.accuracy.filter(|&a| a > ACCURACY_WARNING) {
+            warnings.push(format!("GPS accuracy ±{:.0} m", accuracy));
+        }
+        match &self.last_interval {
+            None if now - self.started > INTERVAL_TIMEOUT => {
+                warnings.push("no intervals yet".to_string())
+            }
+            Some((_, at)) if now - *at > INTERVAL_TIMEOUT => warnings.push(format!(
+                "no intervals for {:.0}s",
+                (now - *at).as_secs_f64()
+            )),
+            _ => {}
+        }
+        if let Some(loss) = self
+            .last_interval
+            .as_ref()
+            .and_then(|(interval, _)| interval.loss)
+            .filter(|&loss| loss > LOSS_WARNING)
+        {
+            warnings.push(format!("loss {:.1}% > {}%", loss, LOSS_WARNING));
+        }
+        warnings
+    }
+
+    /// Shows the dashboard until `shutdown` is triggered, which pressing q or Ctrl-C does.
+    /// Blocks, so it is meant to run on its own thread.
+    pub fn run(mut self, records: mpsc::Receiver<Record>, shutdown: &Shutdown) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.show(&mut terminal, records, shutdown);
+        ratatui::restore();
+        result
+    }
+
+    fn show(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        records: mpsc::Receiver<Record>,
+        shutdown: &Shutdown,
+    ) -> io::Result<()> {
+        while !shutdown.is_triggered() {
+            loop {
+                match records.try_recv() {
+                    Ok(record) => self.update(&record),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(REFRESH)? {
+                continue;
+            }
+            // Raw mode turns Ctrl-C into a key instead of a signal.
+            if let event::Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    shutdown.trigger();
+                } else if self.note.is_some() {
+                    self.edit_note(key.code);
+                } else if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    shutdown.trigger();
+                } else if key.code == KeyCode::Char('n') && self.annotator.is_some() {
+                    self.note = Some(String::new());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Types `key` into the note, which Enter writes and Esc discards.
+    fn edit_note(&mut self, key: KeyCode) {
+        let Some(note) = &mut self.note else {
+            return;
+        };
+        match key {
+            KeyCode::Char(c) => note.push(c),
+            KeyCode::Backspace => {
+                note.pop();
+            }
+            KeyCode::Esc => self.note = None,
+            KeyCode::Enter => {
+                if let (Some(annotator), Some(note)) = (&self.annotator, self.note.take()) {
+                    // The note shows up through the records once it is written.
+                    if let Err(e) = annotator.annotate(&note) {
+                        self.last_note = Some(format!("couldn't write note: {}", e));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [charts, details, warnings] = Layout::vertical([
+            Constraint::Min(9),
+            Constraint::Length(7),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+        let elapsed = self.started.elapsed().as_secs();
+        let title = format!(
+            " {} | {:02}:{:02}:{:02} | q to stop{} ",
+            self.title,
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60,
+            if self.annotator.is_some() {
+                ", n for a note"
+            } else {
+                ""
+            }
+        );
+        frame.render_widget(Block::new().borders(Borders::TOP).title(title), charts);
+
+        let last = self.last_interval.as_ref().map(|(interval, _)| interval);
+        let [throughput, rtt, loss] = Layout::vertical([Constraint::Fill(1); 3]).areas(Rect {
+            y: charts.y + 1,
+            height: charts.height.saturating_sub(1),
+            ..charts
+        });
+        let smoothed = self.smoothed.value().unwrap_or_default();
+        self.sparkline(
+            frame,
+            throughput,
+            format!(
+                "Throughput {:.2} Mbit/s (smoothed {:.2})",
+                last.map_or(0.0, |interval| interval.throughput),
+                smoothed
+            ),
+            &self.throughput,
+            Color::Green,
+        );
+        let rtt_title = match last.and_then(|interval| interval.rtt) {
+            Some(rtt) => format!(
+                "RTT {:.3} ms ±{:.3}",
+                rtt,
+                last.and_then(|interval| interval.rtt_stddev)
+                    .unwrap_or_default()
+            ),
+            None => "RTT".to_string(),
+        };
+        self.sparkline(frame, rtt, rtt_title, &self.rtt, Color::Yellow);
+        let loss_title = match last.and_then(|interval| interval.loss) {
+            Some(loss) => format!("Loss {:.2}%", loss),
+            None => "Loss".to_string(),
+        };
+        self.sparkline(frame, loss, loss_title, &self.loss, Color::Red);
+
+        let [position, link, session] = Layout::horizontal([Constraint::Fill(1); 3]).areas(details);
+        frame.render_widget(
+            Paragraph::new(self.position_lines()).block(Block::bordered().title(" Position ")),
+            position,
+        );
+        frame.render_widget(
+            Paragraph::new(link_lines(last)).block(Block::bordered().title(" Link ")),
+            link,
+        );
+        frame.render_widget(
+            Paragraph::new(self.session_lines()).block(Block::bordered().title(" Session ")),
+            session,
+        );
+
+        if let Some(note) = &self.note {
+            frame.render_widget(
+                Paragraph::new(format!("{}_", note))
+                    .block(Block::bordered().title(" Note | Enter to add, Esc to cancel ")),
+                warnings,
+            );
+            return;
+        }
+        let warnings_text = self.warnings(Instant::now()).join(" | ");
+        let style = if warnings_text.is_empty() {
+            Style::new()
+        } else {
+            Style::new().fg(Color::Red)
+        };
+        let title = match &self.web {
+            Some(url) => format!(" Warnings | web dashboard on {} ", url),
+            None => " Warnings ".to_string(),
+        };
+        frame.render_widget(
+            Paragraph::new(warnings_text)
+                .style(style)
+                .block(Block::bordered().title(title)),
+            warnings,
+        );
+    }
+
+    fn sparkline(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        title: String,
+        history: &VecDeque<f64>,
+        color: Color,
+    ) {
+        // The newest values that fit, in thousandths as the sparkline takes integers.
+        let width = area.width.saturating_sub(2) as usize;
+        let data: Vec<u64> = history
+            .iter()
+            .skip(history.len().saturating_sub(width))
+            .map(|value| (value * 1000.0).round() as u64)
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&data)
+                .style(Style::new().fg(color)),
+            area,
+        );
+    }
+
+    fn position_lines(&self) -> Vec<Line<'static>> {
+        let position = &self.position;
+        if self.last_fix.is_none() {
+            return vec![Line::from("waiting for a fix")];
+        }
+        let mut lines = vec![match position.indoor {
+            Some(indoor) => Line::from(format!(
+                "{:.2} m, {:.2} m floor {}",
+                indoor.x, indoor.y, indoor.floor
+            )),
+            None => Line::from(format!("{:.6}, {:.6}", position.lat, position.lon)),
+        }];
+        if let Some(accuracy) = position.accuracy {
+            lines.push(Line::from(format!("accuracy ±{:.1} m", accuracy)));
+        }
+        if let (Some(speed), Some(track)) = (position.speed, position.track) {
+            lines.push(Line::from(format!(
+                "{:.1} m/s towards {:.0}°",
+                speed, track
+            )));
+        }
+        if let Some(fix) = self.last_fix {
+            lines.push(Line::from(format!(
+                "fix {:.1}s ago ({})",
+                fix.elapsed().as_secs_f64(),
+                position.time
+            )));
+        }
+        lines
+    }
+
+    fn session_lines(&self) -> Vec<Line<'static>> {
+        let loss = crate::stats::loss(self.packets_sent, self.packets_received);
+        vec![
+            Line::from(format!(
+                "{} intervals, {:.2} Mbytes",
+                self.intervals,
+                self.bytes as f64 / 1e6
+            )),
+            Line::from(format!(
+                "{}/{} packets ({:.2}% loss)",
+                self.packets_received,
+                self.packets_sent,
+                loss.unwrap_or_default()
+            )),
+            Line::from(format!("{} errors, {} roams", self.errors, self.roams)),
+            Line::from(format!(
+                "{} GPS fixes, {} scans, {} notes",
+                self.gps_fixes, self.scans, self.annotations
+            )),
+            Line::from(match &self.last_note {
+                Some(note) => format!("last note: {}", note),
+                None => "no notes".to_string(),
+            }),
+        ]
+    }
+}
+
+fn link_lines(interval: Option<&IntervalRecord>) -> Vec<Line<'static>> {
+    let Some(wireless) = interval.and_then(|interval| interval.wireless.as_ref()) else {
+        return vec![Line::from("no wireless statistics")];
+    };
+    let text = |value: Option<&String>| value.cloned().unwrap_or_else(|| "?".to_string());
+    let number = |value: Option<String>| value.unwrap_or_else(|| "?".to_string());
+    vec![
+        Line::from(format!(
+            "{} {}",
+            text(wireless.ssid.as_ref()),
+            text(wireless.bssid.as_ref())
+        )),
+        Line::from(format!(
+            "{} MHz, {} MHz wide",
+            number(wireless.frequency.map(|f| f.to_string())),
+            number(wireless.channel_width.map(|w| w.to_string()))
+        )),
+        Line::from(format!(
+            "signal {} dBm, noise {} dBm",
+            number(wireless.signal.map(|s| s.to_string())),
+            number(wireless.noise.map(|n| n.to_string()))
+        )),
+        Line::from(format!(
+            "tx {} Mbit/s MCS {}, {} retries",
+            number(wireless.tx_bitrate.map(|b| format!("{:.1}", b))),
+            number(wireless.tx_mcs.map(|m| m.to_string())),
+            number(wireless.tx_retries.map(|r| r.to_string()))
+        )),
+        Line::from(format!(
+            "rx {} Mbit/s MCS {}",
+            number(wireless.rx_bitrate.map(|b| format!("{:.1}", b))),
+            number(wireless.rx_mcs.map(|m| m.to_string()))
+        )),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_about_fix_and_loss() {
+        let mut dashboard = Dashboard::new();
+        let start = Instant::now();
+        assert_eq!(dashboard.warnings(start), ["no GPS fix"]);
+
+        dashboard.update(&Record::GpsFix(Position {
+            accuracy: Some(40.0),
+            ..Position::new("2023-04-01T12:00:00.000Z".to_string(), 52.0, 13.0)
+        }));
+        dashboard.update(&Record::Interval(IntervalRecord {
+            bytes: 125_000,
+            throughput: 1.0,
+            packets_sent: Some(100),
+            packets_received: Some(90),
+            loss: Some(10.0),
+            rtt: Some(2.0),
+            rtt_stddev: Some(0.5),
+            ..IntervalRecord::empty(0.0)
+        }));
+        let now = Instant::now();
+        assert_eq!(
+            dashboard.warnings(now),
+            ["GPS accuracy ±40 m", "loss 10.0% > 5%"]
+        );
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            dashboard.warnings(later)[..2],
+            ["no GPS fix for 10s", "GPS accuracy ±40 m"]
+        );
+        assert_eq!(dashboard.throughput, [1.0]);
+        assert_eq!(dashboard.packets_received, 90);
+    }
+}
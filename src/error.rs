@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+/// Errors that stop a run, or keep it from starting.
+#[derive(Debug)]
+pub enum NetMapError {
+    /// The socket couldn't be bound to the local address.
+    Bind {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    /// The socket couldn't be restricted to the chosen interface.
+    BindDevice {
+        interface: String,
+        source: io::Error,
+    },
+    /// The TCP connection to the Receiver couldn't be established or broke.
+    Connect {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    /// The chosen interface has no address in the family of the target.
+    NoSourceAddress {
+        interface: String,
+        target: SocketAddr,
+    },
+    /// The packet size doesn't leave room for the headers or exceeds the receive buffer.
+    InvalidPacketSize {
+        packet_size: usize,
+        min: usize,
+        max: usize,
+    },
+    /// The DSCP doesn't fit into the six bits of the field.
+    InvalidDscp(u8),
+    /// gpsd couldn't be started or reached.
+    Gps(String),
+    /// The config file couldn't be read or doesn't fit the command line.
+    Config(String),
+    /// The zone polygons couldn't be read.
+    Zones(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for NetMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetMapError::Bind { address, source } => {
+                write!(f, "couldn't bind to {}: {}", address, source)
+            }
+            NetMapError::BindDevice { interface, source } => write!(
+                f,
+                "couldn't bind to {}, which requires CAP_NET_RAW: {}",
+                interface, source
+            ),
+            NetMapError::Connect { address, source } => {
+                write!(f, "connection to {} failed: {}", address, source)
+            }
+            NetMapError::NoSourceAddress { interface, target } => {
+                write!(f, "{} has no address to reach {}", interface, target)
+            }
+            NetMapError::InvalidPacketSize {
+                packet_size,
+                min,
+                max,
+            } => write!(
+                f,
+                "packet size {} is outside of {} to {} bytes",
+                packet_size, min, max
+            ),
+            NetMapError::InvalidDscp(dscp) => write!(f, "DSCP {} is outside of 0 to 63", dscp),
+            NetMapError::Gps(message) => write!(f, "GPS failed: {}", message),
+            NetMapError::Config(message) => write!(f, "invalid config: {}", message),
+            NetMapError::Zones(message) => write!(f, "invalid zones: {}", message),
+            NetMapError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for NetMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetMapError::Bind { source, .. }
+            | NetMapError::BindDevice { source, .. }
+            | NetMapError::Connect { source, .. } => Some(source),
+            NetMapError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NetMapError {
+    fn from(e: io::Error) -> Self {
+        NetMapError::Io(e)
+    }
+}
+
+/// Socket errors that don't stop a run, counted per interval.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ErrorCounts {
+    /// ECONNREFUSED, an ICMP port unreachable because the Receiver isn't running (yet)
+    pub connection_refused: u32,
+    /// ENOBUFS, the transmit queue of the driver was full
+    pub no_buffer_space: u32,
+    /// EAGAIN, the socket buffer was full
+    pub would_block: u32,
+    /// ENETUNREACH or EHOSTUNREACH, e.g. while the link is down during a roam
+    pub unreachable: u32,
+    /// anything else, e.g. EMSGSIZE for packets larger than the path MTU
+    pub other: u32,
+}
+
+impl ErrorCounts {
+    pub fn count(&mut self, error: &io::Error) {
+        match error.raw_os_error() {
+            Some(libc::ECONNREFUSED) => self.connection_refused += 1,
+            Some(libc::ENOBUFS) => self.no_buffer_space += 1,
+            Some(libc::EAGAIN) => self.would_block += 1,
+            Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH) => self.unreachable += 1,
+            _ => self.other += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.connection_refused
+            + self.no_buffer_space
+            + self.would_block
+            + self.unreachable
+            + self.other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_errors_by_errno() {
+        let mut errors = ErrorCounts::default();
+        for errno in [
+            libc::ECONNREFUSED,
+            libc::ENOBUFS,
+            libc::ENOBUFS,
+            libc::EAGAIN,
+            libc::ENETUNREACH,
+            libc::EHOSTUNREACH,
+            libc::EMSGSIZE,
+        ] {
+            errors.count(&io::Error::from_raw_os_error(errno));
+        }
+        assert_eq!(
+            errors,
+            ErrorCounts {
+                connection_refused: 1,
+                no_buffer_space: 2,
+                would_block: 1,
+                unreachable: 2,
+                other: 1,
+            }
+        );
+        assert_eq!(errors.total(), 7);
+    }
+
+    #[test]
+    fn errors_without_errno_count_as_other() {
+        let mut errors = ErrorCounts::default();
+        assert_eq!(errors.total(), 0);
+        // Only the errno counts, not the kind that an error was made with.
+        errors.count(&io::Error::from(io::ErrorKind::WouldBlock));
+        errors.count(&io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+        assert_eq!(
+            errors,
+            ErrorCounts {
+                other: 2,
+                ..ErrorCounts::default()
+            }
+        );
+        assert_eq!(errors.total(), 2);
+    }
+}
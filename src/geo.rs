@@ -0,0 +1,562 @@
+//! Distances, bearings and projections of WGS84 coordinates.
+//!
+//! [`Position`] only carries latitude and longitude in degrees. This module measures between them on the sphere
+//! (haversine) or the ellipsoid (Vincenty), and converts them into meters around a reference point,
+//! either in a local east-north-up frame or in UTM, for grids and distances over a whole survey.
+//! All functions use the coordinates only and ignore floor plan positions.
+
+use crate::gps::Position;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Mean earth radius in meters, used on the sphere.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+/// Semi-major axis of the WGS84 ellipsoid in meters.
+pub const WGS84_A: f64 = 6_378_137.0;
+/// Flattening of the WGS84 ellipsoid.
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// Squared first eccentricity of the WGS84 ellipsoid.
+const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+/// Iterations after which Vincenty's formula gives up, which only happens for nearly antipodal points.
+const VINCENTY_ITERATIONS: usize = 200;
+/// Scale factor on the central meridian of a UTM zone.
+const UTM_K0: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+/// False northing in the southern hemisphere.
+const UTM_FALSE_NORTHING: f64 = 10_000_000.0;
+
+/// Great-circle distance between `a` and `b` in meters on a sphere with the mean earth radius.
+/// Within 0.6% of the distance on the ellipsoid.
+pub fn haversine(a: &Position, b: &Position) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.lon - a.lon).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+/// Distance between `a` and `b` in meters on the WGS84 ellipsoid with Vincenty's inverse formula,
+/// accurate to below a millimeter. `None` if it doesn't converge, which only happens for nearly antipodal points.
+pub fn vincenty(a: &Position, b: &Position) -> Option<f64> {
+    let semi_minor = (1.0 - WGS84_F) * WGS84_A;
+    let l = (b.lon - a.lon).to_radians();
+    // reduced latitudes
+    let u1 = ((1.0 - WGS84_F) * a.lat.to_radians().tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * b.lat.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = (cos_u2 * sin_lambda).hypot(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+        if sin_sigma == 0.0 {
+            return Some(0.0);
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+        // zero on the equator
+        let cos_2sigma_m = if cos2_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha
+        };
+        let c = WGS84_F / 16.0 * cos2_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos2_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+        if (lambda - previous).abs() < 1e-12 {
+            let u2 = cos2_alpha * (WGS84_A.powi(2) - semi_minor.powi(2)) / semi_minor.powi(2);
+            let big_a = 1.0 + u2 / 16384.0 * (4096.0 + u2 * (-768.0 + u2 * (320.0 - 175.0 * u2)));
+            let big_b = u2 / 1024.0 * (256.0 + u2 * (-128.0 + u2 * (74.0 - 47.0 * u2)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            return Some(semi_minor * big_a * (sigma - delta_sigma));
+        }
+    }
+    None
+}
+
+/// Initial bearing of the great circle from `a` to `b` in degrees clockwise from true north, from 0 to 360.
+pub fn bearing(a: &Position, b: &Position) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlon = (b.lon - a.lon).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Latitude and longitude `distance` meters from `start` along the great circle with the initial `bearing` in degrees.
+pub fn destination(start: &Position, bearing: f64, distance: f64) -> (f64, f64) {
+    let lat1 = start.lat.to_radians();
+    let angle = distance / EARTH_RADIUS;
+    let bearing = bearing.to_radians();
+    let lat2 = (lat1.sin() * angle.cos() + lat1.cos() * angle.sin() * bearing.cos()).asin();
+    let dlon =
+        (bearing.sin() * angle.sin() * lat1.cos()).atan2(angle.cos() - lat1.sin() * lat2.sin());
+    (
+        lat2.to_degrees(),
+        normalize_lon(start.lon + dlon.to_degrees()),
+    )
+}
+
+/// Longitude in degrees from -180 to 180.
+fn normalize_lon(lon: f64) -> f64 {
+    (lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Earth-centered, earth-fixed coordinates in meters of a point `height` meters above the ellipsoid.
+fn to_ecef(lat: f64, lon: f64, height: f64) -> [f64; 3] {
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+    let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+    [
+        (n + height) * cos_lat * cos_lon,
+        (n + height) * cos_lat * sin_lon,
+        (n * (1.0 - WGS84_E2) + height) * sin_lat,
+    ]
+}
+
+/// Latitude, longitude and height above the ellipsoid of earth-centered, earth-fixed coordinates.
+fn from_ecef([x, y, z]: [f64; 3]) -> (f64, f64, f64) {
+    let p = x.hypot(y);
+    let mut lat = z.atan2(p * (1.0 - WGS84_E2));
+    for _ in 0..20 {
+        let sin_lat = lat.sin();
+        let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+        let next = (z + WGS84_E2 * n * sin_lat).atan2(p);
+        let done = (next - lat).abs() < 1e-15;
+        lat = next;
+        if done {
+            break;
+        }
+    }
+    let (sin_lat, cos_lat) = lat.sin_cos();
+    let height = p * cos_lat + z * sin_lat - WGS84_A * (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+    (lat.to_degrees(), y.atan2(x).to_degrees(), height)
+}
+
+/// Offset in meters from the origin of a [`LocalFrame`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Enu {
+    pub east: f64,
+    pub north: f64,
+    /// negative away from the origin, where the ellipsoid curves below its tangent plane
+    pub up: f64,
+}
+
+/// East-north-up frame tangent to the ellipsoid at a reference point, e.g. the start of a survey.
+/// Distances in the frame are straight lines, which stay within a centimeter of the ones
+/// along the ground for the few kilometers of a survey.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalFrame {
+    origin: [f64; 3],
+    sin_lat: f64,
+    cos_lat: f64,
+    sin_lon: f64,
+    cos_lon: f64,
+}
+
+impl LocalFrame {
+    pub fn new(reference: &Position) -> Self {
+        let (sin_lat, cos_lat) = reference.lat.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = reference.lon.to_radians().sin_cos();
+        LocalFrame {
+            origin: to_ecef(reference.lat, reference.lon, 0.0),
+            sin_lat,
+            cos_lat,
+            sin_lon,
+            cos_lon,
+        }
+    }
+
+    /// Offset of `position`, on the ellipsoid, from the reference point.
+    pub fn to_enu(&self, position: &Position) -> Enu {
+        let point = to_ecef(position.lat, position.lon, 0.0);
+        let [dx, dy, dz] = [
+            point[0] - self.origin[0],
+            point[1] - self.origin[1],
+            point[2] - self.origin[2],
+        ];
+        Enu {
+            east: -self.sin_lon * dx + self.cos_lon * dy,
+            north: -self.sin_lat * self.cos_lon * dx - self.sin_lat * self.sin_lon * dy
+                + self.cos_lat * dz,
+            up: self.cos_lat * self.cos_lon * dx
+                + self.cos_lat * self.sin_lon * dy
+                + self.sin_lat * dz,
+        }
+    }
+
+    /// Latitude and longitude of the point at `enu`, projected onto the ellipsoid.
+    pub fn to_lat_lon(&self, enu: Enu) -> (f64, f64) {
+        let Enu { east, north, up } = enu;
+        let point = [
+            self.origin[0] - self.sin_lon * east - self.sin_lat * self.cos_lon * north
+                + self.cos_lat * self.cos_lon * up,
+            self.origin[1] + self.cos_lon * east - self.sin_lat * self.sin_lon * north
+                + self.cos_lat * self.sin_lon * up,
+            self.origin[2] + self.cos_lat * north + self.sin_lat * up,
+        ];
+        let (lat, lon, _) = from_ecef(point);
+        (lat, lon)
+    }
+}
+
+/// Coefficients of Krüger's series for the transverse Mercator projection, to the fourth order of the third flattening.
+struct Kruger {
+    /// radius of the rectifying sphere in meters
+    radius: f64,
+    alpha: [f64; 4],
+    beta: [f64; 4],
+    delta: [f64; 4],
+}
+
+impl Kruger {
+    fn wgs84() -> Self {
+        let n = WGS84_F / (2.0 - WGS84_F);
+        let (n2, n3, n4) = (n * n, n.powi(3), n.powi(4));
+        Kruger {
+            radius: WGS84_A / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0),
+            alpha: [
+                n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0,
+                13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0,
+                61.0 * n3 / 240.0 - 103.0 * n4 / 140.0,
+                49561.0 * n4 / 161280.0,
+            ],
+            beta: [
+                n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0 - n4 / 360.0,
+                n2 / 48.0 + n3 / 15.0 - 437.0 * n4 / 1440.0,
+                17.0 * n3 / 480.0 - 37.0 * n4 / 840.0,
+                4397.0 * n4 / 161280.0,
+            ],
+            delta: [
+                2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3 + 116.0 * n4 / 45.0,
+                7.0 * n2 / 3.0 - 8.0 * n3 / 5.0 - 227.0 * n4 / 45.0,
+                56.0 * n3 / 15.0 - 136.0 * n4 / 35.0,
+                4279.0 * n4 / 630.0,
+            ],
+        }
+    }
+}
+
+/// Universal Transverse Mercator coordinates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Utm {
+    /// from 1 to 60, each 6° of longitude wide
+    pub zone: u8,
+    /// whether the northing is from the equator, else from 10000 km south of it
+    pub north: bool,
+    pub easting: f64,
+    pub northing: f64,
+}
+
+impl Utm {
+    /// Zone that `lon` lies in, without the exceptions around Norway and Svalbard.
+    pub fn zone_of(lon: f64) -> u8 {
+        ((normalize_lon(lon) + 180.0) / 6.0)
+            .floor()
+            .clamp(0.0, 59.0) as u8
+            + 1
+    }
+
+    /// Coordinates of `position` in the zone it lies in.
+    pub fn from_position(position: &Position) -> Self {
+        Utm::in_zone(position, Utm::zone_of(position.lon), position.lat >= 0.0)
+    }
+
+    /// Coordinates of `position` in a given zone and hemisphere, e.g. the ones of a reference point,
+    /// so that all positions of a survey share one grid even across a zone boundary.
+    pub fn in_zone(position: &Position, zone: u8, north: bool) -> Self {
+        let k = Kruger::wgs84();
+        let e = WGS84_E2.sqrt();
+        let lat = position.lat.to_radians();
+        let dlon = normalize_lon(position.lon - central_meridian(zone)).to_radians();
+        // conformal latitude
+        let t = (lat.sin().atanh() - e * (e * lat.sin()).atanh()).sinh();
+        let xi = t.atan2(dlon.cos());
+        let eta = (dlon.sin() / (1.0 + t * t).sqrt()).atanh();
+        let (mut x, mut y) = (eta, xi);
+        for (j, alpha) in k.alpha.iter().enumerate() {
+            let j = 2.0 * (j + 1) as f64;
+            x += alpha * (j * xi).cos() * (j * eta).sinh();
+            y += alpha * (j * xi).sin() * (j * eta).cosh();
+        }
+        Utm {
+            zone,
+            north,
+            easting: UTM_FALSE_EASTING + UTM_K0 * k.radius * x,
+            northing: false_northing(north) + UTM_K0 * k.radius * y,
+        }
+    }
+
+    /// Latitude and longitude of the coordinates.
+    pub fn to_lat_lon(&self) -> (f64, f64) {
+        let k = Kruger::wgs84();
+        let xi = (self.northing - false_northing(self.north)) / (UTM_K0 * k.radius);
+        let eta = (self.easting - UTM_FALSE_EASTING) / (UTM_K0 * k.radius);
+        let (mut xi_prime, mut eta_prime) = (xi, eta);
+        for (j, beta) in k.beta.iter().enumerate() {
+            let j = 2.0 * (j + 1) as f64;
+            xi_prime -= beta * (j * xi).sin() * (j * eta).cosh();
+            eta_prime -= beta * (j * xi).cos() * (j * eta).sinh();
+        }
+        let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+        let mut lat = chi;
+        for (j, delta) in k.delta.iter().enumerate() {
+            lat += delta * (2.0 * (j + 1) as f64 * chi).sin();
+        }
+        let dlon = eta_prime.sinh().atan2(xi_prime.cos());
+        (
+            lat.to_degrees(),
+            normalize_lon(central_meridian(self.zone) + dlon.to_degrees()),
+        )
+    }
+}
+
+fn central_meridian(zone: u8) -> f64 {
+    f64::from(zone) * 6.0 - 183.0
+}
+
+fn false_northing(north: bool) -> f64 {
+    if north {
+        0.0
+    } else {
+        UTM_FALSE_NORTHING
+    }
+}
+
+impl fmt::Display for Utm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{} {:.1} {:.1}",
+            self.zone,
+            if self.north { 'N' } else { 'S' },
+            self.easting,
+            self.northing
+        )
+    }
+}
+
+/// Area between two parallels and two meridians, e.g. the one covered by a survey.
+/// Doesn't cross the antimeridian.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl BoundingBox {
+    /// Smallest box around the outdoor fixes among `positions`, `None` without any.
+    pub fn from_positions<'a>(positions: impl IntoIterator<Item = &'a Position>) -> Option<Self> {
+        let mut positions = positions
+            .into_iter()
+            .filter(|position| position.has_fix() && position.indoor.is_none());
+        let first = positions.next()?;
+        let mut bounds = BoundingBox {
+            south: first.lat,
+            west: first.lon,
+            north: first.lat,
+            east: first.lon,
+        };
+        for position in positions {
+            bounds.extend(position);
+        }
+        Some(bounds)
+    }
+
+    /// Grows the box to include `position`.
+    pub fn extend(&mut self, position: &Position) {
+        self.south = self.south.min(position.lat);
+        self.west = self.west.min(position.lon);
+        self.north = self.north.max(position.lat);
+        self.east = self.east.max(position.lon);
+    }
+
+    pub fn contains(&self, position: &Position) -> bool {
+        (self.south..=self.north).contains(&position.lat)
+            && (self.west..=self.east).contains(&position.lon)
+    }
+
+    /// Latitude and longitude of the middle of the box.
+    pub fn center(&self) -> (f64, f64) {
+        (
+            (self.south + self.north) / 2.0,
+            (self.west + self.east) / 2.0,
+        )
+    }
+
+    /// Box with a margin of `meters` on every side, e.g. so that markers at the edges stay visible on a map.
+    pub fn pad(&self, meters: f64) -> Self {
+        let dlat = (meters / EARTH_RADIUS).to_degrees();
+        // The box is widest in meters at the parallel closest to the equator.
+        let widest = if self.south > 0.0 {
+            self.south
+        } else if self.north < 0.0 {
+            self.north
+        } else {
+            0.0
+        };
+        let dlon = (meters / (EARTH_RADIUS * widest.to_radians().cos().max(1e-9))).to_degrees();
+        BoundingBox {
+            south: (self.south - dlat).max(-90.0),
+            west: (self.west - dlon).max(-180.0),
+            north: (self.north + dlat).min(90.0),
+            east: (self.east + dlon).min(180.0),
+        }
+    }
+
+    /// Width and height of the box in meters, measured along its middle.
+    pub fn size(&self) -> (f64, f64) {
+        let (lat, lon) = self.center();
+        let at = |lat, lon| Position::new(String::new(), lat, lon);
+        (
+            haversine(&at(lat, self.west), &at(lat, self.east)),
+            haversine(&at(self.south, lon), &at(self.north, lon)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::f64::consts::PI;
+
+    /// Degrees given as degrees, minutes and seconds, negative if `degrees` is.
+    fn dms(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        degrees.signum() * (degrees.abs() + minutes / 60.0 + seconds / 3600.0)
+    }
+
+    fn at(lat: f64, lon: f64) -> Position {
+        Position::new("2023-04-01T12:00:00.000Z".to_string(), lat, lon)
+    }
+
+    #[test]
+    fn matches_reference_values() {
+        // Vincenty's own example, Flinders Peak to Buninyong
+        let flinders_peak = at(dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440));
+        let buninyong = at(dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390));
+        let distance = vincenty(&flinders_peak, &buninyong).unwrap();
+        assert!((distance - 54_972.271).abs() < 1e-3, "{}", distance);
+        assert!((haversine(&flinders_peak, &buninyong) / distance - 1.0).abs() < 0.006);
+
+        // a degree along the equator and along a meridian
+        assert!(
+            (vincenty(&at(0.0, 0.0), &at(0.0, 1.0)).unwrap() - WGS84_A * PI / 180.0).abs() < 1e-6
+        );
+        assert!(
+            (haversine(&at(10.0, 5.0), &at(11.0, 5.0)) - EARTH_RADIUS * PI / 180.0).abs() < 1e-6
+        );
+        assert_eq!(bearing(&at(0.0, 0.0), &at(0.0, 1.0)), 90.0);
+        assert_eq!(bearing(&at(10.0, 5.0), &at(9.0, 5.0)), 180.0);
+
+        // the CN Tower in Toronto
+        let utm = Utm::from_position(&at(dms(43.0, 38.0, 33.24), dms(-79.0, 23.0, 13.7)));
+        assert_eq!((utm.zone, utm.north), (17, true));
+        assert!((utm.easting - 630_084.0).abs() < 1.0, "{}", utm);
+        assert!((utm.northing - 4_833_438.0).abs() < 1.0, "{}", utm);
+        // the origin of zone 31 and its southern counterpart
+        let utm = Utm::from_position(&at(0.0, 3.0));
+        assert_eq!((utm.zone, utm.easting, utm.northing), (31, 500_000.0, 0.0));
+        assert_eq!(
+            Utm::in_zone(&at(0.0, 3.0), 31, false).northing,
+            UTM_FALSE_NORTHING
+        );
+    }
+
+    #[test]
+    fn bounds_positions() {
+        let mut positions = vec![at(52.52, 13.40), at(52.50, 13.45), at(52.51, 13.38)];
+        positions.push(Position::default());
+        let bounds = BoundingBox::from_positions(&positions).unwrap();
+        assert_eq!(
+            bounds,
+            BoundingBox {
+                south: 52.50,
+                west: 13.38,
+                north: 52.52,
+                east: 13.45
+            }
+        );
+        assert!(positions[..3]
+            .iter()
+            .all(|position| bounds.contains(position)));
+        assert!(!bounds.contains(&at(52.53, 13.40)));
+        let (width, height) = bounds.pad(100.0).size();
+        let (inner_width, inner_height) = bounds.size();
+        assert!((width - inner_width - 200.0).abs() < 1.0);
+        assert!((height - inner_height - 200.0).abs() < 1.0);
+        assert_eq!(BoundingBox::from_positions(&[Position::default()]), None);
+    }
+
+    proptest! {
+        #[test]
+        fn distances_agree_and_are_symmetric(
+            lat1 in -89.0..89.0, lon1 in -180.0..180.0, lat2 in -89.0..89.0, lon2 in -180.0..180.0,
+        ) {
+            let (a, b) = (at(lat1, lon1), at(lat2, lon2));
+            prop_assert!((haversine(&a, &b) - haversine(&b, &a)).abs() < 1e-6);
+            if let (Some(ab), Some(ba)) = (vincenty(&a, &b), vincenty(&b, &a)) {
+                prop_assert!((ab - ba).abs() < 1e-3);
+                prop_assert!((haversine(&a, &b) - ab).abs() <= 0.006 * ab + 1e-6);
+            }
+        }
+
+        #[test]
+        fn destination_follows_bearing(
+            lat in -80.0..80.0, lon in -180.0..180.0, course in 0.0..360.0, distance in 1.0..100_000.0,
+        ) {
+            let start = at(lat, lon);
+            let (lat, lon) = destination(&start, course, distance);
+            let end = at(lat, lon);
+            prop_assert!((haversine(&start, &end) - distance).abs() < 1e-3);
+            let error = (bearing(&start, &end) - course + 180.0).rem_euclid(360.0) - 180.0;
+            prop_assert!(error.abs() < 1e-6);
+        }
+
+        #[test]
+        fn local_frame_round_trips(
+            lat in -89.0..89.0, lon in -180.0..180.0, east in -20_000.0..20_000.0, north in -20_000.0..20_000.0,
+        ) {
+            let reference = at(lat, lon);
+            let frame = LocalFrame::new(&reference);
+            let (lat, lon) = frame.to_lat_lon(Enu { east, north, up: 0.0 });
+            let point = at(lat, lon);
+            let enu = frame.to_enu(&point);
+            let (back_lat, back_lon) = frame.to_lat_lon(enu);
+            prop_assert!((back_lat - lat).abs() < 1e-9 && (back_lon - lon).abs() < 1e-9);
+            // close to the ground distance within a survey
+            let straight = enu.east.hypot(enu.north).hypot(enu.up);
+            prop_assert!((straight - vincenty(&reference, &point).unwrap()).abs() < 0.05);
+        }
+
+        #[test]
+        fn utm_round_trips(lat in -80.0..84.0, lon in -180.0..180.0) {
+            let utm = Utm::from_position(&at(lat, lon));
+            prop_assert!((1..=60).contains(&utm.zone));
+            prop_assert!((160_000.0..840_000.0).contains(&utm.easting));
+            let (back_lat, back_lon) = utm.to_lat_lon();
+            prop_assert!((back_lat - lat).abs() < 1e-9, "{} {}", back_lat, lat);
+            prop_assert!((back_lon - lon).abs() < 1e-9, "{} {}", back_lon, lon);
+        }
+    }
+}
@@ -1,185 +1,261 @@
+mod indoor;
+mod interpolate;
 mod position;
 
-use position::Position;
+pub use indoor::{Floor, FloorPlan, Indoor};
+pub use interpolate::Interpolator;
+pub use position::{Estimate, EstimateMethod, IndoorPosition, Position};
+
+use crate::cli::modes::GpsMode;
+use crate::error::NetMapError;
+use crate::record::RecordWriter;
+use crate::shutdown::Shutdown;
 use serde_json::Value;
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+use std::path::Path;
 use std::process::{Command, Output, Stdio};
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// Port gpsd listens on for clients.
+const GPSD_ADDRESS: &str = "127.0.0.1:2947";
+/// Longest wait between two attempts to connect to gpsd.
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+use tracing::{debug, info, warn};
+
+/// Task that follows the reports of gpsd, ending with the reason it stopped.
+pub type GpsTask = JoinHandle<Result<(), NetMapError>>;
+
+/// Configures and starts the GPS source of a run.
+pub struct GpsBuilder {
+    mode: GpsMode,
+    device: String,
+    output: RecordWriter,
+    shutdown: Shutdown,
+}
+
+impl GpsBuilder {
+    pub fn new(mode: GpsMode, device: String) -> Self {
+        GpsBuilder {
+            mode,
+            device,
+            output: RecordWriter::default(),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Records every fix to `output`.
+    pub fn output(mut self, output: RecordWriter) -> Self {
+        self.output = output;
+        self
+    }
 
-use tracing::{debug, info};
+    /// Closes the connection to gpsd once `shutdown` is triggered.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Starts gpsd, or takes positions on the floor plan named by the device in indoor mode,
+    /// and returns a handle to the position it keeps up to date.
+    /// Must be called from within a Tokio runtime.
+    pub fn start(self) -> Result<(Arc<Mutex<Position>>, GpsTask), NetMapError> {
+        Ok(match self.mode {
+            GpsMode::Host => {
+                let mut host = Host::new(self.device)?;
+                host.set_output(self.output);
+                host.set_shutdown(self.shutdown);
+                (host.position(), host.run())
+            }
+            GpsMode::Phone => {
+                let mut phone = Phone::new(self.device)?;
+                phone.set_output(self.output);
+                phone.set_shutdown(self.shutdown);
+                (phone.position(), phone.run())
+            }
+            GpsMode::Indoor => {
+                let mut indoor = Indoor::new(Path::new(&self.device))?;
+                indoor.set_output(self.output);
+                indoor.set_shutdown(self.shutdown);
+                (indoor.position(), indoor.run())
+            }
+        })
+    }
+}
 
 pub struct Host {
     position: Arc<Mutex<Position>>,
+    output: RecordWriter,
+    shutdown: Shutdown,
 }
 
 impl Host {
-    pub fn new(device_path: String) -> Self {
+    pub fn new(device_path: String) -> Result<Self, NetMapError> {
         let position = Arc::new(Mutex::new(Position::default()));
-        let _gpsd_output = Self::run_gpsd(device_path);
-        Host { position }
-    }
-
-    pub fn run_gpsd(device: String) -> Output {
-        Command::new("sudo")
-            .arg("pkill")
-            .arg("gpsd")
-            .output()
-            .expect("couldn't kill running gpsd-service");
-        Command::new("sudo")
-            .arg("systemctl")
-            .arg("stop")
-            .arg("gpsd.socket")
-            .output()
-            .expect("couldn't kill running gpsd-service");
-        Command::new("sudo")
-            .arg("gpsd")
-            .arg("-n")
-            // .arg("-D")
-            // .arg("5")
-            .arg(device)
-            .stdin(Stdio::piped())
-            // .stdout(Stdio::piped())
-            // .stderr(Stdio::piped())
-            .output()
-            .expect("couldn't start gpsd")
-    }
-
-    pub fn run(self) -> thread::JoinHandle<()> {
-        thread::spawn(move || {
-            // binary exponential backoff algorithm base for error case
-            let bebo_base = 2_u64;
-            let mut retries = 0_u32;
-            let mut stream = loop {
-                break match TcpStream::connect("127.0.0.1:2947") {
-                    Ok(s) => s,
-                    Err(_) => {
-                        thread::sleep(Duration::from_millis(bebo_base.pow(retries)));
-                        retries += 1;
-                        debug!("Failed to connect to gpsd on port 2947. Retrying...");
-                        continue;
-                    }
-                };
-            };
-            let mut reader: BufReader<TcpStream> = BufReader::new(
-                stream
-                    .try_clone()
-                    .expect("Failed to create reader for gpsd on port 2947."),
-            );
-            info!("Successfully connected to server in port 2947");
-            let msg = b"?WATCH={\"enable\":true,\"json\":true}";
-
-            stream
-                .write_all(msg)
-                .expect("Couldn't write message to gpsd.");
-            info!("Sent msg, awaiting reply...");
-            loop {
-                let mut buffer = String::new();
-                let _ = reader.read_line(&mut buffer);
-                let data =
-                    serde_json::from_str::<Value>(&buffer).expect("failed parsing gps to json");
-                if &data["class"] == "TPV" {
-                    let mode = data["mode"].as_u64().unwrap();
-                    if mode == 2_u64 || mode == 3_u64 {
-                        let current_position = Position::new(
-                            String::from(data["time"].as_str().unwrap_or("")),
-                            data["lat"].as_f64().unwrap_or(0.0),
-                            data["lon"].as_f64().unwrap_or(0.0),
-                        );
-                        debug!("Locking position.");
-                        *self.position.lock().unwrap() = current_position;
-                    }
-                };
-                // debug!("{}{}","Invalid position: ".green(), buffer);
-            }
+        let _gpsd_output = Self::run_gpsd(device_path)?;
+        Ok(Host {
+            position,
+            output: RecordWriter::default(),
+            shutdown: Shutdown::new(),
         })
     }
+
+    /// Shared handle to the most recent position reported by gpsd.
+    pub fn position(&self) -> Arc<Mutex<Position>> {
+        Arc::clone(&self.position)
+    }
+
+    /// Records every fix reported by gpsd to `output`.
+    pub fn set_output(&mut self, output: RecordWriter) {
+        self.output = output;
+    }
+
+    /// Stops following gpsd once `shutdown` is triggered.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    /// Restarts gpsd on `device`, polling the receiver even without clients.
+    pub fn run_gpsd(device: String) -> Result<Output, NetMapError> {
+        restart_gpsd(&["-n", &device])
+    }
+
+    pub fn run(self) -> GpsTask {
+        tokio::spawn(async move { watch(&self.position, &self.output, &self.shutdown).await })
+    }
 }
 
 pub struct Phone {
     position: Arc<Mutex<Position>>,
+    output: RecordWriter,
+    shutdown: Shutdown,
 }
 
 impl Phone {
-    pub fn new(device_path: String) -> Self {
+    pub fn new(device_path: String) -> Result<Self, NetMapError> {
         let position = Arc::new(Mutex::new(Position::default()));
-        let _gpsd_output = Self::run_gpsd(device_path);
-        Phone { position }
-    }
-
-    pub fn run_gpsd(device: String) -> Output {
-        Command::new("sudo")
-            .arg("pkill")
-            .arg("gpsd")
-            .output()
-            .expect("couldn't kill running gpsd-service");
-        Command::new("sudo")
-            .arg("systemctl")
-            .arg("stop")
-            .arg("gpsd.socket")
-            .output()
-            .expect("couldn't kill running gpsd-service");
-        Command::new("sudo")
-            .arg("gpsd")
-            // .arg("-D")
-            // .arg("5")
-            .arg(device)
-            .stdin(Stdio::piped())
-            // .stdout(Stdio::piped())
-            // .stderr(Stdio::piped())
-            .output()
-            .expect("couldn't start gpsd")
-    }
-
-    pub fn run(self) -> thread::JoinHandle<()> {
-        thread::spawn(move || {
-            // binary exponential backoff algorithm base for error case
-            let bebo_base = 2_u64;
-            let mut retries = 0_u32;
-            let mut stream = loop {
-                break match TcpStream::connect("127.0.0.1:2947") {
-                    Ok(s) => s,
-                    Err(_) => {
-                        thread::sleep(Duration::from_millis(bebo_base.pow(retries)));
-                        retries += 1;
-                        debug!("Failed to connect to gpsd on port 2947. Retrying...");
-                        continue;
-                    }
-                };
+        let _gpsd_output = Self::run_gpsd(device_path)?;
+        Ok(Phone {
+            position,
+            output: RecordWriter::default(),
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// Shared handle to the most recent position reported by gpsd.
+    pub fn position(&self) -> Arc<Mutex<Position>> {
+        Arc::clone(&self.position)
+    }
+
+    /// Records every fix reported by gpsd to `output`.
+    pub fn set_output(&mut self, output: RecordWriter) {
+        self.output = output;
+    }
+
+    /// Stops following gpsd once `shutdown` is triggered.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    /// Restarts gpsd on `device`, e.g. a UDP URL that the phone forwards its NMEA sentences to.
+    pub fn run_gpsd(device: String) -> Result<Output, NetMapError> {
+        restart_gpsd(&[&device])
+    }
+
+    pub fn run(self) -> GpsTask {
+        tokio::spawn(async move { watch(&self.position, &self.output, &self.shutdown).await })
+    }
+}
+
+/// Stops the running gpsd service and starts gpsd with `args`.
+fn restart_gpsd(args: &[&str]) -> Result<Output, NetMapError> {
+    sudo(&["pkill", "gpsd"])?;
+    sudo(&["systemctl", "stop", "gpsd.socket"])?;
+    sudo(&[&["gpsd"], args].concat())
+}
+
+fn sudo(args: &[&str]) -> Result<Output, NetMapError> {
+    Command::new("sudo")
+        .args(args)
+        .stdin(Stdio::piped())
+        .output()
+        .map_err(|e| NetMapError::Gps(format!("couldn't run {}: {}", args.join(" "), e)))
+}
+
+/// Follows the reports of gpsd and keeps `position` up to date until gpsd closes the connection or the shutdown is triggered.
+async fn watch(
+    position: &Mutex<Position>,
+    output: &RecordWriter,
+    shutdown: &Shutdown,
+) -> Result<(), NetMapError> {
+    // binary exponential backoff algorithm base for error case
+    let bebo_base = 2_u64;
+    let mut retries = 0_u32;
+    let mut stream = loop {
+        break match TcpStream::connect(GPSD_ADDRESS).await {
+            Ok(s) => s,
+            Err(_) => {
+                let backoff = Duration::from_millis(bebo_base.pow(retries.min(16)));
+                if shutdown.sleep(backoff.min(MAX_CONNECT_BACKOFF)).await {
+                    return Ok(());
+                }
+                retries += 1;
+                debug!("Failed to connect to gpsd on port 2947. Retrying...");
+                continue;
+            }
+        };
+    };
+    let (reader, mut writer) = stream.split();
+    let mut reports = BufReader::new(reader).lines();
+    info!("Successfully connected to server in port 2947");
+    let msg = b"?WATCH={\"enable\":true,\"json\":true}";
+
+    writer.write_all(msg).await?;
+    info!("Sent msg, awaiting reply...");
+    loop {
+        let report = tokio::select! {
+            report = reports.next_line() => report?,
+            _ = shutdown.wait() => break,
+        };
+        let Some(report) = report else {
+            return Err(NetMapError::Gps("gpsd closed the connection".to_string()));
+        };
+        let data = match serde_json::from_str::<Value>(&report) {
+            Ok(data) => data,
+            Err(e) => {
+                debug!("Invalid report from gpsd ({}): {}", e, report);
+                continue;
+            }
+        };
+        // Only 2D (2) and 3D (3) fixes carry a position.
+        if data["class"] == "TPV" && matches!(data["mode"].as_u64(), Some(2) | Some(3)) {
+            let current_position = Position {
+                speed: data["speed"].as_f64(),
+                track: data["track"].as_f64(),
+                // eph is only reported by some receivers, else the larger of the errors in longitude and latitude
+                accuracy: data["eph"].as_f64().or_else(|| {
+                    let (epx, epy) = (data["epx"].as_f64()?, data["epy"].as_f64()?);
+                    Some(epx.max(epy))
+                }),
+                ..Position::new(
+                    String::from(data["time"].as_str().unwrap_or("")),
+                    data["lat"].as_f64().unwrap_or(0.0),
+                    data["lon"].as_f64().unwrap_or(0.0),
+                )
             };
-            let mut reader: BufReader<TcpStream> = BufReader::new(
-                stream
-                    .try_clone()
-                    .expect("Failed to create reader for gpsd on port 2947."),
-            );
-            info!("Successfully connected to server in port 2947");
-            let msg = b"?WATCH={\"enable\":true,\"json\":true}";
-
-            stream
-                .write_all(msg)
-                .expect("Couldn't write message to gpsd.");
-            info!("Sent msg, awaiting reply...");
-            loop {
-                let mut buffer = String::new();
-                let _ = reader.read_line(&mut buffer);
-                let data =
-                    serde_json::from_str::<Value>(&buffer).expect("failed parsing gps to json");
-                if &data["class"] == "TPV" {
-                    let mode = data["mode"].as_u64().unwrap();
-                    if mode == 2_u64 || mode == 3_u64 {
-                        let current_position = Position::new(
-                            String::from(data["time"].as_str().unwrap_or("")),
-                            data["lat"].as_f64().unwrap_or(0.0),
-                            data["lon"].as_f64().unwrap_or(0.0),
-                        );
-                        debug!("Locking position.");
-                        *self.position.lock().unwrap() = current_position;
-                    }
-                };
-                debug!("Invalid position: {}", buffer);
+            if let Err(e) = output.write(current_position.clone()) {
+                warn!("Couldn't write GPS fix: {}", e);
             }
-        })
+            debug!("Locking position.");
+            *position.lock().unwrap() = current_position;
+        }
     }
+
+    // Stop the reports, gpsd keeps running for other clients.
+    writer.write_all(b"?WATCH={\"enable\":false}").await?;
+    info!("Disconnected from gpsd");
+    Ok(())
 }
@@ -0,0 +1,268 @@
+use super::position::format_time;
+use super::{GpsTask, IndoorPosition, Position};
+use crate::error::NetMapError;
+use crate::record::RecordWriter;
+use crate::session::unix_time;
+use crate::shutdown::Shutdown;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use tracing::{debug, info, warn};
+
+/// Address the positions are entered on by default, next to the port of gpsd.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:2948";
+/// Signature at the start of every PNG file.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Floor plans of a building, read from a TOML file:
+///
+/// ```toml
+/// address = "127.0.0.1:2948"
+///
+/// [[floors]]
+/// floor = 0
+/// image = "ground.png"
+/// scale = 0.05
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FloorPlan {
+    /// address that the positions are entered on
+    #[serde(default = "default_address")]
+    pub address: SocketAddr,
+    pub floors: Vec<Floor>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Floor {
+    pub floor: i32,
+    /// image of the floor plan, relative to the plan file
+    pub image: PathBuf,
+    /// meters per pixel of the image
+    pub scale: f64,
+    /// width and height of the image in pixels, if it is a PNG
+    #[serde(skip)]
+    pub size: Option<(u32, u32)>,
+}
+
+fn default_address() -> SocketAddr {
+    DEFAULT_ADDRESS.parse().unwrap()
+}
+
+impl FloorPlan {
+    /// Reads the plan at `path` and the sizes of its images.
+    pub fn load(path: &Path) -> Result<Self, NetMapError> {
+        let error = |message: String| NetMapError::Gps(format!("{}: {}", path.display(), message));
+        let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let mut plan: FloorPlan = toml::from_str(&text).map_err(|e| error(e.to_string()))?;
+        if plan.floors.is_empty() {
+            return Err(error("no floors".to_string()));
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for floor in &mut plan.floors {
+            if floor.scale <= 0.0 || !floor.scale.is_finite() {
+                return Err(error(format!("invalid scale of floor {}", floor.floor)));
+            }
+            floor.image = dir.join(&floor.image);
+            floor.size = png_size(&floor.image)
+                .map_err(|e| error(format!("{}: {}", floor.image.display(), e)))?;
+        }
+        Ok(plan)
+    }
+
+    /// Converts an entered `x y [floor]` in pixels of the floor plan into meters.
+    /// Without a floor, the position is on `current_floor`.
+    pub fn locate(&self, entry: &str, current_floor: i32) -> Result<IndoorPosition, String> {
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        let (x, y, floor) = match fields[..] {
+            [x, y] => (x, y, None),
+            [x, y, floor] => (x, y, Some(floor)),
+            _ => return Err("expected x y [floor]".to_string()),
+        };
+        let pixel = |value: &str| {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .ok_or_else(|| format!("invalid pixel {:?}", value))
+        };
+        let (x, y) = (pixel(x)?, pixel(y)?);
+        let floor = match floor {
+            Some(floor) => floor
+                .parse()
+                .map_err(|_| format!("invalid floor {:?}", floor))?,
+            None => current_floor,
+        };
+        let plan = self
+            .floors
+            .iter()
+            .find(|plan| plan.floor == floor)
+            .ok_or_else(|| format!("no plan of floor {}", floor))?;
+        if let Some((width, height)) = plan.size {
+            if x > width as f64 || y > height as f64 {
+                return Err(format!(
+                    "{} {} is outside of the {}x{} plan of floor {}",
+                    x, y, width, height, floor
+                ));
+            }
+        }
+        Ok(IndoorPosition {
+            x: x * plan.scale,
+            y: y * plan.scale,
+            floor,
+        })
+    }
+}
+
+/// Width and height of the PNG image at `path`, `None` for other formats.
+fn png_size(path: &Path) -> io::Result<Option<(u32, u32)>> {
+    // signature, then the IHDR chunk with length, type, width and height
+    let mut head = [0; 24];
+    let mut file = File::open(path)?;
+    if file.read_exact(&mut head).is_err() || !head.starts_with(PNG_SIGNATURE) {
+        return Ok(None);
+    }
+    let width = u32::from_be_bytes(head[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(head[20..24].try_into().unwrap());
+    Ok(Some((width, height)))
+}
+
+/// Position source for indoor surveys, where there is no GPS reception.
+/// The surveyor enters the position as `x y [floor]` in pixels of a floor plan,
+/// one per line over a TCP connection, e.g. with `nc` or a tool that turns clicks on the plan into lines.
+pub struct Indoor {
+    plan: Arc<FloorPlan>,
+    listener: TcpListener,
+    position: Arc<Mutex<Position>>,
+    output: RecordWriter,
+    shutdown: Shutdown,
+}
+
+impl Indoor {
+    /// Loads the floor plan at `plan_path` and listens on its address.
+    /// Must be called from within a Tokio runtime.
+    pub fn new(plan_path: &Path) -> Result<Self, NetMapError> {
+        let plan = FloorPlan::load(plan_path)?;
+        let address = plan.address;
+        let listener = std::net::TcpListener::bind(address)
+            .map_err(|source| NetMapError::Bind { address, source })?;
+        listener.set_nonblocking(true)?;
+        info!("Taking positions on {}", address);
+        Ok(Indoor {
+            plan: Arc::new(plan),
+            listener: TcpListener::from_std(listener)?,
+            position: Arc::new(Mutex::new(Position::default())),
+            output: RecordWriter::default(),
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// Shared handle to the most recently entered position.
+    pub fn position(&self) -> Arc<Mutex<Position>> {
+        Arc::clone(&self.position)
+    }
+
+    /// Records every entered position to `output`.
+    pub fn set_output(&mut self, output: RecordWriter) {
+        self.output = output;
+    }
+
+    /// Stops taking positions once `shutdown` is triggered.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    pub fn run(self) -> GpsTask {
+        tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    accepted = self.listener.accept() => accepted?.0,
+                    _ = self.shutdown.wait() => return Ok(()),
+                };
+                tokio::spawn(take_positions(
+                    stream,
+                    Arc::clone(&self.plan),
+                    Arc::clone(&self.position),
+                    self.output.clone(),
+                    self.shutdown.clone(),
+                ));
+            }
+        })
+    }
+}
+
+/// Reads entered positions from `stream` and answers each with the position in meters or an error.
+async fn take_positions(
+    mut stream: TcpStream,
+    plan: Arc<FloorPlan>,
+    position: Arc<Mutex<Position>>,
+    output: RecordWriter,
+    shutdown: Shutdown,
+) {
+    let (reader, mut writer) = stream.split();
+    let mut entries = BufReader::new(reader).lines();
+    loop {
+        let entry = tokio::select! {
+            entry = entries.next_line() => entry,
+            _ = shutdown.wait() => return,
+        };
+        let entry = match entry {
+            Ok(Some(entry)) if entry.trim().is_empty() => continue,
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(e) => {
+                debug!("Couldn't read position: {}", e);
+                return;
+            }
+        };
+        let current_floor = match &position.lock().unwrap().indoor {
+            Some(indoor) => indoor.floor,
+            None => plan.floors[0].floor,
+        };
+        let reply = match plan.locate(&entry, current_floor) {
+            Ok(indoor) => {
+                let current_position = Position::indoor(format_time(unix_time()), indoor);
+                if let Err(e) = output.write(current_position.clone()) {
+                    warn!("Couldn't write position: {}", e);
+                }
+                let reply = format!("ok {}\n", current_position);
+                *position.lock().unwrap() = current_position;
+                reply
+            }
+            Err(e) => format!("error {}\n", e),
+        };
+        if writer.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_entries_on_the_plan() {
+        let plan = FloorPlan {
+            address: default_address(),
+            floors: vec![Floor {
+                floor: 1,
+                image: PathBuf::from("first.png"),
+                scale: 0.05,
+                size: Some((800, 600)),
+            }],
+        };
+        let position = plan.locate("200 100", 1).unwrap();
+        assert_eq!((position.x, position.y, position.floor), (10.0, 5.0, 1));
+        assert!(plan.locate("200 100 2", 1).is_err());
+        assert!(plan.locate("900 100", 1).is_err());
+        assert!(plan.locate("200", 1).is_err());
+    }
+}
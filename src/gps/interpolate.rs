@@ -0,0 +1,222 @@
+use super::position::{format_time, parse_time};
+use super::{Estimate, EstimateMethod, IndoorPosition, Position};
+use crate::geo;
+use crate::record::{IntervalRecord, Record};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Seconds away from the nearest fix after which the confidence of an interpolated position halves.
+const INTERPOLATED_HALF_LIFE: f64 = 10.0;
+/// Seconds after the last fix after which the confidence of a position beyond it halves.
+const EXTRAPOLATED_HALF_LIFE: f64 = 3.0;
+
+/// Assigns every interval the position at its middle, estimated from the fixes around it,
+/// instead of the last fix before its end.
+///
+/// Intervals are held back until the next fix arrives and are then placed linearly in time between the fixes
+/// before and after them. Intervals that wait longer than `max_wait` are moved on from the last fix with its speed
+/// and track (dead reckoning). All other records pass through at once, so intervals may follow later records.
+pub struct Interpolator {
+    max_wait: f64,
+    last_fix: Option<(f64, Position)>,
+    // intervals with the time of their middle and of their arrival, in seconds since the Unix epoch
+    pending: VecDeque<(f64, f64, IntervalRecord)>,
+}
+
+impl Interpolator {
+    pub fn new(max_wait: Duration) -> Self {
+        Interpolator {
+            max_wait: max_wait.as_secs_f64(),
+            last_fix: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Takes a record written at `now`, in seconds since the Unix epoch, and returns the records that are ready.
+    pub fn push(&mut self, record: Record, now: f64) -> Vec<Record> {
+        let mut ready = Vec::new();
+        match record {
+            Record::Interval(interval) => {
+                // The interval is written right at its end.
+                let middle = now - (interval.to - interval.from) / 2.0;
+                self.pending.push_back((middle, now, interval));
+            }
+            Record::GpsFix(fix) => {
+                let next = (parse_time(&fix.time).unwrap_or(now), fix);
+                while let Some((middle, _, _)) = self.pending.front() {
+                    if *middle > next.0 {
+                        break;
+                    }
+                    let (middle, _, interval) = self.pending.pop_front().unwrap();
+                    ready.push(self.place(interval, middle, Some(&next)));
+                }
+                ready.push(Record::GpsFix(next.1.clone()));
+                self.last_fix = Some(next);
+            }
+            record => ready.push(record),
+        }
+        while let Some((_, arrival, _)) = self.pending.front() {
+            if now - arrival < self.max_wait {
+                break;
+            }
+            let (middle, _, interval) = self.pending.pop_front().unwrap();
+            ready.push(self.place(interval, middle, None));
+        }
+        ready
+    }
+
+    /// Places all held back intervals beyond the last fix, e.g. at the end of the session.
+    pub fn flush(&mut self) -> Vec<Record> {
+        let pending: Vec<_> = self.pending.drain(..).collect();
+        pending
+            .into_iter()
+            .map(|(middle, _, interval)| self.place(interval, middle, None))
+            .collect()
+    }
+
+    fn place(
+        &self,
+        mut interval: IntervalRecord,
+        time: f64,
+        next: Option<&(f64, Position)>,
+    ) -> Record {
+        if let Some(position) = estimate(self.last_fix.as_ref(), next, time) {
+            interval.position = position;
+        }
+        Record::Interval(interval)
+    }
+}
+
+/// Position at `time` from the fixes before and after it, `None` without any fix.
+fn estimate(
+    before: Option<&(f64, Position)>,
+    after: Option<&(f64, Position)>,
+    time: f64,
+) -> Option<Position> {
+    let (mut position, method, age, half_life) = match (before, after) {
+        (Some((t0, p0)), Some((t1, p1))) if *t0 <= time && time <= *t1 && t1 > t0 => (
+            between(p0, p1, (time - t0) / (t1 - t0)),
+            EstimateMethod::Interpolated,
+            (time - t0).min(t1 - time),
+            INTERPOLATED_HALF_LIFE,
+        ),
+        (Some((t0, p0)), _) if time >= *t0 => {
+            let (position, method) = dead_reckoning(p0, time - t0);
+            (position, method, time - t0, EXTRAPOLATED_HALF_LIFE)
+        }
+        // before the first fix that arrived in time
+        (Some((t, p)), _) | (None, Some((t, p))) => (
+            p.clone(),
+            EstimateMethod::Held,
+            (t - time).abs(),
+            EXTRAPOLATED_HALF_LIFE,
+        ),
+        (None, None) => return None,
+    };
+    position.time = format_time(time);
+    position.estimate = Some(Estimate {
+        method,
+        confidence: 0.5_f64.powf(age / half_life),
+    });
+    Some(position)
+}
+
+/// Position `fraction` of the way from `p0` to `p1`, which are close enough to ignore the curvature of the earth.
+fn between(p0: &Position, p1: &Position, fraction: f64) -> Position {
+    let nearest = if fraction < 0.5 { p0 } else { p1 };
+    let indoor = match (p0.indoor, p1.indoor) {
+        (Some(a), Some(b)) if a.floor == b.floor => Some(IndoorPosition {
+            x: a.x + (b.x - a.x) * fraction,
+            y: a.y + (b.y - a.y) * fraction,
+            floor: a.floor,
+        }),
+        _ => nearest.indoor,
+    };
+    Position {
+        lat: p0.lat + (p1.lat - p0.lat) * fraction,
+        lon: p0.lon + (p1.lon - p0.lon) * fraction,
+        indoor,
+        ..nearest.clone()
+    }
+}
+
+/// Position `elapsed` seconds after the fix `p0`, moved on with its speed and track if it has them.
+fn dead_reckoning(p0: &Position, elapsed: f64) -> (Position, EstimateMethod) {
+    match (p0.speed, p0.track, p0.indoor) {
+        (Some(speed), Some(track), None) => {
+            let (lat, lon) = geo::destination(p0, track, speed * elapsed);
+            let position = Position {
+                lat,
+                lon,
+                ..p0.clone()
+            };
+            (position, EstimateMethod::DeadReckoning)
+        }
+        _ => (p0.clone(), EstimateMethod::Held),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: f64 = 1_680_350_400.0;
+
+    fn fix(offset: f64, lat: f64, lon: f64) -> Record {
+        Record::GpsFix(Position::new(format_time(START + offset), lat, lon))
+    }
+
+    fn interval(from: f64) -> Record {
+        Record::Interval(IntervalRecord::empty(from))
+    }
+
+    fn position(record: &Record) -> &Position {
+        match record {
+            Record::Interval(interval) => &interval.position,
+            record => panic!("not an interval: {:?}", record),
+        }
+    }
+
+    #[test]
+    fn interpolates_between_fixes() {
+        let mut interpolator = Interpolator::new(Duration::from_secs(10));
+        assert_eq!(interpolator.push(fix(0.0, 52.0, 13.0), START).len(), 1);
+        // middle of the interval at 1 s, a quarter of the way to the next fix
+        assert!(interpolator.push(interval(0.5), START + 1.5).is_empty());
+
+        let ready = interpolator.push(fix(4.0, 53.0, 14.0), START + 4.0);
+        assert_eq!(ready.len(), 2);
+        let position = position(&ready[0]);
+        assert!((position.lat - 52.25).abs() < 1e-9);
+        assert!((position.lon - 13.25).abs() < 1e-9);
+        assert_eq!(position.time, "2023-04-01T12:00:01.000Z");
+        let estimate = position.estimate.unwrap();
+        assert_eq!(estimate.method, EstimateMethod::Interpolated);
+        assert!((estimate.confidence - 0.5_f64.powf(0.1)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn moves_on_from_the_last_fix() {
+        let mut interpolator = Interpolator::new(Duration::from_secs(2));
+        let mut heading_north = Position::new(format_time(START), 0.0, 13.0);
+        heading_north.speed = Some(10.0);
+        heading_north.track = Some(0.0);
+        interpolator.push(Record::GpsFix(heading_north.clone()), START);
+
+        assert!(interpolator.push(interval(2.0), START + 3.5).is_empty());
+        let ready = interpolator.push(interval(3.0), START + 5.5);
+        assert_eq!(ready.len(), 1);
+        let position = position(&ready[0]);
+        // 3 s at 10 m/s north
+        assert!((heading_north.distance(position) - 30.0).abs() < 1e-6);
+        assert!(position.lat > 0.0);
+        assert_eq!(
+            position.estimate.unwrap().method,
+            EstimateMethod::DeadReckoning
+        );
+
+        let ready = interpolator.flush();
+        assert_eq!(ready.len(), 1);
+        assert!(interpolator.flush().is_empty());
+    }
+}
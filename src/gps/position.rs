@@ -1,30 +1,191 @@
+use crate::geo;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Position {
     pub time: String,
     pub lat: f64,
     pub lon: f64,
+    /// position on a floor plan, for indoor surveys without GPS
+    pub indoor: Option<IndoorPosition>,
+    /// speed over ground in m/s, as reported by the receiver
+    pub speed: Option<f64>,
+    /// course over ground in degrees from true north, as reported by the receiver
+    pub track: Option<f64>,
+    /// estimated horizontal error in meters, as reported by the receiver
+    pub accuracy: Option<f64>,
+    /// how the position was derived from the fixes, `None` for a fix
+    pub estimate: Option<Estimate>,
+}
+
+/// Position in meters from the top left corner of a floor plan.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct IndoorPosition {
+    pub x: f64,
+    pub y: f64,
+    pub floor: i32,
+}
+
+/// Position that lies between fixes or after the last one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub method: EstimateMethod,
+    /// from 1 right at a fix down to 0 far away from any
+    pub confidence: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EstimateMethod {
+    /// Linear in time between the fixes before and after.
+    Interpolated,
+    /// Moved on from the last fix with its speed and track.
+    DeadReckoning,
+    /// The nearest fix, without speed and track to move on from it.
+    Held,
 }
 
 impl Position {
     pub fn new(time: String, lat: f64, lon: f64) -> Position {
-        Position { time, lat, lon }
+        Position {
+            time,
+            lat,
+            lon,
+            indoor: None,
+            speed: None,
+            track: None,
+            accuracy: None,
+            estimate: None,
+        }
+    }
+
+    /// Position on a floor plan, without coordinates.
+    pub fn indoor(time: String, indoor: IndoorPosition) -> Position {
+        Position {
+            indoor: Some(indoor),
+            ..Position::new(time, 0.0, 0.0)
+        }
+    }
+
+    /// Whether the position comes from a fix, the default position has no time.
+    pub fn has_fix(&self) -> bool {
+        !self.time.is_empty()
+    }
+
+    /// Distance to `other` in meters: great-circle distance (haversine) outdoors,
+    /// straight line on the same floor indoors and infinite between floors.
+    pub fn distance(&self, other: &Position) -> f64 {
+        match (&self.indoor, &other.indoor) {
+            (Some(a), Some(b)) if a.floor == b.floor => (a.x - b.x).hypot(a.y - b.y),
+            (None, None) => geo::haversine(self, other),
+            _ => f64::INFINITY,
+        }
     }
+}
 
-    pub fn default() -> Position {
+impl Default for Position {
+    fn default() -> Position {
         Position::new(String::from(""), 0.0, 0.0)
     }
 }
 
 impl PartialEq for Position {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time && self.lat == other.lat && self.lon == other.lon
+        self.time == other.time
+            && self.lat == other.lat
+            && self.lon == other.lon
+            && self.indoor == other.indoor
+            && self.speed == other.speed
+            && self.track == other.track
+            && self.accuracy == other.accuracy
+            && self.estimate == other.estimate
     }
 }
 
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "")
+        match &self.indoor {
+            Some(indoor) => write!(
+                f,
+                "{:.2} m, {:.2} m on floor {} ({})",
+                indoor.x, indoor.y, indoor.floor, self.time
+            ),
+            None => write!(f, "{}, {} ({})", self.lat, self.lon, self.time),
+        }
+    }
+}
+
+/// Formats seconds since the Unix epoch like gpsd, e.g. 2023-04-01T12:00:00.000Z.
+pub(crate) fn format_time(unix_time: f64) -> String {
+    let millis = (unix_time.max(0.0) * 1000.0).round() as u64;
+    let (seconds, millis) = (millis / 1000, millis % 1000);
+    let time = seconds % 86400;
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis
+    )
+}
+
+/// Seconds since the Unix epoch of a UTC time formatted like gpsd, `None` for other formats.
+pub(crate) fn parse_time(time: &str) -> Option<f64> {
+    let (date, time) = time.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':');
+    let hours: i64 = time.next()?.parse().ok()?;
+    let minutes: i64 = time.next()?.parse().ok()?;
+    let seconds: f64 = time.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // days since the epoch from the civil date, the inverse of `format_time`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some((days * 86400 + hours * 3600 + minutes * 60) as f64 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_time_like_gpsd() {
+        for (unix_time, time) in [
+            (1_680_350_400.25, "2023-04-01T12:00:00.250Z"),
+            (951_782_400.0, "2000-02-29T00:00:00.000Z"),
+            (0.0, "1970-01-01T00:00:00.000Z"),
+        ] {
+            assert_eq!(format_time(unix_time), time);
+            assert_eq!(parse_time(time), Some(unix_time));
+        }
+        assert_eq!(parse_time("2023-04-01T12:00:00Z"), Some(1_680_350_400.0));
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("2023-13-01T12:00:00Z"), None);
     }
 }
@@ -0,0 +1,39 @@
+//! NetMap records bandwidths at different physical locations of a wireless network using customized UDP or TCP
+//! traffic and GPS tracking.
+//!
+//! A run is set up through [`SenderBuilder`] or [`ReceiverBuilder`] and a [`GpsBuilder`] from within a Tokio runtime,
+//! which drives the traffic, the echoes and the GPS reports as tasks until a [`Shutdown`] is triggered.
+//! The modes that send set up their session file, header and GPS source through a [`RunBuilder`].
+//! Every measurement is written to a [`RecordWriter`], which stores it in a session file
+//! and streams it to callbacks or channels registered with [`RecordWriter::on_record`] and [`RecordWriter::subscribe`].
+
+pub mod annotation;
+pub mod cli;
+pub mod clients;
+pub mod compare;
+pub mod dashboard;
+pub mod error;
+pub mod geo;
+pub mod gps;
+pub mod link;
+pub mod record;
+pub mod report;
+pub mod run;
+pub mod session;
+pub mod shutdown;
+pub mod stats;
+pub mod web;
+pub mod wireless;
+pub mod zones;
+
+pub use clients::{
+    ProbeConfig, Protocol, Receiver, ReceiverBuilder, Sender, SenderBuilder, SurveyPlan,
+    SweepConfig, TcpReceiver, TcpSender,
+};
+pub use error::NetMapError;
+pub use gps::{GpsBuilder, Position};
+pub use link::LinkInfo;
+pub use record::{Record, RecordWriter};
+pub use run::{Run, RunBuilder};
+pub use session::{Session, SessionReader};
+pub use shutdown::{Pause, Shutdown};
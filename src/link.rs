@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::ptr;
+
+/// Network interface that the traffic of a run goes over.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkInfo {
+    pub name: String,
+    pub mac: Option<String>,
+    pub addresses: Vec<IpAddr>,
+    pub mtu: Option<u32>,
+}
+
+impl LinkInfo {
+    /// Reads the metadata of the interface called `name`.
+    pub fn read(name: &str) -> io::Result<Self> {
+        let addresses: Vec<IpAddr> = Self::addresses()?
+            .into_iter()
+            .filter(|(interface, _)| interface == name)
+            .map(|(_, address)| address)
+            .collect();
+        let sysfs = format!("/sys/class/net/{}", name);
+        if addresses.is_empty() && fs::metadata(&sysfs).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no interface called {}", name),
+            ));
+        }
+
+        let mac = fs::read_to_string(format!("{}/address", sysfs))
+            .ok()
+            .map(|mac| mac.trim().to_string())
+            .filter(|mac| !mac.is_empty());
+        let mtu = fs::read_to_string(format!("{}/mtu", sysfs))
+            .ok()
+            .and_then(|mtu| mtu.trim().parse().ok());
+
+        Ok(LinkInfo {
+            name: name.to_string(),
+            mac,
+            addresses,
+            mtu,
+        })
+    }
+
+    /// Reads the metadata of the interface that `address` is assigned to.
+    pub fn for_address(address: IpAddr) -> io::Result<Option<Self>> {
+        match Self::addresses()?
+            .into_iter()
+            .find(|(_, interface_address)| *interface_address == address)
+        {
+            Some((name, _)) => Self::read(&name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the metadata of the interface that the routing table picks to reach `target`.
+    pub fn route(target: &SocketAddr) -> io::Result<Option<Self>> {
+        let unspecified: IpAddr = match target {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        // Connecting a UDP socket doesn't send anything but selects the source address.
+        let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+        socket.connect(target)?;
+        Self::for_address(socket.local_addr()?.ip())
+    }
+
+    /// Whether the interface is a wireless one that nl80211 knows about.
+    pub fn is_wireless(&self) -> bool {
+        let sysfs = format!("/sys/class/net/{}", self.name);
+        fs::metadata(format!("{}/wireless", sysfs)).is_ok()
+            || fs::metadata(format!("{}/phy80211", sysfs)).is_ok()
+    }
+
+    /// First address of the interface in the same family as `target`, a link-local one only if there is no other.
+    pub fn source_address(&self, target: &IpAddr) -> Option<IpAddr> {
+        let candidates = || {
+            self.addresses
+                .iter()
+                .filter(|address| address.is_ipv6() == target.is_ipv6())
+        };
+        candidates()
+            .find(|address| !is_link_local(address))
+            .or_else(|| candidates().next())
+            .copied()
+    }
+
+    /// All IP addresses of all interfaces, as pairs of interface name and address.
+    fn addresses() -> io::Result<Vec<(String, IpAddr)>> {
+        let mut ifaddrs: *mut libc::ifaddrs = ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut addresses = Vec::new();
+        let mut current = ifaddrs;
+        while !current.is_null() {
+            let ifaddr = unsafe { &*current };
+            current = ifaddr.ifa_next;
+            if ifaddr.ifa_addr.is_null() {
+                continue;
+            }
+
+            let name = unsafe { CStr::from_ptr(ifaddr.ifa_name) }
+                .to_string_lossy()
+                .into_owned();
+            let address = match unsafe { (*ifaddr.ifa_addr).sa_family } as libc::c_int {
+                libc::AF_INET => {
+                    let sockaddr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in) };
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sockaddr.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sockaddr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in6) };
+                    IpAddr::V6(Ipv6Addr::from(sockaddr.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            };
+            addresses.push((name, address));
+        }
+
+        unsafe { libc::freeifaddrs(ifaddrs) };
+        Ok(addresses)
+    }
+}
+
+/// Whether `address` is only valid on its link, like the fe80::/10 address that every IPv6 interface has.
+fn is_link_local(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_link_local(),
+        IpAddr::V6(address) => address.is_unicast_link_local(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_address_prefers_global_addresses() {
+        let link = |addresses: &[&str]| LinkInfo {
+            name: "wlan0".to_string(),
+            mac: None,
+            addresses: addresses.iter().map(|a| a.parse().unwrap()).collect(),
+            mtu: None,
+        };
+        let v4: IpAddr = "192.168.1.10".parse().unwrap();
+        let v6: IpAddr = "2001:db8::10".parse().unwrap();
+
+        let dual = link(&["192.168.1.20", "fe80::1", "2001:db8::20"]);
+        assert_eq!(
+            dual.source_address(&v4),
+            Some("192.168.1.20".parse().unwrap())
+        );
+        assert_eq!(
+            dual.source_address(&v6),
+            Some("2001:db8::20".parse().unwrap())
+        );
+
+        let link_local = link(&["169.254.3.4", "fe80::1"]);
+        assert_eq!(
+            link_local.source_address(&v4),
+            Some("169.254.3.4".parse().unwrap())
+        );
+        assert_eq!(
+            link_local.source_address(&v6),
+            Some("fe80::1".parse().unwrap())
+        );
+
+        assert_eq!(link(&["fe80::1"]).source_address(&v4), None);
+    }
+}
@@ -1,66 +1,361 @@
-mod cli;
-mod clients;
-mod gps;
-
-use clap::Parser;
-use cli::{modes::GpsMode, modes::RunMode, Cli};
-use clients::{Receiver, Sender};
-use gps::{Host, Phone};
-use std::net::{SocketAddr, UdpSocket};
+use netmap::annotation::Annotator;
+use netmap::cli::modes::RunMode;
+use netmap::cli::Cli;
+use netmap::compare::{Alignment, Comparison, Thresholds};
+use netmap::dashboard::Dashboard;
+use netmap::report::Report;
+use netmap::session::{Session, SessionHeader};
+use netmap::stats::Ewma;
+use netmap::web::WebServer;
+use netmap::zones::Zones;
+use netmap::{
+    LinkInfo, Pause, Protocol, Receiver, Record, RecordWriter, RunBuilder, Shutdown, SurveyPlan,
+    SweepConfig,
+};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
 use tracing_subscriber::{fmt, EnvFilter};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     let subscriber = fmt::Subscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting tracing default failed");
 
-    let cli = Cli::parse();
+    let cli = Cli::parse_with_config()?;
+    // These only read files, there is no run to set up.
+    match cli.mode {
+        RunMode::Compare { .. } => {
+            compare(cli.mode)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        RunMode::Report { .. } => {
+            report(cli.mode)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
+    let shutdown = Shutdown::on_signals()?;
+    let (tui, web) = match &cli.mode {
+        RunMode::Receiver { .. } => return receive(cli.mode, shutdown).await,
+        RunMode::Sender { tui, web, .. } => (*tui, *web),
+        _ => (false, None),
+    };
+    let pause = Pause::new();
+    let builder = RunBuilder::new(cli.mode.clone())?
+        .shutdown(shutdown.clone())
+        .pause(pause.clone());
+    // Printing, subscribing and listening before the header is written, so they all get it.
+    if !tui {
+        print_records(builder.output());
+    }
+    let records = tui.then(|| builder.output().subscribe());
+    let web = web
+        .map(|address| WebServer::new(address, builder.output()))
+        .transpose()?;
+    let run = builder.start()?;
+    let web_url = web.as_ref().map(WebServer::url).transpose()?;
+
+    let mut dashboard = None;
+    match records {
+        Some(records) => {
+            let mut terminal = Dashboard::new().annotator(run.annotator.clone());
+            if let Some(url) = &web_url {
+                terminal = terminal.web(url.clone());
+            }
+            let shutdown = shutdown.clone();
+            dashboard = Some(thread::spawn(move || terminal.run(records, &shutdown)));
+        }
+        // The survey reads its marks along with the notes.
+        None if matches!(cli.mode, RunMode::Survey { .. }) => {}
+        // The dashboard has the terminal, notes are typed into it instead.
+        None => read_annotations(run.annotator.clone()),
+    }
+    if let Some(mut web) = web {
+        web.set_position(Arc::clone(&run.position));
+        web.set_annotator(run.annotator.clone());
+        web.set_pause(pause);
+        web.set_shutdown(shutdown.clone());
+        if let (false, Some(url)) = (tui, &web_url) {
+            println!("Dashboard on {}", url);
+        }
+        let _web_handler = web.run();
+    }
+
+    let output = run.output().clone();
     let client_handler = match cli.mode {
         RunMode::Sender {
-            port,
-            target_ip,
-            target_port,
             data_rate,
             packet_size,
-            sleep_adjust,
-            gps_mode,
-            gps_device,
-            output_file: _,
+            protocol: Protocol::Udp,
+            ..
+        } => tokio::spawn(run.sender().build()?.run(data_rate, packet_size, output)),
+        RunMode::Sender {
+            packet_size,
+            protocol: Protocol::Tcp,
+            ..
+        } => tokio::spawn(run.sender().build_tcp()?.run(packet_size, output)),
+        RunMode::Probe {
+            packet_size, probe, ..
+        } => tokio::spawn(
+            run.sender()
+                .build()?
+                .probe(probe.config(packet_size), output),
+        ),
+        RunMode::Sweep {
+            data_rate,
+            min_size,
+            max_size,
+            size_step,
+            step_duration,
+            ..
         } => {
-            let _gps_handler = match gps_mode {
-                GpsMode::Host => {
-                    let host = Host::new(gps_device);
-                    host.run()
-                }
-                GpsMode::Phone => {
-                    let phone = Phone::new(gps_device);
-                    phone.run()
-                }
+            let config = SweepConfig {
+                data_rate,
+                min_size,
+                max_size,
+                size_step,
+                step_duration: Duration::from_secs(step_duration),
             };
-            // Bind to a socket address.
-            let recv_socket = UdpSocket::bind(format!("127.0.0.1:{}", port)).unwrap();
-            recv_socket.set_nonblocking(true)?;
-
-            // Create the target socket address.
-            let target_address = SocketAddr::new(target_ip, target_port);
-
-            let sender = Sender::new(recv_socket, target_address);
-            sender.run(data_rate, packet_size, sleep_adjust)
+            tokio::spawn(run.sender().build()?.sweep(config, output))
         }
-        RunMode::Receiver {
-            port,
-            output_file: _,
+        RunMode::Wmm {
+            data_rate,
+            packet_size,
+            duration,
+            ..
+        } => tokio::spawn(run.sender().build()?.wmm(
+            data_rate,
+            packet_size,
+            Duration::from_secs(duration),
+            output,
+        )),
+        RunMode::Survey {
+            plan,
+            data_rate,
+            packet_size,
+            every_meters,
+            every_seconds,
+            probe,
+            ..
         } => {
-            let receiver = Receiver::new(port);
-            receiver.run()
+            let plan = SurveyPlan {
+                steps: plan,
+                data_rate,
+                packet_size,
+                probe: probe.config(packet_size),
+                min_distance: every_meters,
+                interval: every_seconds.map(Duration::from_secs),
+            };
+            let marks = read_marks(run.annotator.clone());
+            tokio::spawn(run.sender().build()?.survey(plan, marks, output))
+        }
+        RunMode::Compare { .. } | RunMode::Report { .. } | RunMode::Receiver { .. } => {
+            unreachable!("handled above")
         }
     };
 
-    let handlers = vec![client_handler];
+    let result = client_handler.await.map_err(|_| "client task panicked")?;
+    let summary = run.finish().await?;
+    if let Some(dashboard) = dashboard {
+        // The dashboard closes on shutdown, the summary is printed once the terminal is back to normal.
+        dashboard
+            .join()
+            .map_err(|_| "dashboard panicked")?
+            .map_err(|e| format!("dashboard failed: {}", e))?;
+        println!("{}", summary);
+    }
+    result?;
+    Ok(exit_code(&shutdown))
+}
+
+/// Like a shell, reports a stop by signal as 128 + the signal number.
+fn exit_code(shutdown: &Shutdown) -> ExitCode {
+    match shutdown.signal() {
+        Some(signal) => ExitCode::from(128 + signal as u8),
+        None => ExitCode::SUCCESS,
+    }
+}
+
+/// Echoes the traffic of the receiver mode until it is stopped.
+async fn receive(
+    mode: RunMode,
+    shutdown: Shutdown,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let RunMode::Receiver {
+        port,
+        protocol,
+        ref interface,
+        ref output_file,
+    } = mode
+    else {
+        unreachable!("not the receiver mode: {}", mode)
+    };
+    let output = RecordWriter::new(output_file.as_deref())?;
+    print_records(&output);
+    let interface = interface.as_deref().map(LinkInfo::read).transpose()?;
+    output.write(SessionHeader::new(mode.clone(), interface.clone(), None))?;
+
+    let mut builder = Receiver::builder(port).shutdown(shutdown.clone());
+    if let Some(interface) = interface {
+        builder = builder.interface(interface);
+    }
+    let result = match protocol {
+        Protocol::Udp => tokio::spawn(builder.build()?.run()),
+        Protocol::Tcp => tokio::spawn(builder.build_tcp()?.run()),
+    }
+    .await
+    .map_err(|_| "client task panicked")?;
+    output.finish()?;
+    result?;
+    Ok(exit_code(&shutdown))
+}
+
+/// Compares the sessions of the compare mode, prints every location and writes the diff files.
+fn compare(mode: RunMode) -> Result<(), Box<dyn std::error::Error>> {
+    let RunMode::Compare {
+        baseline,
+        current,
+        radius,
+        zones,
+        throughput_drop,
+        loss_increase,
+        rtt_increase,
+        min_intervals,
+        geojson,
+        html,
+    } = mode
+    else {
+        unreachable!("not the compare mode: {}", mode)
+    };
+    let read = |path: &Path| {
+        Session::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+    };
+    let (before, after) = (read(&baseline)?, read(&current)?);
+    let alignment = match zones {
+        Some(zones) => Alignment::Zones(Zones::load(&zones)?),
+        None => Alignment::Nearest(radius),
+    };
+    let thresholds = Thresholds {
+        throughput_drop,
+        loss_increase,
+        rtt_increase,
+        min_intervals,
+    };
+    let comparison = Comparison::new(&before, &after, &alignment, &thresholds);
+    for location in &comparison.locations {
+        println!("{}", location);
+    }
+    println!("{}", comparison);
 
-    for thread in handlers {
-        thread.join().unwrap();
+    if let Some(path) = geojson {
+        fs::write(path, format!("{:#}\n", comparison.to_geojson()))?;
+    }
+    if let Some(path) = html {
+        let html = comparison.to_html(
+            &baseline.display().to_string(),
+            &current.display().to_string(),
+        );
+        fs::write(path, html)?;
     }
     Ok(())
 }
+
+/// Writes the HTML report of the report mode.
+fn report(mode: RunMode) -> Result<(), Box<dyn std::error::Error>> {
+    let RunMode::Report {
+        session,
+        output_file,
+        title,
+        tiles,
+        ..
+    } = mode
+    else {
+        unreachable!("not the report mode: {}", mode)
+    };
+    let read = Session::read(&session)
+        .map_err(|e| format!("couldn't read {}: {}", session.display(), e))?;
+    let title = title.unwrap_or_else(|| {
+        let name = session.file_name().unwrap_or(session.as_os_str());
+        format!("NetMap report {}", name.to_string_lossy())
+    });
+    let report = Report::new(&title, &read).tiles(tiles);
+    let path = output_file.unwrap_or_else(|| session.with_extension("html"));
+    fs::write(&path, report.to_html())?;
+    println!(
+        "{}: {} intervals, {} roam events",
+        path.display(),
+        report.intervals.len(),
+        report.roams.len()
+    );
+    Ok(())
+}
+
+/// Prints the records as they are written.
+fn print_records(output: &RecordWriter) {
+    let mut throughput = Ewma::with_half_life(THROUGHPUT_HALF_LIFE);
+    output.on_record(move |record| print_record(record, &mut throughput));
+}
+
+/// Number of intervals after which an interval counts half in the smoothed throughput.
+const THROUGHPUT_HALF_LIFE: f64 = 5.0;
+
+fn print_record(record: &Record, throughput: &mut Ewma) {
+    match record {
+        Record::Header(header) => println!("{}", header),
+        Record::Interval(interval) => println!(
+            "{} (smoothed {:.2} Mbit/s)",
+            interval,
+            throughput.add(interval.throughput)
+        ),
+        Record::Event(event) => println!("{}", event),
+        Record::Probe(result) => println!("{}", result),
+        Record::Sweep(result) => println!("{}", result),
+        Record::Wmm(result) => println!("{}", result),
+        Record::Waypoint(waypoint) => println!("{}", waypoint),
+        Record::Footer(footer) => println!("{}", footer.summary),
+        Record::GpsFix(_) | Record::Scan(_) => {}
+    }
+}
+
+/// Writes every line typed on stdin as an annotation.
+fn read_annotations(annotator: Annotator) {
+    read_stdin(annotator, None);
+}
+
+/// Marks the current location as the next waypoint of a survey whenever Enter is pressed on an empty line,
+/// other lines are written as annotations.
+fn read_marks(annotator: Annotator) -> mpsc::Receiver<()> {
+    let (sender, receiver) = mpsc::channel(1);
+    read_stdin(annotator, Some(sender));
+    receiver
+}
+
+fn read_stdin(annotator: Annotator, marks: Option<mpsc::Sender<()>>) {
+    // A plain thread, blocking reads of stdin would keep the runtime from shutting down.
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            match annotator.annotate(&line) {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    // The mark may be dropped if the last one wasn't picked up yet.
+                    if let Some(Err(TrySendError::Closed(_))) =
+                        marks.as_ref().map(|marks| marks.try_send(()))
+                    {
+                        break;
+                    }
+                }
+                Err(e) => warn!("Couldn't write annotation: {}", e),
+            }
+        }
+    });
+}
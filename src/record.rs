@@ -0,0 +1,319 @@
+use crate::annotation::Annotation;
+use crate::clients::{
+    AccessCategory, ProbeResult, Protocol, RoamEvent, SweepResult, Waypoint, WmmResult,
+};
+use crate::error::ErrorCounts;
+use crate::gps::{Interpolator, Position};
+use crate::session::{unix_time, SessionFooter, SessionHeader, SessionSummary, SummaryAccumulator};
+use crate::wireless::{ScanRecord, WirelessStats};
+use crate::zones::Zones;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// A single line of a session file, tagged with its `type`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Header(SessionHeader),
+    Interval(IntervalRecord),
+    GpsFix(Position),
+    Event(Event),
+    Scan(ScanRecord),
+    Probe(ProbeResult),
+    Sweep(SweepResult),
+    Wmm(WmmResult),
+    Waypoint(Waypoint),
+    Footer(SessionFooter),
+}
+
+/// Something that happened during a run, tagged with its `event` kind.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Roam(RoamEvent),
+    Annotation(Annotation),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Roam(event) => event.fmt(f),
+            Event::Annotation(annotation) => annotation.fmt(f),
+        }
+    }
+}
+
+/// Geotagged measurements of one logging interval, written by every protocol.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IntervalRecord {
+    pub protocol: Protocol,
+    /// start of the interval in seconds since the start of the run
+    pub from: f64,
+    /// end of the interval in seconds since the start of the run
+    pub to: f64,
+    pub position: Position,
+    /// bytes transferred in this interval
+    pub bytes: u64,
+    /// throughput (UDP) or goodput (TCP) in Mbit/s
+    pub throughput: f64,
+    pub packets_sent: Option<u32>,
+    pub packets_received: Option<u32>,
+    /// loss in percent
+    pub loss: Option<f64>,
+    /// round trip time in milliseconds, the mean over the echoes of the interval
+    pub rtt: Option<f64>,
+    /// standard deviation of the round trip times of the echoes in milliseconds (jitter)
+    pub rtt_stddev: Option<f64>,
+    /// transient socket errors in this interval
+    pub errors: Option<ErrorCounts>,
+    /// TCP retransmissions in this interval
+    pub retransmits: Option<u32>,
+    /// TCP congestion window in segments
+    pub cwnd: Option<u32>,
+    /// TCP congestion control algorithm
+    pub congestion_control: Option<String>,
+    /// DSCP the packets were marked with
+    pub dscp: Option<u8>,
+    /// WMM access category that the DSCP maps to
+    pub access_category: Option<AccessCategory>,
+    /// statistics of the wireless link sampled during this interval
+    pub wireless: Option<WirelessStats>,
+    /// survey waypoint the interval was measured at
+    pub waypoint: Option<u32>,
+    /// names of the zones the position is in, see [`RecordWriter::zones`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+}
+
+#[cfg(test)]
+impl IntervalRecord {
+    /// A UDP interval of one second from `from` without measurements, for tests to fill in what they check.
+    pub(crate) fn empty(from: f64) -> Self {
+        IntervalRecord {
+            protocol: Protocol::Udp,
+            from,
+            to: from + 1.0,
+            position: Position::default(),
+            bytes: 0,
+            throughput: 0.0,
+            packets_sent: None,
+            packets_received: None,
+            loss: None,
+            rtt: None,
+            rtt_stddev: None,
+            errors: None,
+            retransmits: None,
+            cwnd: None,
+            congestion_control: None,
+            dscp: None,
+            access_category: None,
+            wireless: None,
+            waypoint: None,
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for IntervalRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:3.1}-{:3.1} {:.2} Mbytes {:.2} Mbit/s",
+            self.from,
+            self.to,
+            self.bytes as f64 / 1000000.0,
+            self.throughput
+        )?;
+        if let (Some(sent), Some(received), Some(loss)) =
+            (self.packets_sent, self.packets_received, self.loss)
+        {
+            write!(f, " {}/{} ({:3.2}%)", received, sent, loss)?;
+        }
+        if let (Some(retransmits), Some(cwnd)) = (self.retransmits, self.cwnd) {
+            write!(f, " {} retr {} cwnd", retransmits, cwnd)?;
+        }
+        write!(f, " {:.3}ms", self.rtt.unwrap_or_default())?;
+        if let Some(rtt_stddev) = self.rtt_stddev {
+            write!(f, " ±{:.3}ms", rtt_stddev)?;
+        }
+        if let Some(errors) = self.errors.filter(|errors| errors.total() > 0) {
+            write!(f, " {} errors", errors.total())?;
+        }
+        if let Some(access_category) = self.access_category {
+            write!(f, " [{}]", access_category)?;
+        }
+        if !self.zones.is_empty() {
+            write!(f, " in {}", self.zones.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<SessionHeader> for Record {
+    fn from(header: SessionHeader) -> Self {
+        Record::Header(header)
+    }
+}
+
+impl From<IntervalRecord> for Record {
+    fn from(record: IntervalRecord) -> Self {
+        Record::Interval(record)
+    }
+}
+
+impl From<Position> for Record {
+    fn from(position: Position) -> Self {
+        Record::GpsFix(position)
+    }
+}
+
+impl From<RoamEvent> for Record {
+    fn from(event: RoamEvent) -> Self {
+        Record::Event(Event::Roam(event))
+    }
+}
+
+impl From<Event> for Record {
+    fn from(event: Event) -> Self {
+        Record::Event(event)
+    }
+}
+
+impl From<ScanRecord> for Record {
+    fn from(record: ScanRecord) -> Self {
+        Record::Scan(record)
+    }
+}
+
+impl From<ProbeResult> for Record {
+    fn from(result: ProbeResult) -> Self {
+        Record::Probe(result)
+    }
+}
+
+impl From<SweepResult> for Record {
+    fn from(result: SweepResult) -> Self {
+        Record::Sweep(result)
+    }
+}
+
+impl From<WmmResult> for Record {
+    fn from(result: WmmResult) -> Self {
+        Record::Wmm(result)
+    }
+}
+
+impl From<Waypoint> for Record {
+    fn from(waypoint: Waypoint) -> Self {
+        Record::Waypoint(waypoint)
+    }
+}
+
+impl From<SessionFooter> for Record {
+    fn from(footer: SessionFooter) -> Self {
+        Record::Footer(footer)
+    }
+}
+
+type Callback = Box<dyn FnMut(&Record) + Send>;
+
+/// Writes records as JSON lines to the session file, if there is one, keeps the summary for the footer
+/// and hands every record to the registered callbacks.
+/// Clones share the same file and callbacks, so several threads can write to it.
+#[derive(Clone, Default)]
+pub struct RecordWriter {
+    file: Option<Arc<Mutex<BufWriter<File>>>>,
+    summary: Arc<Mutex<SummaryAccumulator>>,
+    callbacks: Arc<Mutex<Vec<Callback>>>,
+    interpolator: Option<Arc<Mutex<Interpolator>>>,
+    zones: Option<Arc<Zones>>,
+}
+
+impl RecordWriter {
+    /// Creates the session file at `path`, replacing an existing one.
+    pub fn new(path: Option<&Path>) -> io::Result<Self> {
+        let file = match path {
+            Some(path) => Some(Arc::new(Mutex::new(BufWriter::new(File::create(path)?)))),
+            None => None,
+        };
+        Ok(RecordWriter {
+            file,
+            ..Default::default()
+        })
+    }
+
+    /// Places every interval at the position estimated from the fixes around it, see [`Interpolator`].
+    /// Intervals are held back for the next fix, at most for `max_wait`.
+    pub fn interpolate(mut self, max_wait: Duration) -> Self {
+        self.interpolator = Some(Arc::new(Mutex::new(Interpolator::new(max_wait))));
+        self
+    }
+
+    /// Labels every interval with the names of the `zones` its position is in,
+    /// and adds aggregates per zone to the summary.
+    pub fn zones(mut self, zones: Zones) -> Self {
+        self.zones = Some(Arc::new(zones));
+        self
+    }
+
+    /// Calls `callback` with every record written from now on, on the thread that writes it.
+    pub fn on_record(&self, callback: impl FnMut(&Record) + Send + 'static) {
+        self.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    /// Streams every record written from now on over a channel.
+    pub fn subscribe(&self) -> mpsc::Receiver<Record> {
+        let (sender, receiver) = mpsc::channel();
+        self.on_record(move |record| {
+            // The subscriber may have hung up, the other outputs don't depend on it.
+            let _ = sender.send(record.clone());
+        });
+        receiver
+    }
+
+    pub fn write(&self, record: impl Into<Record>) -> io::Result<()> {
+        let record = record.into();
+        match &self.interpolator {
+            Some(interpolator) => {
+                let ready = interpolator.lock().unwrap().push(record, unix_time());
+                ready.into_iter().try_for_each(|record| self.emit(record))
+            }
+            None => self.emit(record),
+        }
+    }
+
+    fn emit(&self, mut record: Record) -> io::Result<()> {
+        if let (Some(zones), Record::Interval(interval)) = (&self.zones, &mut record) {
+            interval.zones = zones.names(&interval.position);
+        }
+        self.summary.lock().unwrap().add(&record);
+        for callback in self.callbacks.lock().unwrap().iter_mut() {
+            callback(&record);
+        }
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap();
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the footer with the summary of all records written so far and returns the summary.
+    pub fn finish(&self) -> io::Result<SessionSummary> {
+        if let Some(interpolator) = &self.interpolator {
+            let held_back = interpolator.lock().unwrap().flush();
+            held_back
+                .into_iter()
+                .try_for_each(|record| self.emit(record))?;
+        }
+        let footer = SessionFooter::new(self.summary.lock().unwrap().summary());
+        let summary = footer.summary.clone();
+        self.write(footer)?;
+        Ok(summary)
+    }
+}
@@ -0,0 +1,537 @@
+//! Single-file HTML report of a session, for readers without GIS tools.
+//!
+//! The report embeds the intervals, roam events and annotations as JSON and draws the map and the charts with an inline
+//! script, so it opens offline in any browser. OpenStreetMap tiles are only loaded behind the map if asked for.
+
+use crate::annotation::Annotation;
+use crate::clients::RoamEvent;
+use crate::compare::escape;
+use crate::gps::Position;
+use crate::record::{Event, IntervalRecord, Record};
+use crate::session::{Session, SessionSummary};
+use crate::stats::Distribution;
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+/// Intervals, roam events, annotations and summary of a session, rendered by [`Report::to_html`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub title: String,
+    /// run and host the session was recorded with, one `(label, value)` pair per line
+    pub details: Vec<(String, String)>,
+    pub summary: SessionSummary,
+    pub intervals: Vec<IntervalRecord>,
+    pub roams: Vec<RoamEvent>,
+    pub annotations: Vec<Annotation>,
+    /// whether to draw OpenStreetMap tiles behind the map, which needs a connection when opening the report
+    pub tiles: bool,
+}
+
+impl Report {
+    pub fn new(title: &str, session: &Session) -> Self {
+        let header = &session.header;
+        let mut details = vec![
+            ("Mode".to_string(), header.to_string()),
+            (
+                "Recorded by".to_string(),
+                format!("NetMap {} on {}", header.tool_version, header.host),
+            ),
+        ];
+        if let Some(interface) = &header.interface {
+            details.push(("Interface".to_string(), interface.name.clone()));
+        }
+        if let Some(gps) = &header.gps {
+            details.push((
+                "Positions".to_string(),
+                format!("{:?} {}", gps.mode, gps.device).to_lowercase(),
+            ));
+        }
+        if session.footer.is_none() {
+            details.push(("Note".to_string(), "the run was interrupted".to_string()));
+        }
+        let mut intervals = Vec::new();
+        let mut roams = Vec::new();
+        for record in &session.records {
+            match record {
+                Record::Interval(interval) => intervals.push(interval.clone()),
+                Record::Event(Event::Roam(event)) => roams.push(event.clone()),
+                _ => {}
+            }
+        }
+        // Interpolated intervals may have been written after later records.
+        intervals.sort_by(|a, b| a.to.total_cmp(&b.to));
+        Report {
+            title: title.to_string(),
+            details,
+            summary: session.summary(),
+            intervals,
+            roams,
+            annotations: session.annotations().cloned().collect(),
+            tiles: false,
+        }
+    }
+
+    pub fn tiles(mut self, tiles: bool) -> Self {
+        self.tiles = tiles;
+        self
+    }
+
+    /// Data that the script of the report draws.
+    fn data(&self) -> Value {
+        let intervals: Vec<Value> = self
+            .intervals
+            .iter()
+            .map(|interval| {
+                let mut point = location(&interval.position);
+                point["t"] = json!(interval.to);
+                point["throughput"] = json!(interval.throughput);
+                point["rtt"] = json!(interval.rtt);
+                point["loss"] = json!(interval.loss);
+                point["label"] = json!(interval.to_string());
+                point
+            })
+            .collect();
+        let roams: Vec<Value> = self
+            .roams
+            .iter()
+            .map(|roam| {
+                let mut point = location(&roam.position);
+                point["t"] = json!(roam.time);
+                point["label"] = json!(roam.to_string());
+                point
+            })
+            .collect();
+        let annotations: Vec<Value> = self
+            .annotations
+            .iter()
+            .map(|annotation| {
+                let mut point = location(&annotation.position);
+                point["t"] = json!(annotation.time);
+                point["label"] = json!(annotation.text);
+                point
+            })
+            .collect();
+        json!({"tiles": self.tiles, "intervals": intervals, "roams": roams, "annotations": annotations})
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n\
+             </head>\n<body>\n<h1>{}</h1>\n<table class=\"details\">\n",
+            escape(&self.title),
+            STYLE,
+            escape(&self.title)
+        );
+        for (label, value) in &self.details {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape(label),
+                escape(value)
+            );
+        }
+        let _ = writeln!(
+            html,
+            "</table>\n<p>{}</p>",
+            escape(self.summary.to_string().lines().next().unwrap_or_default())
+        );
+
+        html.push_str(
+            "<h2>Map</h2>\n<p><label>Color by <select id=\"metric\">\
+             <option value=\"throughput\">throughput</option><option value=\"rtt\">round trip time</option>\
+             <option value=\"loss\">loss</option></select></label> \
+             <label id=\"floors\" hidden>Floor <select id=\"floor\"></select></label> \
+             <span id=\"legend\"></span></p>\n\
+             <canvas id=\"map\" width=\"900\" height=\"600\"></canvas>\n\
+             <h2>Throughput (Mbit/s)</h2>\n<canvas id=\"throughput\" class=\"chart\" width=\"900\" height=\"200\"></canvas>\n\
+             <h2>Round trip time (ms)</h2>\n<canvas id=\"rtt\" class=\"chart\" width=\"900\" height=\"200\"></canvas>\n\
+             <h2>Loss (%)</h2>\n<canvas id=\"loss\" class=\"chart\" width=\"900\" height=\"200\"></canvas>\n",
+        );
+
+        html.push_str(
+            "<h2>Summary</h2>\n<table>\n<tr><th>Metric</th><th>Intervals</th><th>Mean</th><th>95% CI</th>\
+             <th>SD</th><th>Min</th><th>Median</th><th>p95</th><th>p99</th><th>Max</th></tr>\n",
+        );
+        for (metric, distribution) in [
+            ("Throughput (Mbit/s)", &self.summary.throughput_stats),
+            ("Round trip time (ms)", &self.summary.rtt_stats),
+            ("Loss (%)", &self.summary.loss_stats),
+        ] {
+            if let Some(distribution) = distribution {
+                let _ = writeln!(html, "{}", distribution_row(metric, distribution));
+            }
+        }
+        html.push_str("</table>\n");
+        if !self.summary.zones.is_empty() {
+            html.push_str(
+                "<table>\n<tr><th>Zone</th><th>Intervals</th><th>Median throughput (Mbit/s)</th>\
+                 <th>Loss (%)</th><th>RTT p95 (ms)</th></tr>\n",
+            );
+            for zone in &self.summary.zones {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{:.2}</td>{}{}</tr>",
+                    escape(&zone.name),
+                    zone.intervals,
+                    zone.throughput,
+                    cell(zone.loss, 2),
+                    cell(zone.rtt_p95, 3)
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        let _ = writeln!(html, "<h2>Roam events ({})</h2>", self.roams.len());
+        if !self.roams.is_empty() {
+            html.push_str(
+                "<table>\n<tr><th>Time (s)</th><th>Position</th><th>From</th><th>To</th>\
+                 <th>Outage (ms)</th><th>Packets lost</th></tr>\n",
+            );
+            for roam in &self.roams {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{:.1}</td><td>{}</td><td>{}</td><td>{}</td>{}<td>{}</td></tr>",
+                    roam.time,
+                    escape(&roam.position.to_string()),
+                    escape(&link(&roam.from_bssid, roam.from_frequency)),
+                    escape(&link(&roam.to_bssid, roam.to_frequency)),
+                    cell(roam.outage, 1),
+                    roam.packets_lost
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        let _ = writeln!(html, "<h2>Annotations ({})</h2>", self.annotations.len());
+        if !self.annotations.is_empty() {
+            html.push_str("<table>\n<tr><th>Time (s)</th><th>Position</th><th>Note</th></tr>\n");
+            for annotation in &self.annotations {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{:.1}</td><td>{}</td><td class=\"note\">{}</td></tr>",
+                    annotation.time,
+                    escape(&annotation.position.to_string()),
+                    escape(&annotation.text)
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        // "</" can't appear inside of a script element.
+        let data = self.data().to_string().replace("</", "<\\/");
+        let _ = write!(
+            html,
+            "<script>\nconst DATA = {};\n{}</script>\n</body>\n</html>\n",
+            data, SCRIPT
+        );
+        html
+    }
+}
+
+/// Coordinates of `position` for the script, outdoors or on the floor plan, none without a fix.
+fn location(position: &Position) -> Value {
+    match position.indoor {
+        _ if !position.has_fix() => json!({}),
+        Some(indoor) => json!({"x": indoor.x, "y": indoor.y, "floor": indoor.floor}),
+        None => json!({"lat": position.lat, "lon": position.lon}),
+    }
+}
+
+fn link(bssid: &str, frequency: Option<u32>) -> String {
+    match frequency {
+        Some(frequency) => format!("{} ({} MHz)", bssid, frequency),
+        None => bssid.to_string(),
+    }
+}
+
+fn cell(value: Option<f64>, precision: usize) -> String {
+    match value {
+        Some(value) => format!("<td>{:.*}</td>", precision, value),
+        None => "<td></td>".to_string(),
+    }
+}
+
+fn distribution_row(metric: &str, distribution: &Distribution) -> String {
+    let ci = distribution
+        .ci95
+        .map(|(low, high)| format!("{:.3}&ndash;{:.3}", low, high))
+        .unwrap_or_default();
+    format!(
+        "<tr><td>{}</td><td>{}</td><td>{:.3}</td><td>{}</td>{}<td>{:.3}</td><td>{:.3}</td><td>{:.3}</td>\
+         <td>{:.3}</td><td>{:.3}</td></tr>",
+        metric,
+        distribution.count,
+        distribution.mean,
+        ci,
+        cell(distribution.std_dev, 3),
+        distribution.min,
+        distribution.p50,
+        distribution.p95,
+        distribution.p99,
+        distribution.max
+    )
+}
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 1em 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }
+th { background: #f4f4f4; }
+td:first-child, td.note, table.details td { text-align: left; }
+canvas { border: 1px solid #ccc; display: block; max-width: 100%; }
+#legend span { display: inline-block; width: 1em; height: 1em; vertical-align: middle; }
+";
+
+/// Draws the map and the charts from `DATA`.
+const SCRIPT: &str = r##"// Metrics for which lower values are better are colored the other way around.
+const LOWER_IS_BETTER = { throughput: false, rtt: true, loss: true };
+const MARGIN = 40;
+const intervals = DATA.intervals, roams = DATA.roams, annotations = DATA.annotations;
+const located = intervals.concat(roams, annotations);
+const indoor = located.some(p => p.floor !== undefined);
+const floors = [...new Set(located.map(p => p.floor).filter(f => f !== undefined))].sort((a, b) => a - b);
+const metricSelect = document.getElementById("metric"), floorSelect = document.getElementById("floor");
+const tiles = {};
+
+function range(values) {
+  const finite = values.filter(Number.isFinite);
+  return finite.length ? [Math.min(...finite), Math.max(...finite)] : [0, 1];
+}
+
+// From red for the worst to green for the best value.
+function color(value, [min, max], lowerIsBetter) {
+  if (!Number.isFinite(value)) return "#999";
+  let q = max > min ? (value - min) / (max - min) : 1;
+  if (lowerIsBetter) q = 1 - q;
+  return `hsl(${Math.round(120 * q)}, 80%, 45%)`;
+}
+
+// Web Mercator, in fractions of the world.
+function mercator(p) {
+  const lat = p.lat * Math.PI / 180;
+  return [(p.lon + 180) / 360, (1 - Math.log(Math.tan(lat) + 1 / Math.cos(lat)) / Math.PI) / 2];
+}
+
+function drawMap() {
+  const canvas = document.getElementById("map"), ctx = canvas.getContext("2d");
+  const metric = metricSelect.value, floor = Number(floorSelect.value);
+  const shown = p => indoor ? p.floor === floor : p.lat !== undefined;
+  const points = intervals.filter(shown), marks = roams.filter(shown), notes = annotations.filter(shown);
+  ctx.clearRect(0, 0, canvas.width, canvas.height);
+  ctx.font = "12px sans-serif";
+  if (!points.length && !marks.length && !notes.length) {
+    ctx.fillText("No positions recorded", MARGIN, MARGIN);
+    return;
+  }
+  // World coordinates: meters on the floor plan with y down, else Web Mercator.
+  const world = p => indoor ? [p.x, p.y] : mercator(p);
+  const all = points.concat(marks, notes).map(world);
+  let [x0, x1] = range(all.map(w => w[0])), [y0, y1] = range(all.map(w => w[1]));
+  const width = canvas.width - 2 * MARGIN, height = canvas.height - 2 * MARGIN;
+  let scale = Math.min(width / Math.max(x1 - x0, 1e-9), height / Math.max(y1 - y0, 1e-9));
+  scale = Math.min(scale, indoor ? 100 : 256 * 2 ** 19);
+  let zoom;
+  if (DATA.tiles && !indoor) {
+    zoom = Math.max(0, Math.min(19, Math.floor(Math.log2(scale / 256))));
+    scale = 256 * 2 ** zoom;
+  }
+  const cx = (x0 + x1) / 2, cy = (y0 + y1) / 2;
+  const screen = w => [canvas.width / 2 + (w[0] - cx) * scale, canvas.height / 2 + (w[1] - cy) * scale];
+  const unscreen = (sx, sy) => [cx + (sx - canvas.width / 2) / scale, cy + (sy - canvas.height / 2) / scale];
+
+  if (zoom !== undefined) {
+    const n = 2 ** zoom, [l, t] = unscreen(0, 0), [r, b] = unscreen(canvas.width, canvas.height);
+    for (let tx = Math.floor(l * n); tx <= Math.floor(r * n); tx++) {
+      for (let ty = Math.max(0, Math.floor(t * n)); ty <= Math.min(n - 1, Math.floor(b * n)); ty++) {
+        const key = `${zoom}/${((tx % n) + n) % n}/${ty}`;
+        if (!tiles[key]) {
+          tiles[key] = new Image();
+          tiles[key].onload = drawMap;
+          tiles[key].src = `https://tile.openstreetmap.org/${key}.png`;
+        }
+        if (tiles[key].complete && tiles[key].naturalWidth) {
+          const [sx, sy] = screen([tx / n, ty / n]);
+          ctx.drawImage(tiles[key], sx, sy, 256, 256);
+        }
+      }
+    }
+    ctx.fillStyle = "#333";
+    ctx.fillText("© OpenStreetMap contributors", canvas.width - 190, canvas.height - 6);
+  } else {
+    // Coordinate grid instead of a background map.
+    ctx.strokeStyle = "#eee";
+    ctx.fillStyle = "#666";
+    for (let i = 0; i <= 4; i++) {
+      const sx = MARGIN + i * width / 4, sy = MARGIN + i * height / 4;
+      ctx.beginPath(); ctx.moveTo(sx, 0); ctx.lineTo(sx, canvas.height); ctx.stroke();
+      ctx.beginPath(); ctx.moveTo(0, sy); ctx.lineTo(canvas.width, sy); ctx.stroke();
+      const [wx, wy] = unscreen(sx, sy);
+      if (indoor) {
+        ctx.fillText(`${wx.toFixed(1)} m`, sx + 2, canvas.height - 4);
+        ctx.fillText(`${wy.toFixed(1)} m`, 2, sy - 2);
+      } else {
+        const lon = wx * 360 - 180;
+        const lat = Math.atan(Math.sinh(Math.PI * (1 - 2 * wy))) * 180 / Math.PI;
+        ctx.fillText(lon.toFixed(5), sx + 2, canvas.height - 4);
+        ctx.fillText(lat.toFixed(5), 2, sy - 2);
+      }
+    }
+  }
+
+  const values = range(intervals.map(p => p[metric]));
+  ctx.strokeStyle = "#888";
+  ctx.beginPath();
+  points.forEach((p, i) => { const [sx, sy] = screen(world(p)); i ? ctx.lineTo(sx, sy) : ctx.moveTo(sx, sy); });
+  ctx.stroke();
+  for (const p of points) {
+    const [sx, sy] = screen(world(p));
+    ctx.fillStyle = color(p[metric], values, LOWER_IS_BETTER[metric]);
+    ctx.beginPath(); ctx.arc(sx, sy, 5, 0, 2 * Math.PI); ctx.fill();
+  }
+  // Roams as black diamonds.
+  ctx.fillStyle = "#000";
+  for (const p of marks) {
+    const [sx, sy] = screen(world(p));
+    ctx.beginPath();
+    ctx.moveTo(sx, sy - 7); ctx.lineTo(sx + 7, sy); ctx.lineTo(sx, sy + 7); ctx.lineTo(sx - 7, sy);
+    ctx.fill();
+  }
+  // Annotations as blue squares with their text.
+  for (const p of notes) {
+    const [sx, sy] = screen(world(p));
+    ctx.fillStyle = "#06f";
+    ctx.fillRect(sx - 5, sy - 5, 10, 10);
+    ctx.fillStyle = "#000";
+    ctx.fillText(p.label, sx + 8, sy + 4);
+  }
+
+  const legend = document.getElementById("legend");
+  const swatch = v => `<span style="background: ${color(v, values, LOWER_IS_BETTER[metric])}"></span>`;
+  legend.innerHTML = `${swatch(values[0])} ${values[0].toFixed(2)} &ndash; ${swatch(values[1])} ${values[1].toFixed(2)}` +
+    (marks.length ? " &#9670; roam" : "") + (notes.length ? ' <b style="color: #06f">&#9632;</b> note' : "");
+}
+
+function drawChart(metric) {
+  const canvas = document.getElementById(metric), ctx = canvas.getContext("2d");
+  const points = intervals.filter(p => Number.isFinite(p[metric]));
+  ctx.font = "12px sans-serif";
+  ctx.fillStyle = "#666";
+  if (!points.length) {
+    ctx.fillText("Not measured", MARGIN, MARGIN);
+    return;
+  }
+  const [t0, t1] = range(located.map(p => p.t));
+  let [v0, v1] = range(points.map(p => p[metric]));
+  v0 = Math.min(v0, 0);
+  if (v1 <= v0) v1 = v0 + 1;
+  const width = canvas.width - 2 * MARGIN, height = canvas.height - 2 * MARGIN;
+  const sx = t => MARGIN + (t1 > t0 ? (t - t0) / (t1 - t0) : 0.5) * width;
+  const sy = v => canvas.height - MARGIN - (v - v0) / (v1 - v0) * height;
+  ctx.strokeStyle = "#ccc";
+  ctx.strokeRect(MARGIN, MARGIN, width, height);
+  ctx.fillText(v1.toFixed(2), 2, MARGIN + 4);
+  ctx.fillText(v0.toFixed(2), 2, canvas.height - MARGIN);
+  ctx.fillText(`${t0.toFixed(0)} s`, MARGIN, canvas.height - MARGIN + 16);
+  ctx.fillText(`${t1.toFixed(0)} s`, canvas.width - MARGIN - 30, canvas.height - MARGIN + 16);
+  ctx.setLineDash([4, 4]);
+  ctx.strokeStyle = "#000";
+  for (const roam of roams) {
+    ctx.beginPath(); ctx.moveTo(sx(roam.t), MARGIN); ctx.lineTo(sx(roam.t), canvas.height - MARGIN); ctx.stroke();
+  }
+  ctx.strokeStyle = "#06f";
+  ctx.fillStyle = "#06f";
+  for (const note of annotations) {
+    ctx.beginPath(); ctx.moveTo(sx(note.t), MARGIN); ctx.lineTo(sx(note.t), canvas.height - MARGIN); ctx.stroke();
+    ctx.fillText(note.label, sx(note.t) + 3, MARGIN + 12);
+  }
+  ctx.setLineDash([]);
+  ctx.strokeStyle = "#2a6fdb";
+  ctx.beginPath();
+  points.forEach((p, i) => i ? ctx.lineTo(sx(p.t), sy(p[metric])) : ctx.moveTo(sx(p.t), sy(p[metric])));
+  ctx.stroke();
+}
+
+if (floors.length) {
+  floorSelect.innerHTML = floors.map(f => `<option value="${f}">${f}</option>`).join("");
+  document.getElementById("floors").hidden = floors.length < 2;
+}
+metricSelect.onchange = floorSelect.onchange = drawMap;
+drawMap();
+["throughput", "rtt", "loss"].forEach(drawChart);
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::modes::RunMode;
+    use crate::clients::Protocol;
+    use crate::session::SessionHeader;
+
+    #[test]
+    fn embeds_intervals_roams_and_annotations() {
+        let mode = RunMode::Receiver {
+            port: 2000,
+            protocol: Protocol::Udp,
+            interface: None,
+            output_file: None,
+        };
+        let position = Position::new("2023-04-01T12:00:00.000Z".to_string(), 52.0, 13.0);
+        let interval = |to: f64, throughput| {
+            Record::Interval(IntervalRecord {
+                position: position.clone(),
+                bytes: 125_000,
+                throughput,
+                packets_sent: Some(100),
+                packets_received: Some(100),
+                loss: Some(0.0),
+                ..IntervalRecord::empty(to - 1.0)
+            })
+        };
+        let session = Session {
+            header: SessionHeader::new(mode, None, None),
+            records: vec![
+                interval(2.0, 1.0),
+                Record::Event(Event::Roam(RoamEvent {
+                    time: 1.5,
+                    position: Position::default(),
+                    from_bssid: "</script>".to_string(),
+                    to_bssid: "aa:bb:cc:dd:ee:ff".to_string(),
+                    from_frequency: None,
+                    to_frequency: Some(5180),
+                    outage: None,
+                    packets_lost: 0,
+                })),
+                interval(1.0, 2.0),
+                Record::Event(Event::Annotation(Annotation {
+                    time: 1.2,
+                    text: "microwave <on>".to_string(),
+                    position: position.clone(),
+                })),
+            ],
+            footer: None,
+        };
+
+        let report = Report::new("Survey", &session);
+        assert_eq!(report.summary.intervals, 2);
+        let data = report.data();
+        assert_eq!(data["intervals"][0]["t"], 1.0);
+        assert_eq!(data["intervals"][0]["lat"], 52.0);
+        assert_eq!(data["intervals"][1]["throughput"], 1.0);
+        assert_eq!(
+            data["roams"][0],
+            json!({"t": 1.5, "label": report.roams[0].to_string()})
+        );
+
+        assert_eq!(
+            data["annotations"][0],
+            json!({"t": 1.2, "lat": 52.0, "lon": 13.0, "label": "microwave <on>"})
+        );
+
+        let html = report.to_html();
+        assert_eq!(html.matches("</script>").count(), 1);
+        assert!(html.contains("<td>&lt;/script&gt;</td><td>aa:bb:cc:dd:ee:ff (5180 MHz)</td>"));
+        assert!(html.contains("<td class=\"note\">microwave &lt;on&gt;</td>"));
+        assert!(html.contains("the run was interrupted"));
+    }
+}
@@ -0,0 +1,300 @@
+//! Setup shared by the modes that send: the session file and its header, the GPS source, the statistics of the
+//! wireless link and the access point scans around the traffic of a Sender.
+
+use crate::annotation::Annotator;
+use crate::cli::modes::{RecordingArgs, RunMode, TargetArgs};
+use crate::clients::{Protocol, Sender, SenderBuilder};
+use crate::error::NetMapError;
+use crate::gps::{GpsBuilder, GpsTask, Position};
+use crate::link::LinkInfo;
+use crate::record::RecordWriter;
+use crate::session::{SessionHeader, SessionSummary};
+use crate::shutdown::{Pause, Shutdown};
+use crate::wireless::{Scanner, WirelessSampler, WirelessStats};
+use crate::zones::Zones;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Configures the run of a sending mode, from its command line options.
+pub struct RunBuilder {
+    mode: RunMode,
+    target: TargetArgs,
+    recording: RecordingArgs,
+    interface: Option<LinkInfo>,
+    output: RecordWriter,
+    shutdown: Shutdown,
+    pause: Pause,
+}
+
+impl RunBuilder {
+    /// Checks the packet sizes and the interface of `mode` and opens its session file.
+    /// Fails for the modes that don't send.
+    pub fn new(mode: RunMode) -> Result<Self, NetMapError> {
+        let (target, recording) = match &mode {
+            RunMode::Sender {
+                target, recording, ..
+            }
+            | RunMode::Probe {
+                target, recording, ..
+            }
+            | RunMode::Sweep {
+                target, recording, ..
+            }
+            | RunMode::Wmm {
+                target, recording, ..
+            }
+            | RunMode::Survey {
+                target, recording, ..
+            } => (target.clone(), recording.clone()),
+            _ => return Err(NetMapError::Config(format!("{} doesn't send", mode))),
+        };
+        if let RunMode::Sender {
+            sleep_adjust: Some(_),
+            ..
+        } = &mode
+        {
+            warn!("--sleep-adjust is deprecated and has no effect, the packets are paced against the clock");
+        }
+        for packet_size in packet_sizes(&mode) {
+            Sender::payload_size(packet_size, &target.address())?;
+        }
+        let interface = target
+            .interface
+            .as_deref()
+            .map(LinkInfo::read)
+            .transpose()?;
+        let output = open(&recording)?;
+        Ok(RunBuilder {
+            mode,
+            target,
+            recording,
+            interface,
+            output,
+            shutdown: Shutdown::new(),
+            pause: Pause::new(),
+        })
+    }
+
+    /// The session file, to register callbacks and subscribers that should get the header as well.
+    pub fn output(&self) -> &RecordWriter {
+        &self.output
+    }
+
+    /// Stops the GPS source and the traffic once `shutdown` is triggered.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Holds the traffic while `pause` is paused.
+    pub fn pause(mut self, pause: Pause) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Writes the header and starts the GPS source, along with the sampling of the link statistics and the scans
+    /// if the traffic goes over a wireless interface. Must be called from within a Tokio runtime.
+    pub fn start(self) -> Result<Run, NetMapError> {
+        let target_address = self.target.address();
+        // Without an explicitly chosen interface, the one picked by the routing table is recorded.
+        let link = match self.interface.clone() {
+            Some(interface) => Some(interface),
+            None => LinkInfo::route(&target_address)?,
+        };
+        info!("Sending over {:?}", link);
+        let tos = match &self.mode {
+            RunMode::Sender { marking, .. } => marking.tos(),
+            _ => None,
+        };
+        let scan_interval = match &self.mode {
+            RunMode::Sender { scan_interval, .. } => *scan_interval,
+            _ => None,
+        };
+        self.output.write(SessionHeader::new(
+            self.mode,
+            link.clone(),
+            Some(self.recording.gps()),
+        ))?;
+
+        let gps = self.recording.gps();
+        let (position, gps) = GpsBuilder::new(gps.mode, gps.device)
+            .output(self.output.clone())
+            .shutdown(self.shutdown.clone())
+            .start()?;
+        let annotator = Annotator::new(self.output.clone(), Arc::clone(&position));
+
+        // Background tasks that stop with the run, awaited before the footer.
+        let mut tasks = Vec::new();
+        let wireless = link.as_ref().filter(|link| link.is_wireless()).map(|link| {
+            let sampler = WirelessSampler::new(link.name.clone());
+            let stats = sampler.stats();
+            tasks.push(sampler.run(self.shutdown.clone()));
+            stats
+        });
+        if let Some(scan_interval) = scan_interval {
+            match link.as_ref().filter(|link| link.is_wireless()) {
+                Some(link) => {
+                    let scanner = Scanner::new(
+                        link.name.clone(),
+                        Duration::from_secs(scan_interval),
+                        Arc::clone(&position),
+                    );
+                    tasks.push(scanner.run(self.output.clone(), self.shutdown.clone()));
+                }
+                None => warn!("Not scanning for access points, {:?} isn't wireless", link),
+            }
+        }
+
+        Ok(Run {
+            link,
+            position,
+            annotator,
+            output: self.output,
+            target: self.target,
+            interface: self.interface,
+            tos,
+            wireless,
+            gps,
+            tasks,
+            shutdown: self.shutdown,
+            pause: self.pause,
+        })
+    }
+}
+
+/// A started run, with its header written and its GPS source running.
+pub struct Run {
+    /// interface that the traffic goes over, as recorded in the header
+    pub link: Option<LinkInfo>,
+    /// position kept up to date by the GPS source
+    pub position: Arc<Mutex<Position>>,
+    /// writes notes to the session, with the time counted from the header
+    pub annotator: Annotator,
+    output: RecordWriter,
+    target: TargetArgs,
+    interface: Option<LinkInfo>,
+    tos: Option<u8>,
+    wireless: Option<Arc<Mutex<Option<WirelessStats>>>>,
+    gps: GpsTask,
+    /// sampling of the wireless link and scans for access points
+    tasks: Vec<JoinHandle<()>>,
+    shutdown: Shutdown,
+    pause: Pause,
+}
+
+impl Run {
+    pub fn output(&self) -> &RecordWriter {
+        &self.output
+    }
+
+    /// A Sender towards the target of the run, with its port, interface, marking, position, link statistics,
+    /// shutdown and pause, that times its intervals on the clock of the annotations.
+    pub fn sender(&self) -> SenderBuilder {
+        let mut builder = Sender::builder(self.target.address())
+            .port(self.target.port)
+            .position(Arc::clone(&self.position))
+            .shutdown(self.shutdown.clone())
+            .pause(self.pause.clone())
+            .started(self.annotator.started());
+        if let Some(tos) = self.tos {
+            builder = builder.tos(tos);
+        }
+        if let Some(interface) = self.interface.clone() {
+            builder = builder.interface(interface);
+        }
+        if let Some(wireless) = &self.wireless {
+            builder = builder.wireless(Arc::clone(wireless));
+        }
+        builder
+    }
+
+    /// Stops the GPS source, the sampling of the wireless link and the scans, waits for them and writes the footer
+    /// with the summary of the run.
+    /// Call it once the traffic is done, even if it failed, so the file covers the run up to the error.
+    pub async fn finish(self) -> Result<SessionSummary, NetMapError> {
+        self.shutdown.trigger();
+        match self.gps.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("{}", e),
+            Err(_) => warn!("GPS task panicked"),
+        }
+        for task in self.tasks {
+            if task.await.is_err() {
+                warn!("Wireless task panicked");
+            }
+        }
+        Ok(self.output.finish()?)
+    }
+}
+
+/// Opens the session file, with the intervals placed between the fixes around them and labeled with the zones
+/// if the options ask for it.
+fn open(recording: &RecordingArgs) -> Result<RecordWriter, NetMapError> {
+    let mut output = RecordWriter::new(recording.output_file.as_deref())?;
+    if let Some(max_wait) = recording.interpolate {
+        output = output.interpolate(Duration::from_secs(max_wait));
+    }
+    if let Some(zones) = &recording.zones {
+        output = output.zones(Zones::load(zones)?);
+    }
+    Ok(output)
+}
+
+/// Sizes of the UDP packets that `mode` sends, which have to leave room for the headers.
+fn packet_sizes(mode: &RunMode) -> Vec<usize> {
+    match mode {
+        RunMode::Sender {
+            packet_size,
+            protocol: Protocol::Udp,
+            ..
+        }
+        | RunMode::Probe { packet_size, .. }
+        | RunMode::Wmm { packet_size, .. }
+        | RunMode::Survey { packet_size, .. } => vec![*packet_size],
+        RunMode::Sweep {
+            min_size, max_size, ..
+        } => vec![*min_size, *max_size],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+
+    fn mode(args: &[&str]) -> RunMode {
+        Cli::try_parse_from([&["netmap"], args].concat())
+            .unwrap()
+            .mode
+    }
+
+    #[test]
+    fn checks_the_mode_before_opening_the_session() {
+        assert!(matches!(
+            RunBuilder::new(mode(&["receiver"])),
+            Err(NetMapError::Config(_))
+        ));
+        assert!(matches!(
+            RunBuilder::new(mode(&["sweep", "--min-size", "10"])),
+            Err(NetMapError::InvalidPacketSize {
+                packet_size: 10,
+                ..
+            })
+        ));
+        // TCP streams, the packet size is only how much is written at once.
+        assert!(RunBuilder::new(mode(&[
+            "sender",
+            "--protocol",
+            "tcp",
+            "--packet-size",
+            "10"
+        ]))
+        .is_ok());
+        // Deprecated, but still accepted.
+        assert!(RunBuilder::new(mode(&["sender", "-f", "10"])).is_ok());
+    }
+}
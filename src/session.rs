@@ -0,0 +1,567 @@
+use crate::annotation::Annotation;
+use crate::cli::modes::{GpsMode, RunMode};
+use crate::link::LinkInfo;
+use crate::record::{Event, Record};
+use crate::stats::{Distribution, IntervalMetrics};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Version of the session file format, increased whenever older versions of NetMap can't read the files anymore:
+/// a record changes its shape or a new record or event type is added. New fields of existing records are optional
+/// instead, and files of older versions are migrated by [`SessionReader`] when they are read.
+///
+/// 2 added waypoints, 3 annotations, 4 stored the options of the run as `parameters` next to the `mode` name.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// First record of a session file, describes the run that the following records belong to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionHeader {
+    /// version of the format the file was written in
+    pub schema_version: u32,
+    /// version of NetMap that wrote the file
+    pub tool_version: String,
+    /// name of the host NetMap ran on
+    pub host: String,
+    /// seconds since the Unix epoch
+    pub start_time: f64,
+    /// mode of the run, e.g. `sender`
+    pub mode: String,
+    /// options of the run by name, e.g. `data_rate`, as they were when the file was written
+    ///
+    /// Kept apart from [`RunMode`] so files stay readable when the command line changes, see [`SessionHeader::run_mode`].
+    pub parameters: Map<String, Value>,
+    /// interface the traffic went over, `None` if it couldn't be determined
+    pub interface: Option<LinkInfo>,
+    /// where the positions came from, `None` for runs without positions
+    pub gps: Option<GpsSource>,
+}
+
+impl SessionHeader {
+    pub fn new(mode: RunMode, interface: Option<LinkInfo>, gps: Option<GpsSource>) -> Self {
+        // A mode serializes as a map with its name as the only key.
+        let (mode, parameters) = match serde_json::to_value(mode) {
+            Ok(Value::Object(mode)) => mode.into_iter().next(),
+            _ => None,
+        }
+        .expect("modes serialize as maps");
+        SessionHeader {
+            schema_version: SCHEMA_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            host: hostname(),
+            start_time: unix_time(),
+            mode,
+            parameters: match parameters {
+                Value::Object(parameters) => parameters,
+                _ => Map::new(),
+            },
+            interface,
+            gps,
+        }
+    }
+
+    /// The mode of the run with its options, if they are still understood by this version of NetMap.
+    /// Options that were added since the file was written get their defaults.
+    pub fn run_mode(&self) -> Option<RunMode> {
+        let mut mode = Map::new();
+        mode.insert(self.mode.clone(), Value::Object(self.parameters.clone()));
+        serde_json::from_value(Value::Object(mode)).ok()
+    }
+}
+
+impl fmt::Display for SessionHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.run_mode() {
+            Some(mode) => mode.fmt(f),
+            None => write!(
+                f,
+                "{} (recorded by NetMap {})",
+                self.mode, self.tool_version
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GpsSource {
+    pub mode: GpsMode,
+    pub device: String,
+}
+
+/// Last record of a completed session file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionFooter {
+    /// seconds since the Unix epoch
+    pub end_time: f64,
+    pub summary: SessionSummary,
+}
+
+impl SessionFooter {
+    pub fn new(summary: SessionSummary) -> Self {
+        SessionFooter {
+            end_time: unix_time(),
+            summary,
+        }
+    }
+}
+
+/// Totals over all records of a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionSummary {
+    pub intervals: u64,
+    /// measured time in seconds
+    pub duration: f64,
+    pub bytes: u64,
+    /// average throughput in Mbit/s
+    pub throughput: Option<f64>,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// loss in percent
+    pub loss: Option<f64>,
+    /// average round trip time in milliseconds
+    pub rtt: Option<f64>,
+    /// distribution of the throughput of the intervals in Mbit/s
+    #[serde(default)]
+    pub throughput_stats: Option<Distribution>,
+    /// distribution of the round trip time of the intervals in milliseconds
+    #[serde(default)]
+    pub rtt_stats: Option<Distribution>,
+    /// distribution of the loss of the intervals in percent
+    #[serde(default)]
+    pub loss_stats: Option<Distribution>,
+    pub gps_fixes: u64,
+    pub events: u64,
+    pub scans: u64,
+    #[serde(default)]
+    pub waypoints: u64,
+    /// notes added during the run, not counted in `events`
+    #[serde(default)]
+    pub annotations: u64,
+    /// aggregates of the intervals in each zone, by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<ZoneSummary>,
+}
+
+/// Aggregates of the intervals measured in one zone.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ZoneSummary {
+    pub name: String,
+    pub intervals: u64,
+    /// median throughput of the intervals in Mbit/s
+    pub throughput: f64,
+    /// 95th percentile of the round trip times in milliseconds
+    pub rtt_p95: Option<f64>,
+    /// loss over all packets in percent
+    pub loss: Option<f64>,
+}
+
+impl fmt::Display for ZoneSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} intervals {:.2} Mbit/s median",
+            self.name, self.intervals, self.throughput
+        )?;
+        if let Some(loss) = self.loss {
+            write!(f, " {:3.2}% loss", loss)?;
+        }
+        if let Some(rtt) = self.rtt_p95 {
+            write!(f, " {:.3}ms p95", rtt)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} intervals over {:.1}s: {:.2} Mbytes {:.2} Mbit/s {}/{} ({:3.2}%) {:.3}ms",
+            self.intervals,
+            self.duration,
+            self.bytes as f64 / 1000000.0,
+            self.throughput.unwrap_or_default(),
+            self.packets_received,
+            self.packets_sent,
+            self.loss.unwrap_or_default(),
+            self.rtt.unwrap_or_default()
+        )?;
+        for (metric, stats) in [
+            ("throughput (Mbit/s)", &self.throughput_stats),
+            ("rtt (ms)", &self.rtt_stats),
+            ("loss (%)", &self.loss_stats),
+        ] {
+            if let Some(stats) = stats {
+                write!(f, "\n  {}: {}", metric, stats)?;
+            }
+        }
+        for zone in &self.zones {
+            write!(f, "\n  {}", zone)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the summary while the records are written.
+#[derive(Debug, Default)]
+pub(crate) struct SummaryAccumulator {
+    summary: SessionSummary,
+    metrics: IntervalMetrics,
+    zones: BTreeMap<String, IntervalMetrics>,
+}
+
+impl SummaryAccumulator {
+    pub(crate) fn add(&mut self, record: &Record) {
+        let summary = &mut self.summary;
+        match record {
+            Record::Interval(interval) => {
+                summary.intervals += 1;
+                summary.duration += interval.to - interval.from;
+                summary.bytes += interval.bytes;
+                self.metrics.add(interval);
+                for zone in &interval.zones {
+                    self.zones.entry(zone.clone()).or_default().add(interval);
+                }
+            }
+            Record::GpsFix(_) => summary.gps_fixes += 1,
+            Record::Event(Event::Annotation(_)) => summary.annotations += 1,
+            Record::Event(_) => summary.events += 1,
+            Record::Scan(_) => summary.scans += 1,
+            Record::Waypoint(_) => summary.waypoints += 1,
+            Record::Header(_)
+            | Record::Probe(_)
+            | Record::Sweep(_)
+            | Record::Wmm(_)
+            | Record::Footer(_) => {}
+        }
+    }
+
+    pub(crate) fn summary(&self) -> SessionSummary {
+        let mut summary = self.summary.clone();
+        summary.throughput =
+            (summary.duration > 0.0).then(|| summary.bytes as f64 * 8.0 / summary.duration / 1e6);
+        summary.packets_sent = self.metrics.packets_sent;
+        summary.packets_received = self.metrics.packets_received;
+        summary.loss = self.metrics.total_loss();
+        summary.rtt = self.metrics.rtt.mean();
+        summary.throughput_stats = self.metrics.throughput.distribution();
+        summary.rtt_stats = self.metrics.rtt.distribution();
+        summary.loss_stats = self.metrics.loss.distribution();
+        summary.zones = self
+            .zones
+            .iter()
+            .map(|(name, metrics)| ZoneSummary {
+                name: name.clone(),
+                intervals: metrics.intervals(),
+                throughput: metrics.throughput.percentile(50.0).unwrap_or_default(),
+                rtt_p95: metrics.rtt.percentile(95.0),
+                loss: metrics.total_loss(),
+            })
+            .collect();
+        summary
+    }
+}
+
+/// Reads the records of a session file one by one, after checking its header.
+pub struct SessionReader<R> {
+    header: SessionHeader,
+    lines: io::Lines<R>,
+}
+
+impl SessionReader<BufReader<File>> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> SessionReader<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let first = lines
+            .next()
+            .ok_or_else(|| invalid_data("empty session file".to_string()))??;
+
+        // Check the version before parsing the rest of the header, its fields may have changed.
+        let header: Value = serde_json::from_str(&first)?;
+        if header["type"] != "header" {
+            return Err(invalid_data(
+                "session file doesn't start with a header".to_string(),
+            ));
+        }
+        let mut header = header;
+        match header["schema_version"].as_u64() {
+            Some(version) if version <= SCHEMA_VERSION as u64 => migrate(&mut header, version)?,
+            version => {
+                return Err(invalid_data(format!(
+                    "unsupported schema version {:?}, expected at most {}",
+                    version, SCHEMA_VERSION
+                )))
+            }
+        }
+        let header = match serde_json::from_value(header)? {
+            Record::Header(header) => header,
+            _ => unreachable!("the type was checked above"),
+        };
+        Ok(SessionReader { header, lines })
+    }
+
+    pub fn header(&self) -> &SessionHeader {
+        &self.header
+    }
+}
+
+impl<R: BufRead> Iterator for SessionReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(io::Error::from));
+        }
+    }
+}
+
+/// A session file loaded into memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub header: SessionHeader,
+    /// all records between header and footer
+    pub records: Vec<Record>,
+    /// `None` if the run was interrupted before the footer was written
+    pub footer: Option<SessionFooter>,
+}
+
+impl Session {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let reader = SessionReader::open(path)?;
+        let header = reader.header().clone();
+        let mut records = Vec::new();
+        let mut footer = None;
+        for record in reader {
+            match record? {
+                Record::Footer(last) => footer = Some(last),
+                record => records.push(record),
+            }
+        }
+        Ok(Session {
+            header,
+            records,
+            footer,
+        })
+    }
+
+    /// Summary of the footer, or of the records if the run was interrupted.
+    pub fn summary(&self) -> SessionSummary {
+        if let Some(footer) = &self.footer {
+            return footer.summary.clone();
+        }
+        let mut accumulator = SummaryAccumulator::default();
+        self.records
+            .iter()
+            .for_each(|record| accumulator.add(record));
+        accumulator.summary()
+    }
+
+    /// Notes added during the run, in the order they were written.
+    pub fn annotations(&self) -> impl Iterator<Item = &Annotation> {
+        self.records.iter().filter_map(|record| match record {
+            Record::Event(Event::Annotation(annotation)) => Some(annotation),
+            _ => None,
+        })
+    }
+}
+
+/// Brings the header of a file of an older `version` into the current shape.
+/// The other records only gained optional fields and types since.
+fn migrate(header: &mut Value, version: u64) -> io::Result<()> {
+    if version < 4 {
+        // The mode was stored as a map from its name to its options.
+        let (mode, parameters) = match header["mode"].take() {
+            Value::Object(mode) if mode.len() == 1 => mode.into_iter().next().unwrap(),
+            _ => return Err(invalid_data("header without a mode".to_string())),
+        };
+        header["mode"] = Value::String(mode);
+        header["parameters"] = parameters;
+    }
+    Ok(())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Protocol;
+    use crate::gps::Position;
+    use crate::record::{IntervalRecord, RecordWriter};
+    use std::io::Cursor;
+
+    fn receiver_mode() -> RunMode {
+        RunMode::Receiver {
+            port: 4321,
+            protocol: Protocol::Udp,
+            interface: None,
+            output_file: None,
+        }
+    }
+
+    #[test]
+    fn reads_written_session() {
+        let path =
+            std::env::temp_dir().join(format!("netmap-session-{}.jsonl", std::process::id()));
+        let output = RecordWriter::new(Some(&path)).unwrap();
+        let header = SessionHeader {
+            start_time: 1_680_350_400.0,
+            ..SessionHeader::new(receiver_mode(), None, None)
+        };
+        let interval = IntervalRecord {
+            position: Position::new("2023-04-01T12:00:00.000Z".to_string(), 52.5, 13.4),
+            bytes: 125_000,
+            throughput: 1.0,
+            packets_sent: Some(100),
+            packets_received: Some(99),
+            loss: Some(1.0),
+            rtt: Some(2.5),
+            ..IntervalRecord::empty(0.0)
+        };
+        output.write(header.clone()).unwrap();
+        output.write(interval.clone()).unwrap();
+        output.write(interval.position.clone()).unwrap();
+        let summary = output.finish().unwrap();
+
+        let session = Session::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(session.header, header);
+        assert_eq!(
+            session.records,
+            vec![
+                Record::Interval(interval.clone()),
+                Record::GpsFix(interval.position)
+            ]
+        );
+        assert_eq!(session.footer.unwrap().summary, summary);
+        assert_eq!(summary.intervals, 1);
+        assert_eq!(summary.gps_fixes, 1);
+        assert_eq!(summary.throughput, Some(1.0));
+        assert_eq!(summary.loss, Some(1.0));
+    }
+
+    #[test]
+    fn aggregates_per_zone() {
+        let mut accumulator = SummaryAccumulator::default();
+        for (throughput, rtt, zones) in [
+            (10.0, 2.0, vec!["lobby"]),
+            (20.0, 4.0, vec!["lobby", "ground floor"]),
+            (40.0, 30.0, vec!["lobby"]),
+            (5.0, 1.0, vec![]),
+        ] {
+            accumulator.add(&Record::Interval(IntervalRecord {
+                throughput,
+                packets_sent: Some(100),
+                packets_received: Some(99),
+                loss: Some(1.0),
+                rtt: Some(rtt),
+                zones: zones.into_iter().map(String::from).collect(),
+                ..IntervalRecord::empty(0.0)
+            }));
+        }
+        let summary = accumulator.summary();
+        assert_eq!(summary.intervals, 4);
+        let names: Vec<_> = summary.zones.iter().map(|zone| &zone.name[..]).collect();
+        assert_eq!(names, ["ground floor", "lobby"]);
+        let lobby = &summary.zones[1];
+        assert_eq!(lobby.intervals, 3);
+        // within the precision of the histogram
+        assert!((lobby.throughput - 20.0).abs() < 0.02);
+        assert_eq!(lobby.rtt_p95, Some(30.0));
+        assert_eq!(lobby.loss, Some(1.0));
+    }
+
+    #[test]
+    fn reads_header_of_older_version() {
+        // Written before the sender had --interpolate, --zones, --tui and --web, with an option removed since.
+        let contents = r#"{"type":"header","schema_version":1,"tool_version":"0.1.0","host":"laptop","start_time":1680350400.0,"mode":{"sender":{"port":1234,"target_ip":"192.168.1.10","target_port":4321,"data_rate":1.0,"packet_size":1500,"sleep_adjust":100,"protocol":"udp","dscp":null,"tos":null,"scan_interval":null,"interface":"wlan0","output_file":"walk.jsonl","gps_mode":"phone","gps_device":"/dev/USB0"}},"interface":null,"gps":{"mode":"phone","device":"/dev/USB0"}}
+"#;
+        let reader = SessionReader::new(Cursor::new(contents)).unwrap();
+        let header = reader.header();
+        assert_eq!(header.mode, "sender");
+        assert_eq!(header.parameters["sleep_adjust"], 100);
+        let Some(RunMode::Sender {
+            target,
+            recording,
+            tui,
+            web,
+            ..
+        }) = header.run_mode()
+        else {
+            panic!("not a sender: {:?}", header);
+        };
+        assert_eq!(target.target_port, 4321);
+        assert_eq!(recording.interpolate, None);
+        assert!(!tui);
+        assert_eq!(web, None);
+    }
+
+    #[test]
+    fn keeps_parameters_that_no_longer_parse() {
+        let mut header = SessionHeader::new(receiver_mode(), None, None);
+        header.parameters["protocol"] = "quic".into();
+        let contents = format!(
+            "{}\n",
+            serde_json::to_string(&Record::Header(header)).unwrap()
+        );
+        let reader = SessionReader::new(Cursor::new(contents)).unwrap();
+        assert_eq!(reader.header().parameters["protocol"], "quic");
+        assert_eq!(reader.header().run_mode(), None);
+        assert!(reader
+            .header()
+            .to_string()
+            .starts_with("receiver (recorded by NetMap"));
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let mut header = serde_json::to_value(Record::Header(SessionHeader::new(
+            receiver_mode(),
+            None,
+            None,
+        )))
+        .unwrap();
+        header["schema_version"] = (SCHEMA_VERSION + 1).into();
+        let contents = format!("{}\n", header);
+        let error = SessionReader::new(Cursor::new(contents)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,164 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// Value of the flag while the run goes on.
+const RUNNING: i32 = 0;
+/// Value of the flag after a stop that wasn't caused by a signal.
+const REQUESTED: i32 = -1;
+
+/// Signal shared by the tasks of a run that tells them to stop.
+/// The tasks wait for it next to their I/O, finish what they are doing and return.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // RUNNING, REQUESTED or the number of the signal that stopped the run
+    flag: AtomicI32,
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Triggers the shutdown on SIGINT (Ctrl-C) and SIGTERM.
+    /// Must be called from within a Tokio runtime.
+    pub fn on_signals() -> io::Result<Self> {
+        let shutdown = Self::new();
+        for kind in [SignalKind::interrupt(), SignalKind::terminate()] {
+            let mut signals = signal(kind)?;
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if signals.recv().await.is_some() {
+                    shutdown.stop(kind.as_raw_value());
+                }
+            });
+        }
+        Ok(shutdown)
+    }
+
+    /// Tells all tasks to stop, unless a signal already did.
+    pub fn trigger(&self) {
+        self.stop(REQUESTED);
+    }
+
+    fn stop(&self, reason: i32) {
+        let _ =
+            self.inner
+                .flag
+                .compare_exchange(RUNNING, reason, Ordering::SeqCst, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.inner.flag.load(Ordering::SeqCst) != RUNNING
+    }
+
+    /// The signal that stopped the run, if any.
+    pub fn signal(&self) -> Option<i32> {
+        match self.inner.flag.load(Ordering::SeqCst) {
+            RUNNING | REQUESTED => None,
+            signal => Some(signal),
+        }
+    }
+
+    /// Completes once the shutdown is triggered.
+    pub async fn wait(&self) {
+        let notified = self.inner.notify.notified();
+        tokio::pin!(notified);
+        // Register before checking the flag, so a trigger in between isn't missed.
+        notified.as_mut().enable();
+        if !self.is_triggered() {
+            notified.await;
+        }
+    }
+
+    /// Sleeps for `duration` or until the shutdown is triggered, whichever comes first.
+    /// Returns whether the shutdown was triggered.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = self.wait() => true,
+            _ = tokio::time::sleep(duration) => false,
+        }
+    }
+}
+
+/// Switch shared by the Sender and its controls that holds the traffic while it is paused.
+/// No intervals are recorded while paused.
+#[derive(Debug, Clone, Default)]
+pub struct Pause {
+    inner: Arc<PauseInner>,
+}
+
+#[derive(Debug, Default)]
+struct PauseInner {
+    paused: AtomicBool,
+    notify: Notify,
+}
+
+impl Pause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pause(&self) {
+        self.inner.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.inner.paused.store(false, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::SeqCst)
+    }
+
+    /// Completes once the traffic isn't paused.
+    pub async fn resumed(&self) {
+        let notified = self.inner.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_paused() {
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trigger_keeps_signal() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_triggered());
+
+        shutdown.stop(libc::SIGTERM);
+        shutdown.trigger();
+        assert!(shutdown.is_triggered());
+        assert_eq!(shutdown.signal(), Some(libc::SIGTERM));
+    }
+
+    #[tokio::test]
+    async fn trigger_wakes_waiting_tasks() {
+        let shutdown = Shutdown::new();
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.sleep(Duration::from_secs(10)).await }
+        });
+        tokio::task::yield_now().await;
+
+        shutdown.trigger();
+        assert!(waiting.await.unwrap());
+        assert_eq!(shutdown.signal(), None);
+    }
+}
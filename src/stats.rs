@@ -0,0 +1,567 @@
+//! Streaming statistics of interval metrics such as throughput, round trip time and loss.
+//!
+//! [`Welford`] keeps mean and variance, [`Histogram`] the distribution for percentiles and [`Ewma`] a smoothed value,
+//! each in constant memory per sample. [`Metric`] combines the first two into a [`Distribution`]
+//! for summaries and exports.
+
+use crate::record::IntervalRecord;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Confidence level of the intervals in a [`Distribution`].
+pub const CONFIDENCE: f64 = 0.95;
+
+/// Mean and variance of a stream of samples, updated with Welford's algorithm,
+/// which stays accurate where summing squares would cancel out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Welford {
+    count: u64,
+    mean: f64,
+    // sum of the squared differences from the mean
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Welford {
+    pub fn new() -> Self {
+        Welford::default()
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        if self.count == 1 {
+            (self.min, self.max) = (value, value);
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+    }
+
+    /// Adds all samples of `other`, as if they had been added one by one (Chan's parallel algorithm).
+    pub fn merge(&mut self, other: &Welford) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Sample variance, `None` below two samples.
+    pub fn variance(&self) -> Option<f64> {
+        (self.count > 1).then(|| self.m2 / (self.count - 1) as f64)
+    }
+
+    /// Sample standard deviation, `None` below two samples.
+    pub fn std_dev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Interval that contains the true mean with the probability `confidence`, e.g. 0.95,
+    /// from Student's t-distribution. `None` below two samples.
+    pub fn confidence_interval(&self, confidence: f64) -> Option<(f64, f64)> {
+        let std_dev = self.std_dev()?;
+        let t = student_t_quantile((1.0 + confidence) / 2.0, (self.count - 1) as f64);
+        let margin = t * std_dev / (self.count as f64).sqrt();
+        Some((self.mean - margin, self.mean + margin))
+    }
+}
+
+/// Exponentially weighted moving average, which follows recent samples and smooths out single outliers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Ewma {
+    /// weight of each new sample, from 0 to 1
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    pub fn new(alpha: f64) -> Self {
+        Ewma {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Average in which a sample's weight halves after `samples` further samples.
+    pub fn with_half_life(samples: f64) -> Self {
+        Ewma::new(1.0 - 0.5_f64.powf(1.0 / samples))
+    }
+
+    /// Adds `value` and returns the new average. The first sample is taken as it is.
+    pub fn add(&mut self, value: f64) -> f64 {
+        let average = match self.value {
+            Some(average) if value.is_finite() => average + self.alpha * (value - average),
+            Some(average) => average,
+            None => value,
+        };
+        self.value = Some(average);
+        average
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+/// Histogram with buckets of the same relative width (HDR histogram), so percentiles are accurate to
+/// `significant_digits` across all magnitudes, without keeping the samples.
+///
+/// Values are counted in multiples of `resolution`, the smallest difference that matters, e.g. 0.001 ms for
+/// round trip times. Negative values count as 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    resolution: f64,
+    // log2 of half the number of sub-buckets per bucket
+    sub_bucket_half_magnitude: u32,
+    counts: Vec<u64>,
+    total: u64,
+    // exact extremes, to which the percentiles are clamped
+    min: f64,
+    max: f64,
+}
+
+impl Histogram {
+    pub fn new(resolution: f64, significant_digits: u8) -> Self {
+        let sub_buckets = 2.0 * 10_f64.powi(significant_digits.clamp(1, 5) as i32);
+        Histogram {
+            resolution,
+            sub_bucket_half_magnitude: sub_buckets.log2().ceil() as u32 - 1,
+            counts: Vec::new(),
+            total: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        let index = self.index((value / self.resolution).round().max(0.0) as u64);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.total += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Adds the counts of `other`, which must have the same resolution and precision.
+    pub fn merge(&mut self, other: &Histogram) {
+        debug_assert_eq!(self.resolution, other.resolution);
+        debug_assert_eq!(
+            self.sub_bucket_half_magnitude,
+            other.sub_bucket_half_magnitude
+        );
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Value that `p` percent of the samples are at or below, `None` without samples.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((p / 100.0).clamp(0.0, 1.0) * self.total as f64)
+            .ceil()
+            .max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let value = self.highest_equivalent(index) as f64 * self.resolution;
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    fn index(&self, value: u64) -> usize {
+        let half = self.sub_bucket_half_magnitude;
+        let mask = (1u64 << (half + 1)) - 1;
+        let bucket = (64 - (value | mask).leading_zeros()) - (half + 1);
+        let sub_bucket = value >> bucket;
+        (((bucket as u64 + 1) << half) + sub_bucket - (1 << half)) as usize
+    }
+
+    /// Highest value, in multiples of the resolution, that counts towards `index`.
+    fn highest_equivalent(&self, index: usize) -> u64 {
+        let half = self.sub_bucket_half_magnitude;
+        let bucket = (index >> half) as i64 - 1;
+        let sub_bucket = (index as u64 & ((1 << half) - 1)) + (1 << half);
+        let (bucket, sub_bucket) = if bucket < 0 {
+            (0, sub_bucket - (1 << half))
+        } else {
+            (bucket as u32, sub_bucket)
+        };
+        ((sub_bucket + 1) << bucket) - 1
+    }
+}
+
+/// Mean, spread and percentiles of one metric.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    pub count: u64,
+    pub mean: f64,
+    /// `None` below two samples
+    pub std_dev: Option<f64>,
+    pub min: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// 95% confidence interval of the mean, `None` below two samples
+    pub ci95: Option<(f64, f64)>,
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mean {:.3}", self.mean)?;
+        if let Some((low, high)) = self.ci95 {
+            write!(f, " ({:.3}-{:.3})", low, high)?;
+        }
+        if let Some(std_dev) = self.std_dev {
+            write!(f, " sd {:.3}", std_dev)?;
+        }
+        write!(
+            f,
+            " min {:.3} p50 {:.3} p95 {:.3} p99 {:.3} max {:.3}",
+            self.min, self.p50, self.p95, self.p99, self.max
+        )
+    }
+}
+
+/// Streaming statistics of one metric: mean and variance, and percentiles from a histogram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    moments: Welford,
+    histogram: Histogram,
+}
+
+impl Metric {
+    /// Throughput in Mbit/s to the kbit/s.
+    pub fn throughput() -> Self {
+        Metric::new(0.001)
+    }
+
+    /// Round trip time in milliseconds to the microsecond.
+    pub fn rtt() -> Self {
+        Metric::new(0.001)
+    }
+
+    /// Loss in percent to the thousandth.
+    pub fn loss() -> Self {
+        Metric::new(0.001)
+    }
+
+    /// Metric with percentiles to three significant digits, in multiples of `resolution`.
+    pub fn new(resolution: f64) -> Self {
+        Metric {
+            moments: Welford::new(),
+            histogram: Histogram::new(resolution, 3),
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.moments.add(value);
+        self.histogram.add(value);
+    }
+
+    pub fn merge(&mut self, other: &Metric) {
+        self.moments.merge(&other.moments);
+        self.histogram.merge(&other.histogram);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.moments.count()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        self.moments.mean()
+    }
+
+    pub fn moments(&self) -> &Welford {
+        &self.moments
+    }
+
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.histogram.percentile(p)
+    }
+
+    /// `None` without samples.
+    pub fn distribution(&self) -> Option<Distribution> {
+        Some(Distribution {
+            count: self.moments.count(),
+            mean: self.moments.mean()?,
+            std_dev: self.moments.std_dev(),
+            min: self.moments.min()?,
+            max: self.moments.max()?,
+            p50: self.percentile(50.0)?,
+            p95: self.percentile(95.0)?,
+            p99: self.percentile(99.0)?,
+            ci95: self.moments.confidence_interval(CONFIDENCE),
+        })
+    }
+}
+
+/// Statistics of the throughput, round trip time and loss of a set of intervals, e.g. of a session or a location.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalMetrics {
+    /// throughput of each interval in Mbit/s
+    pub throughput: Metric,
+    /// mean round trip time of each interval in milliseconds
+    pub rtt: Metric,
+    /// loss of each interval in percent
+    pub loss: Metric,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+}
+
+impl Default for IntervalMetrics {
+    fn default() -> Self {
+        IntervalMetrics {
+            throughput: Metric::throughput(),
+            rtt: Metric::rtt(),
+            loss: Metric::loss(),
+            packets_sent: 0,
+            packets_received: 0,
+        }
+    }
+}
+
+impl IntervalMetrics {
+    pub fn add(&mut self, interval: &IntervalRecord) {
+        self.throughput.add(interval.throughput);
+        if let Some(rtt) = interval.rtt {
+            self.rtt.add(rtt);
+        }
+        if let Some(loss) = interval.loss {
+            self.loss.add(loss);
+        }
+        self.packets_sent += interval.packets_sent.unwrap_or_default() as u64;
+        self.packets_received += interval.packets_received.unwrap_or_default() as u64;
+    }
+
+    pub fn intervals(&self) -> u64 {
+        self.throughput.count()
+    }
+
+    /// Loss over all packets in percent, `None` if none were sent.
+    pub fn total_loss(&self) -> Option<f64> {
+        loss(self.packets_sent, self.packets_received)
+    }
+}
+
+/// Loss in percent of the packets sent, `None` if none were.
+pub fn loss(packets_sent: u64, packets_received: u64) -> Option<f64> {
+    (packets_sent > 0)
+        .then(|| packets_sent.saturating_sub(packets_received) as f64 / packets_sent as f64 * 100.0)
+}
+
+/// Quantile of the standard normal distribution at `p` (Acklam's approximation, relative error below 1.2e-9).
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Quantile of Student's t-distribution with `dof` degrees of freedom at `p`.
+/// Exact for one and two degrees of freedom, else a Cornish-Fisher expansion around the normal quantile,
+/// within 0.01 of the exact value.
+pub fn student_t_quantile(p: f64, dof: f64) -> f64 {
+    if dof <= 1.0 {
+        return (std::f64::consts::PI * (p - 0.5)).tan();
+    }
+    if dof <= 2.0 {
+        return (2.0 * p - 1.0) / (2.0 * p * (1.0 - p)).sqrt();
+    }
+    let z = normal_quantile(p);
+    let (z3, z5, z7, z9) = (z.powi(3), z.powi(5), z.powi(7), z.powi(9));
+    z + (z3 + z) / (4.0 * dof)
+        + (5.0 * z5 + 16.0 * z3 + 3.0 * z) / (96.0 * dof.powi(2))
+        + (3.0 * z7 + 19.0 * z5 + 17.0 * z3 - 15.0 * z) / (384.0 * dof.powi(3))
+        + (79.0 * z9 + 776.0 * z7 + 1482.0 * z5 - 1920.0 * z3 - 945.0 * z) / (92160.0 * dof.powi(4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn welford_matches_two_pass_and_merges() {
+        let samples = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let mut all = Welford::new();
+        samples.iter().for_each(|&x| all.add(x));
+        assert_eq!(all.mean(), Some(5.0));
+        assert!((all.variance().unwrap() - 32.0 / 7.0).abs() < 1e-12);
+        assert_eq!((all.min(), all.max()), (Some(2.0), Some(9.0)));
+
+        let (mut first, mut second) = (Welford::new(), Welford::new());
+        samples[..3].iter().for_each(|&x| first.add(x));
+        samples[3..].iter().for_each(|&x| second.add(x));
+        first.merge(&second);
+        assert_eq!(first.count(), 8);
+        assert!((first.mean().unwrap() - 5.0).abs() < 1e-12);
+        assert!((first.variance().unwrap() - all.variance().unwrap()).abs() < 1e-12);
+
+        // mean 5, t(0.975, 7) = 2.365
+        let (low, high) = all.confidence_interval(0.95).unwrap();
+        let margin = 2.365 * (32.0_f64 / 7.0).sqrt() / 8.0_f64.sqrt();
+        assert!((low - (5.0 - margin)).abs() < 0.01 && (high - (5.0 + margin)).abs() < 0.01);
+        assert_eq!(Welford::new().mean(), None);
+    }
+
+    #[test]
+    fn quantiles_match_tables() {
+        assert!((normal_quantile(0.975) - 1.959_964).abs() < 1e-6);
+        assert!((normal_quantile(0.5)).abs() < 1e-12);
+        assert!((normal_quantile(0.001) + 3.090_232).abs() < 1e-6);
+        for (dof, t) in [
+            (1.0, 12.706),
+            (2.0, 4.303),
+            (3.0, 3.182),
+            (5.0, 2.571),
+            (10.0, 2.228),
+            (30.0, 2.042),
+        ] {
+            assert!((student_t_quantile(0.975, dof) - t).abs() < 0.01, "{}", dof);
+        }
+    }
+
+    #[test]
+    fn histogram_percentiles_are_within_precision() {
+        let mut histogram = Histogram::new(0.001, 3);
+        let mut samples: Vec<f64> = (1..=10_000).map(|i| i as f64 * 0.37).collect();
+        samples.iter().for_each(|&x| histogram.add(x));
+        samples.sort_by(f64::total_cmp);
+        for p in [1.0, 50.0, 90.0, 95.0, 99.0, 99.9] {
+            let exact = samples[(p / 100.0 * samples.len() as f64).ceil() as usize - 1];
+            let estimate = histogram.percentile(p).unwrap();
+            assert!(
+                (estimate / exact - 1.0).abs() < 1e-3,
+                "p{}: {} {}",
+                p,
+                estimate,
+                exact
+            );
+        }
+        assert_eq!(histogram.percentile(100.0), Some(3700.0));
+        assert_eq!(histogram.percentile(0.0), Some(0.37));
+
+        let mut small = Histogram::new(1.0, 2);
+        [0.0, 0.0, 1.0, 3.0].iter().for_each(|&x| small.add(x));
+        assert_eq!(small.percentile(50.0), Some(0.0));
+        assert_eq!(small.percentile(75.0), Some(1.0));
+        small.merge(&histogram_of(&[1e6]));
+        assert_eq!(small.count(), 5);
+        assert!((small.percentile(100.0).unwrap() / 1e6 - 1.0).abs() < 1e-2);
+        assert_eq!(Histogram::new(1.0, 2).percentile(50.0), None);
+    }
+
+    fn histogram_of(samples: &[f64]) -> Histogram {
+        let mut histogram = Histogram::new(1.0, 2);
+        samples.iter().for_each(|&x| histogram.add(x));
+        histogram
+    }
+
+    #[test]
+    fn ewma_follows_with_half_life() {
+        let mut ewma = Ewma::with_half_life(1.0);
+        assert_eq!(ewma.add(10.0), 10.0);
+        assert_eq!(ewma.add(20.0), 15.0);
+        assert_eq!(ewma.add(f64::NAN), 15.0);
+        assert_eq!(ewma.value(), Some(15.0));
+    }
+}
@@ -0,0 +1,771 @@
+//! Live web dashboard of a Sender, for following and controlling a run from a phone.
+//!
+//! A minimal HTTP server: `GET /` serves the page, `GET /events` streams the records as server-sent events,
+//! `POST /start` and `POST /stop` resume and pause the traffic, `POST /mark` marks the current location
+//! as a waypoint and `POST /annotate` adds the text of the body as an annotation.
+//! Everything but the page needs the random token of the server, which [`WebServer::url`] carries to the page,
+//! so other sites open in the same browser can't control the run or follow it.
+
+use crate::annotation::Annotator;
+use crate::clients::{Waypoint, WaypointTrigger};
+use crate::error::NetMapError;
+use crate::gps::Position;
+use crate::record::{Record, RecordWriter};
+use crate::shutdown::{Pause, Shutdown};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Records kept for pages that connect later, about an hour of intervals.
+const HISTORY: usize = 4000;
+/// Messages buffered for a slow page before it misses some.
+const BACKLOG: usize = 256;
+/// Longest request body that is read.
+const MAX_BODY: usize = 64 * 1024;
+/// Longest request or header line that is read.
+const MAX_LINE: usize = 8 * 1024;
+/// Most header lines that a request may have.
+const MAX_HEADERS: usize = 64;
+/// Header in which the page sends the token with the controls.
+const TOKEN_HEADER: &str = "x-netmap-token";
+
+/// Serves the dashboard of a run on a local address.
+pub struct WebServer {
+    listener: TcpListener,
+    output: RecordWriter,
+    history: Arc<Mutex<History>>,
+    position: Arc<Mutex<Position>>,
+    annotator: Annotator,
+    pause: Pause,
+    shutdown: Shutdown,
+    marks: AtomicU32,
+    token: String,
+}
+
+/// Messages that a page gets when it connects, followed by the live ones.
+struct History {
+    header: Option<String>,
+    /// the last fix, as the current position
+    fix: Option<String>,
+    messages: VecDeque<String>,
+    live: broadcast::Sender<String>,
+}
+
+impl History {
+    fn publish(&mut self, message: String) {
+        self.messages.push_back(message.clone());
+        if self.messages.len() > HISTORY {
+            self.messages.pop_front();
+        }
+        // No page may be connected.
+        let _ = self.live.send(message);
+    }
+}
+
+impl WebServer {
+    /// Listens on `address` and collects the records written to `output` from now on.
+    /// Must be called from within a Tokio runtime.
+    pub fn new(address: SocketAddr, output: &RecordWriter) -> Result<Self, NetMapError> {
+        let listener = std::net::TcpListener::bind(address)
+            .map_err(|source| NetMapError::Bind { address, source })?;
+        listener.set_nonblocking(true)?;
+        let history = Arc::new(Mutex::new(History {
+            header: None,
+            fix: None,
+            messages: VecDeque::new(),
+            live: broadcast::channel(BACKLOG).0,
+        }));
+        output.on_record({
+            let history = Arc::clone(&history);
+            move |record| {
+                let Ok(message) = serde_json::to_string(record) else {
+                    return;
+                };
+                let mut history = history.lock().unwrap();
+                match record {
+                    Record::Header(_) => history.header = Some(message),
+                    // Only the current position matters, the trail is drawn from the intervals.
+                    Record::GpsFix(_) => {
+                        history.fix = Some(message.clone());
+                        let _ = history.live.send(message);
+                    }
+                    Record::Scan(_) => {
+                        let _ = history.live.send(message);
+                    }
+                    _ => history.publish(message),
+                }
+            }
+        });
+        let position = Arc::new(Mutex::new(Position::default()));
+        Ok(WebServer {
+            listener: TcpListener::from_std(listener)?,
+            output: output.clone(),
+            history,
+            annotator: Annotator::new(output.clone(), Arc::clone(&position)),
+            position,
+            pause: Pause::new(),
+            shutdown: Shutdown::new(),
+            marks: AtomicU32::new(0),
+            token: new_token()?,
+        })
+    }
+
+    /// Address the server listens on, with the actual port if it was chosen by the system.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Address of the page with the token that lets it control and follow the run.
+    pub fn url(&self) -> io::Result<String> {
+        Ok(format!(
+            "http://{}/?token={}",
+            self.local_addr()?,
+            self.token
+        ))
+    }
+
+    /// Tags the marks with the position kept up to date by a GPS source.
+    pub fn set_position(&mut self, position: Arc<Mutex<Position>>) {
+        self.position = position;
+    }
+
+    /// Writes the notes of the page with `annotator`, so they are timed and placed like the ones from elsewhere.
+    pub fn set_annotator(&mut self, annotator: Annotator) {
+        self.annotator = annotator;
+    }
+
+    /// Stops and starts the traffic with `pause`, which the Sender has to hold its traffic with.
+    pub fn set_pause(&mut self, pause: Pause) {
+        self.pause = pause;
+    }
+
+    /// Stops serving once `shutdown` is triggered.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    pub fn run(self) -> JoinHandle<Result<(), NetMapError>> {
+        tokio::spawn(async move {
+            info!("Serving the dashboard on {}", self.url()?);
+            let server = Arc::new(self);
+            loop {
+                let stream = tokio::select! {
+                    accepted = server.listener.accept() => accepted?.0,
+                    _ = server.shutdown.wait() => return Ok(()),
+                };
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    if let Err(e) = server.serve(stream).await {
+                        debug!("Couldn't serve request: {}", e);
+                    }
+                });
+            }
+        })
+    }
+
+    /// Answers a single request, connections aren't kept alive.
+    async fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        let (reader, mut writer) = stream.split();
+        let mut reader = BufReader::new(reader);
+        let request = match Request::read(&mut reader).await {
+            Ok(request) => request,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return respond(
+                    &mut writer,
+                    "431 Request Header Fields Too Large",
+                    "text/plain",
+                    &format!("{}\n", e),
+                )
+                .await;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let authorized = request
+            .token
+            .as_deref()
+            .is_some_and(|token| same_secret(token, &self.token));
+        let (status, content_type, body) = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+            ("GET", "/events") | ("POST", _) if !authorized => {
+                debug!(
+                    "Refused {} {} without the token",
+                    request.method, request.path
+                );
+                (
+                    "403 Forbidden",
+                    "text/plain",
+                    "token missing or wrong\n".to_string(),
+                )
+            }
+            ("GET", "/events") => return self.events(&mut writer).await,
+            ("POST", "/start") => {
+                info!("Resumed from the dashboard");
+                self.pause.resume();
+                self.publish_state();
+                ("200 OK", "application/json", self.state())
+            }
+            ("POST", "/stop") => {
+                info!("Paused from the dashboard");
+                self.pause.pause();
+                self.publish_state();
+                ("200 OK", "application/json", self.state())
+            }
+            ("POST", "/mark") => {
+                let waypoint = self.mark();
+                (
+                    "200 OK",
+                    "application/json",
+                    serde_json::to_string(&waypoint).unwrap_or_default(),
+                )
+            }
+            ("POST", "/annotate") => {
+                match self
+                    .annotator
+                    .annotate(&String::from_utf8_lossy(&request.body))
+                {
+                    Ok(Some(annotation)) => (
+                        "200 OK",
+                        "application/json",
+                        serde_json::to_string(&annotation).unwrap_or_default(),
+                    ),
+                    Ok(None) => ("400 Bad Request", "text/plain", "empty note\n".to_string()),
+                    Err(e) => {
+                        warn!("Couldn't write annotation: {}", e);
+                        (
+                            "500 Internal Server Error",
+                            "text/plain",
+                            format!("{}\n", e),
+                        )
+                    }
+                }
+            }
+            ("GET" | "POST", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "method not allowed\n".to_string(),
+            ),
+        };
+        respond(&mut writer, status, content_type, &body).await
+    }
+
+    /// Streams the state, the history and then every new record until the page or the run goes away.
+    async fn events(&self, writer: &mut (impl AsyncWriteExt + Unpin)) -> io::Result<()> {
+        writer
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n\
+                  Connection: close\r\n\r\n",
+            )
+            .await?;
+        // Subscribing with the history locked, so no record is missed or sent twice.
+        let (backlog, mut live) = {
+            let history = self.history.lock().unwrap();
+            let backlog: Vec<String> = history
+                .header
+                .iter()
+                .chain(&history.messages)
+                .chain(&history.fix)
+                .cloned()
+                .collect();
+            (backlog, history.live.subscribe())
+        };
+        send_event(writer, &self.state()).await?;
+        for message in &backlog {
+            send_event(writer, message).await?;
+        }
+        loop {
+            let message = tokio::select! {
+                message = live.recv() => message,
+                _ = self.shutdown.wait() => return Ok(()),
+            };
+            match message {
+                Ok(message) => send_event(writer, &message).await?,
+                Err(RecvError::Lagged(missed)) => debug!("A page missed {} records", missed),
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    fn state(&self) -> String {
+        json!({"type": "state", "paused": self.pause.is_paused()}).to_string()
+    }
+
+    fn publish_state(&self) {
+        let _ = self.history.lock().unwrap().live.send(self.state());
+    }
+
+    /// Writes the current position as a waypoint marked by the surveyor.
+    fn mark(&self) -> Waypoint {
+        let waypoint = Waypoint {
+            id: self.marks.fetch_add(1, Ordering::SeqCst) + 1,
+            trigger: WaypointTrigger::Mark,
+            position: self.position.lock().unwrap().clone(),
+        };
+        info!("{}", waypoint);
+        if let Err(e) = self.output.write(waypoint.clone()) {
+            warn!("Couldn't write waypoint: {}", e);
+        }
+        waypoint
+    }
+}
+
+/// The parts of a request that the server looks at.
+struct Request {
+    method: String,
+    /// target without the query string
+    path: String,
+    /// from the token header, or from the query string for the event stream, which can't send headers
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads the request line, the headers and the body, fails with `InvalidData` for overlong headers.
+    async fn read(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Self> {
+        let request_line = read_line(reader).await?;
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut token = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .map(str::to_string);
+
+        let mut content_length = 0;
+        let mut headers = 0;
+        loop {
+            let line = read_line(reader).await?;
+            if line.trim().is_empty() {
+                break;
+            }
+            headers += 1;
+            if headers > MAX_HEADERS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("more than {} header lines", MAX_HEADERS),
+                ));
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                } else if name.eq_ignore_ascii_case(TOKEN_HEADER) {
+                    token = Some(value.trim().to_string());
+                }
+            }
+        }
+        let mut body = vec![0; content_length.min(MAX_BODY)];
+        reader.read_exact(&mut body).await?;
+        Ok(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            token,
+            body,
+        })
+    }
+}
+
+/// Reads a line of at most `MAX_LINE` bytes, an empty one at the end of the stream.
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<String> {
+    let mut line = String::new();
+    let read = reader.take(MAX_LINE as u64).read_line(&mut line).await?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("header line longer than {} bytes", MAX_LINE),
+        ));
+    }
+    Ok(line)
+}
+
+async fn respond(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    writer.write_all(response.as_bytes()).await
+}
+
+/// Random secret of 128 bits in hex.
+fn new_token() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Compares the token without stopping at the first difference, so the time taken doesn't give it away.
+fn same_secret(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+async fn send_event(writer: &mut (impl AsyncWriteExt + Unpin), message: &str) -> io::Result<()> {
+    writer
+        .write_all(format!("data: {}\n\n", message).as_bytes())
+        .await
+}
+
+/// The dashboard, which follows `/events` and draws the breadcrumb trail on a canvas.
+const PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>NetMap</title>
+<style>
+body { font-family: sans-serif; margin: 0.5em; }
+h1 { font-size: 1.2em; margin: 0.2em 0; }
+button { font-size: 1.1em; padding: 0.5em 1em; margin: 0.2em 0.2em 0.2em 0; }
+#state { font-weight: bold; }
+.paused { color: #c00; }
+.running { color: #080; }
+.tiles { display: grid; grid-template-columns: repeat(auto-fill, minmax(9em, 1fr)); gap: 0.4em; margin: 0.5em 0; }
+.tile { border: 1px solid #ccc; border-radius: 4px; padding: 0.3em 0.5em; }
+.tile span { display: block; font-size: 1.3em; }
+canvas { border: 1px solid #ccc; width: 100%; max-width: 900px; }
+#warnings { color: #c00; }
+#note input { font-size: 1.1em; padding: 0.4em; }
+#log { font-size: 0.85em; color: #444; }
+</style>
+</head>
+<body>
+<h1 id="title">NetMap</h1>
+<p><span id="state">connecting</span>
+<button onclick="control('start')">Start</button>
+<button onclick="control('stop')">Stop</button>
+<button onclick="control('mark')">Mark</button></p>
+<form id="note"><input id="text" placeholder="Note, e.g. door closed" size="30"> <button>Add note</button></form>
+<div class="tiles">
+<div class="tile">Throughput<span id="throughput">-</span></div>
+<div class="tile">Round trip time<span id="rtt">-</span></div>
+<div class="tile">Loss<span id="loss">-</span></div>
+<div class="tile">Signal<span id="signal">-</span></div>
+<div class="tile">Position<span id="position">-</span></div>
+<div class="tile">Intervals<span id="intervals">0</span></div>
+</div>
+<p id="warnings"></p>
+<canvas id="trail" width="900" height="600"></canvas>
+<ul id="log"></ul>
+<script>
+const trail = [], marks = [], notes = [];
+let fix = null, last = null, intervals = 0, lastFixTime = 0, lastIntervalTime = 0;
+// The server hands out the page with its token, which every request but the page itself needs.
+const token = new URLSearchParams(location.search).get("token") || "";
+
+function post(path, body) {
+  return fetch(path, { method: "POST", headers: { "X-NetMap-Token": token }, body });
+}
+function control(action) {
+  post("/" + action).catch(() => {});
+}
+
+document.getElementById("note").onsubmit = event => {
+  event.preventDefault();
+  const text = document.getElementById("text");
+  if (!text.value.trim()) return;
+  post("/annotate", text.value).then(() => { text.value = ""; }).catch(() => {});
+};
+
+function log(text) {
+  const item = document.createElement("li");
+  item.textContent = text;
+  const list = document.getElementById("log");
+  list.insertBefore(item, list.firstChild);
+  while (list.children.length > 20) list.removeChild(list.lastChild);
+}
+
+function coordinates(position) {
+  if (!position || !position.time) return null;
+  if (position.indoor) return { x: position.indoor.x, y: position.indoor.y, floor: position.indoor.floor };
+  return { lat: position.lat, lon: position.lon };
+}
+
+// From red for the lowest to green for the highest throughput.
+function color(value, min, max) {
+  const q = max > min ? (value - min) / (max - min) : 1;
+  return `hsl(${Math.round(120 * q)}, 80%, 45%)`;
+}
+
+function draw() {
+  const canvas = document.getElementById("trail"), ctx = canvas.getContext("2d");
+  ctx.clearRect(0, 0, canvas.width, canvas.height);
+  const current = coordinates(fix);
+  const all = trail.map(p => p.at).concat(marks).concat(notes).concat(current ? [current] : []);
+  if (!all.length) return;
+  // Meters on the floor plan with y down, else an equirectangular projection around the first point.
+  const indoor = all[0].floor !== undefined;
+  const scaleLon = indoor ? 1 : Math.cos(all[0].lat * Math.PI / 180);
+  const world = p => indoor ? [p.x, p.y] : [p.lon * scaleLon * 111320, -p.lat * 110540];
+  const points = all.filter(p => (p.floor !== undefined) === indoor).map(world);
+  const xs = points.map(p => p[0]), ys = points.map(p => p[1]);
+  const x0 = Math.min(...xs), x1 = Math.max(...xs), y0 = Math.min(...ys), y1 = Math.max(...ys);
+  const margin = 20;
+  const scale = Math.min((canvas.width - 2 * margin) / Math.max(x1 - x0, 10),
+                         (canvas.height - 2 * margin) / Math.max(y1 - y0, 10));
+  const screen = p => {
+    const [x, y] = world(p);
+    return [canvas.width / 2 + (x - (x0 + x1) / 2) * scale, canvas.height / 2 + (y - (y0 + y1) / 2) * scale];
+  };
+  const values = trail.map(p => p.throughput);
+  const min = Math.min(...values), max = Math.max(...values);
+  for (const p of trail) {
+    if ((p.at.floor !== undefined) !== indoor) continue;
+    const [sx, sy] = screen(p.at);
+    ctx.fillStyle = color(p.throughput, min, max);
+    ctx.beginPath(); ctx.arc(sx, sy, 5, 0, 2 * Math.PI); ctx.fill();
+  }
+  ctx.fillStyle = "#000";
+  for (const p of marks) {
+    const [sx, sy] = screen(p);
+    ctx.beginPath();
+    ctx.moveTo(sx, sy - 7); ctx.lineTo(sx + 7, sy); ctx.lineTo(sx, sy + 7); ctx.lineTo(sx - 7, sy);
+    ctx.fill();
+  }
+  // Notes as blue squares with their text.
+  for (const p of notes) {
+    if ((p.floor !== undefined) !== indoor) continue;
+    const [sx, sy] = screen(p);
+    ctx.fillStyle = "#06f";
+    ctx.fillRect(sx - 5, sy - 5, 10, 10);
+    ctx.fillStyle = "#000";
+    ctx.fillText(p.text, sx + 8, sy + 4);
+  }
+  if (current && (current.floor !== undefined) === indoor) {
+    const [sx, sy] = screen(current);
+    ctx.strokeStyle = "#06f";
+    ctx.lineWidth = 3;
+    ctx.beginPath(); ctx.arc(sx, sy, 9, 0, 2 * Math.PI); ctx.stroke();
+    ctx.lineWidth = 1;
+  }
+  ctx.fillStyle = "#666";
+  ctx.fillText(`${(canvas.width / scale).toFixed(0)} m across`, 4, canvas.height - 4);
+}
+
+function warnings() {
+  const now = Date.now(), list = [];
+  if (!fix) list.push("no GPS fix");
+  else if (!fix.indoor && now - lastFixTime > 5000) list.push(`no GPS fix for ${Math.round((now - lastFixTime) / 1000)}s`);
+  if (fix && fix.accuracy > 25) list.push(`GPS accuracy ±${fix.accuracy.toFixed(0)} m`);
+  if (last && last.loss > 5) list.push(`loss ${last.loss.toFixed(1)}% > 5%`);
+  if (lastIntervalTime && now - lastIntervalTime > 3000 && !document.getElementById("state").classList.contains("paused"))
+    list.push(`no intervals for ${Math.round((now - lastIntervalTime) / 1000)}s`);
+  document.getElementById("warnings").textContent = list.join(" | ");
+}
+
+function show(id, text) {
+  document.getElementById(id).textContent = text;
+}
+
+function update(record) {
+  switch (record.type) {
+    case "state": {
+      const state = document.getElementById("state");
+      state.textContent = record.paused ? "paused" : "running";
+      state.className = record.paused ? "paused" : "running";
+      break;
+    }
+    case "header":
+      show("title", "NetMap " + (record.host || ""));
+      break;
+    case "interval": {
+      last = record;
+      intervals += 1;
+      lastIntervalTime = Date.now();
+      const at = coordinates(record.position);
+      if (at) trail.push({ at, throughput: record.throughput });
+      show("throughput", `${record.throughput.toFixed(2)} Mbit/s`);
+      show("rtt", record.rtt == null ? "-" : `${record.rtt.toFixed(2)} ms`);
+      show("loss", record.loss == null ? "-" : `${record.loss.toFixed(2)}%`);
+      const signal = record.wireless && record.wireless.signal;
+      show("signal", signal == null ? "-" : `${signal} dBm`);
+      show("intervals", intervals);
+      break;
+    }
+    case "gps_fix":
+      fix = record;
+      lastFixTime = Date.now();
+      show("position", record.indoor
+        ? `${record.indoor.x.toFixed(1)}, ${record.indoor.y.toFixed(1)} m`
+        : `${record.lat.toFixed(5)}, ${record.lon.toFixed(5)}` + (record.accuracy == null ? "" : ` ±${record.accuracy.toFixed(0)} m`));
+      break;
+    case "waypoint": {
+      const at = coordinates(record.position);
+      if (at) marks.push(at);
+      log(`waypoint ${record.id} (${record.trigger})`);
+      break;
+    }
+    case "event":
+      if (record.event === "roam") log(`${record.time.toFixed(1)}s roamed from ${record.from_bssid} to ${record.to_bssid}`);
+      if (record.event === "annotation") {
+        const at = coordinates(record.position);
+        if (at) notes.push({ ...at, text: record.text });
+        log(`${record.time.toFixed(1)}s note: ${record.text}`);
+      }
+      break;
+  }
+}
+
+const events = new EventSource("/events?token=" + encodeURIComponent(token));
+events.onmessage = message => { update(JSON.parse(message.data)); draw(); warnings(); };
+events.onerror = () => { show("state", "disconnected"); document.getElementById("state").className = "paused"; };
+setInterval(warnings, 1000);
+</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::record::Event;
+
+    /// Sends `request` as it is and returns the whole response.
+    async fn request(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Starts a server with the pause and the shutdown it controls.
+    fn serve(output: &RecordWriter) -> (SocketAddr, String, Pause, Shutdown) {
+        let mut server = WebServer::new("127.0.0.1:0".parse().unwrap(), output).unwrap();
+        let address = server.local_addr().unwrap();
+        let token = server.token.clone();
+        let (pause, shutdown) = (Pause::new(), Shutdown::new());
+        server.set_pause(pause.clone());
+        server.set_shutdown(shutdown.clone());
+        let _running = server.run();
+        (address, token, pause, shutdown)
+    }
+
+    #[tokio::test]
+    async fn pauses_marks_and_annotates() {
+        let output = RecordWriter::default();
+        let records = output.subscribe();
+        let (address, token, pause, shutdown) = serve(&output);
+        let post = |path: &str, body: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\nX-NetMap-Token: {}\r\nContent-Length: {}\r\n\r\n{}",
+                path,
+                token,
+                body.len(),
+                body
+            )
+        };
+
+        let response = request(address, &post("/stop", "")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(r#"{"paused":true,"type":"state"}"#));
+        assert!(pause.is_paused());
+        request(address, &post("/start", "")).await;
+        assert!(!pause.is_paused());
+
+        let response = request(address, &post("/mark", "")).await;
+        assert!(response.contains(r#""trigger":"mark""#));
+        match records.try_recv().unwrap() {
+            Record::Waypoint(waypoint) => assert_eq!(waypoint.id, 1),
+            record => panic!("not a waypoint: {:?}", record),
+        }
+
+        let response = request(address, &post("/annotate", "microwave on")).await;
+        assert!(response.contains(r#""text":"microwave on""#));
+        match records.try_recv().unwrap() {
+            Record::Event(Event::Annotation(annotation)) => {
+                assert_eq!(annotation.text, "microwave on")
+            }
+            record => panic!("not an annotation: {:?}", record),
+        }
+        assert!(request(address, &post("/annotate", ""))
+            .await
+            .starts_with("HTTP/1.1 400"));
+        assert!(request(address, &post("/missing", ""))
+            .await
+            .starts_with("HTTP/1.1 404"));
+        assert!(request(address, "GET / HTTP/1.1\r\n\r\n")
+            .await
+            .contains("EventSource"));
+
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_without_the_token() {
+        let output = RecordWriter::default();
+        let (address, token, pause, shutdown) = serve(&output);
+
+        // A form of another site can post, but doesn't know the token.
+        for stop in [
+            "POST /stop HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n".to_string(),
+            "POST /stop?token=guess HTTP/1.1\r\n\r\n".to_string(),
+            format!("POST /stop HTTP/1.1\r\nX-NetMap-Token: {}0\r\n\r\n", token),
+        ] {
+            assert!(request(address, &stop).await.starts_with("HTTP/1.1 403"));
+        }
+        assert!(!pause.is_paused());
+        assert!(request(address, "GET /events HTTP/1.1\r\n\r\n")
+            .await
+            .starts_with("HTTP/1.1 403"));
+
+        let events = format!("GET /events?token={} HTTP/1.1\r\n\r\n", token);
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(events.as_bytes()).await.unwrap();
+        let mut response = vec![0; 256];
+        let read = stream.read(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response[..read]).starts_with("HTTP/1.1 200 OK"));
+
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn limits_the_header() {
+        let output = RecordWriter::default();
+        let (address, _token, _pause, shutdown) = serve(&output);
+
+        let long_line = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        assert!(request(address, &long_line)
+            .await
+            .starts_with("HTTP/1.1 431"));
+        let many_lines = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Padding: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(request(address, &many_lines)
+            .await
+            .starts_with("HTTP/1.1 431"));
+        let enough_lines = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Padding: a\r\n".repeat(MAX_HEADERS)
+        );
+        assert!(request(address, &enough_lines)
+            .await
+            .starts_with("HTTP/1.1 200"));
+
+        shutdown.trigger();
+    }
+}
@@ -0,0 +1,344 @@
+mod scan;
+
+pub use scan::{ScanRecord, Scanner};
+
+use crate::shutdown::Shutdown;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+use tracing::{debug, info};
+
+/// Interval at which the link statistics are sampled, matching the logging interval of the Senders.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Statistics of a wireless link as reported by nl80211 (through `iw`) or `/proc/net/wireless`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct WirelessStats {
+    pub bssid: Option<String>,
+    pub ssid: Option<String>,
+    /// in MHz
+    pub frequency: Option<u32>,
+    /// in MHz
+    pub channel_width: Option<u32>,
+    /// in dBm
+    pub signal: Option<i32>,
+    /// in dBm
+    pub noise: Option<i32>,
+    /// in Mbit/s
+    pub tx_bitrate: Option<f64>,
+    /// in Mbit/s
+    pub rx_bitrate: Option<f64>,
+    pub tx_mcs: Option<u8>,
+    pub rx_mcs: Option<u8>,
+    /// retransmissions since the previous sample
+    pub tx_retries: Option<u64>,
+    /// frames that failed to be sent since the previous sample
+    pub tx_failed: Option<u64>,
+}
+
+impl WirelessStats {
+    /// Parses the output of `iw dev <interface> link`, `None` if not connected.
+    pub fn parse_link(output: &str) -> Option<Self> {
+        let mut lines = output.lines();
+        let bssid = lines
+            .next()?
+            .strip_prefix("Connected to ")?
+            .split_whitespace()
+            .next()?
+            .to_string();
+
+        let mut stats = WirelessStats {
+            bssid: Some(bssid),
+            ..Default::default()
+        };
+        for (key, value) in lines.filter_map(Self::split_field) {
+            match key {
+                "SSID" => stats.ssid = Some(value.to_string()),
+                "freq" => stats.frequency = value.parse().ok(),
+                "signal" => stats.signal = Self::parse_dbm(value),
+                "tx bitrate" => stats.merge_bitrate(value, true),
+                "rx bitrate" => stats.merge_bitrate(value, false),
+                _ => {}
+            }
+        }
+        Some(stats)
+    }
+
+    /// Adds the retry counters of the associated station from the output of `iw dev <interface> station dump`.
+    /// The counters are cumulative, see [`WirelessSampler`] for the per sample values.
+    pub fn merge_station_dump(&mut self, output: &str) {
+        let mut in_station = false;
+        for line in output.lines() {
+            if let Some(station) = line.strip_prefix("Station ") {
+                let mac = station.split_whitespace().next();
+                in_station = self.bssid.is_none() || mac == self.bssid.as_deref();
+                continue;
+            }
+            if !in_station {
+                continue;
+            }
+            match Self::split_field(line) {
+                Some(("tx retries", value)) => self.tx_retries = value.parse().ok(),
+                Some(("tx failed", value)) => self.tx_failed = value.parse().ok(),
+                Some(("signal", value)) if self.signal.is_none() => {
+                    self.signal = Self::parse_dbm(value)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds the noise floor of the channel in use from the output of `iw dev <interface> survey dump`.
+    pub fn merge_survey_dump(&mut self, output: &str) {
+        let mut in_use = false;
+        for line in output.lines() {
+            if line.starts_with("Survey data") {
+                in_use = false;
+                continue;
+            }
+            match Self::split_field(line) {
+                Some(("frequency", value)) => in_use = value.ends_with("[in use]"),
+                Some(("noise", value)) if in_use => self.noise = Self::parse_dbm(value),
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds the channel width from the output of `iw dev <interface> info`.
+    pub fn merge_info(&mut self, output: &str) {
+        let channel = output
+            .lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix("channel "));
+        let width = channel
+            .and_then(|channel| channel.split("width: ").nth(1))
+            .and_then(|width| width.split_whitespace().next())
+            .and_then(|width| width.parse().ok());
+        if width.is_some() {
+            self.channel_width = width;
+        }
+    }
+
+    /// Parses the line of `interface` in `/proc/net/wireless`, the fallback for drivers without nl80211.
+    pub fn parse_proc(contents: &str, interface: &str) -> Option<Self> {
+        let line = contents.lines().skip(2).find(|line| {
+            line.trim_start()
+                .strip_prefix(interface)
+                .is_some_and(|rest| rest.starts_with(':'))
+        })?;
+        let columns: Vec<&str> = line.split(':').nth(1)?.split_whitespace().collect();
+        // status, link quality, signal level, noise level, ...
+        let level = |column: usize| -> Option<i32> {
+            let value = columns
+                .get(column)?
+                .trim_end_matches('.')
+                .parse::<i32>()
+                .ok()?;
+            // -256 marks a value the driver doesn't report
+            (value != -256 && value != 0).then_some(value)
+        };
+
+        Some(WirelessStats {
+            signal: level(2),
+            noise: level(3),
+            ..Default::default()
+        })
+    }
+
+    /// Splits an indented `key: value` line.
+    fn split_field(line: &str) -> Option<(&str, &str)> {
+        let (key, value) = line.split_once(':')?;
+        Some((key.trim(), value.trim()))
+    }
+
+    /// Parses values like `-58 dBm` or `-58 [-60, -61] dBm`.
+    fn parse_dbm(value: &str) -> Option<i32> {
+        value.split_whitespace().next()?.parse().ok()
+    }
+
+    /// Parses bitrates like `650.0 MBit/s VHT-MCS 7 80MHz short GI VHT-NSS 2`.
+    fn merge_bitrate(&mut self, value: &str, tx: bool) {
+        let tokens: Vec<&str> = value.split_whitespace().collect();
+        let bitrate = tokens.first().and_then(|rate| rate.parse().ok());
+        let mcs = tokens
+            .iter()
+            .position(|token| *token == "MCS" || token.ends_with("-MCS"))
+            .and_then(|i| tokens.get(i + 1))
+            .and_then(|mcs| mcs.parse().ok());
+        let width = tokens
+            .iter()
+            .find_map(|token| token.strip_suffix("MHz"))
+            .and_then(|width| width.parse().ok());
+
+        if tx {
+            self.tx_bitrate = bitrate;
+            self.tx_mcs = mcs;
+        } else {
+            self.rx_bitrate = bitrate;
+            self.rx_mcs = mcs;
+        }
+        if self.channel_width.is_none() {
+            self.channel_width = width;
+        }
+    }
+}
+
+/// Periodically samples the statistics of a wireless interface in the background.
+pub struct WirelessSampler {
+    interface: String,
+    stats: Arc<Mutex<Option<WirelessStats>>>,
+}
+
+impl WirelessSampler {
+    pub fn new(interface: String) -> Self {
+        WirelessSampler {
+            interface,
+            stats: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Shared handle to the most recent sample, `None` while the interface isn't associated.
+    pub fn stats(&self) -> Arc<Mutex<Option<WirelessStats>>> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Samples until `shutdown` is triggered. Must be called from within a Tokio runtime.
+    pub fn run(self, shutdown: Shutdown) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!("Sampling wireless statistics of {}", self.interface);
+            let mut last_counters: Option<(Option<u64>, Option<u64>)> = None;
+            loop {
+                let mut stats = tokio::select! {
+                    stats = self.sample() => stats,
+                    _ = shutdown.wait() => break,
+                };
+                if let Some(stats) = stats.as_mut() {
+                    // Report the retry counters per sample instead of since association.
+                    let counters = (stats.tx_retries, stats.tx_failed);
+                    if let Some((retries, failed)) = last_counters {
+                        stats.tx_retries = Self::delta(counters.0, retries);
+                        stats.tx_failed = Self::delta(counters.1, failed);
+                    } else {
+                        stats.tx_retries = None;
+                        stats.tx_failed = None;
+                    }
+                    last_counters = Some(counters);
+                }
+                debug!("Wireless statistics: {:?}", stats);
+                *self.stats.lock().unwrap() = stats;
+                tokio::select! {
+                    _ = tokio::time::sleep(SAMPLE_INTERVAL) => {}
+                    _ = shutdown.wait() => break,
+                }
+            }
+            debug!("Stopped sampling wireless statistics of {}", self.interface);
+        })
+    }
+
+    async fn sample(&self) -> Option<WirelessStats> {
+        match self.iw(&["link"]).await {
+            Some(link) => {
+                let mut stats = WirelessStats::parse_link(&link)?;
+                if let Some(station_dump) = self.iw(&["station", "dump"]).await {
+                    stats.merge_station_dump(&station_dump);
+                }
+                if let Some(survey_dump) = self.iw(&["survey", "dump"]).await {
+                    stats.merge_survey_dump(&survey_dump);
+                }
+                if let Some(info) = self.iw(&["info"]).await {
+                    stats.merge_info(&info);
+                }
+                Some(stats)
+            }
+            None => {
+                let contents = fs::read_to_string("/proc/net/wireless").ok()?;
+                WirelessStats::parse_proc(&contents, &self.interface)
+            }
+        }
+    }
+
+    /// Runs `iw dev <interface> <args>`, `None` if `iw` is missing or fails.
+    async fn iw(&self, args: &[&str]) -> Option<String> {
+        let output = Command::new("iw")
+            .arg("dev")
+            .arg(&self.interface)
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn delta(current: Option<u64>, last: Option<u64>) -> Option<u64> {
+        Some(current?.saturating_sub(last?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_iw_link() {
+        let stats =
+            WirelessStats::parse_link(include_str!("wireless/fixtures/iw_link.txt")).unwrap();
+        assert_eq!(stats.bssid.as_deref(), Some("64:d1:54:9a:3b:10"));
+        assert_eq!(stats.ssid.as_deref(), Some("campus-net"));
+        assert_eq!(stats.frequency, Some(5180));
+        assert_eq!(stats.signal, Some(-58));
+        assert_eq!(stats.tx_bitrate, Some(650.0));
+        assert_eq!(stats.rx_bitrate, Some(866.7));
+        assert_eq!(stats.tx_mcs, Some(7));
+        assert_eq!(stats.rx_mcs, Some(9));
+        assert_eq!(stats.channel_width, Some(80));
+    }
+
+    #[test]
+    fn disconnected_link_has_no_stats() {
+        assert_eq!(
+            WirelessStats::parse_link(include_str!("wireless/fixtures/iw_link_disconnected.txt")),
+            None
+        );
+    }
+
+    #[test]
+    fn merges_station_survey_and_info() {
+        let mut stats =
+            WirelessStats::parse_link(include_str!("wireless/fixtures/iw_link.txt")).unwrap();
+        stats.merge_station_dump(include_str!("wireless/fixtures/iw_station_dump.txt"));
+        stats.merge_survey_dump(include_str!("wireless/fixtures/iw_survey_dump.txt"));
+        stats.merge_info(include_str!("wireless/fixtures/iw_info.txt"));
+        assert_eq!(stats.tx_retries, Some(417));
+        assert_eq!(stats.tx_failed, Some(3));
+        assert_eq!(stats.noise, Some(-92));
+        assert_eq!(stats.channel_width, Some(80));
+    }
+
+    #[test]
+    fn parses_proc_net_wireless() {
+        let contents = include_str!("wireless/fixtures/proc_net_wireless.txt");
+        let stats = WirelessStats::parse_proc(contents, "wlan0").unwrap();
+        assert_eq!(stats.signal, Some(-58));
+        assert_eq!(stats.noise, None);
+        assert_eq!(WirelessStats::parse_proc(contents, "wlan1"), None);
+    }
+
+    #[tokio::test]
+    async fn sampler_stops_with_the_shutdown() {
+        let shutdown = Shutdown::new();
+        let task = WirelessSampler::new("netmap-test0".to_string()).run(shutdown.clone());
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .expect("sampler still running")
+            .unwrap();
+    }
+}
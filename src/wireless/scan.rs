@@ -0,0 +1,284 @@
+use crate::gps::Position;
+use crate::record::RecordWriter;
+use crate::shutdown::Shutdown;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+use tracing::{debug, info, warn};
+
+/// An access point seen in a scan.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccessPoint {
+    pub bssid: String,
+    /// empty for hidden networks
+    pub ssid: String,
+    /// in MHz
+    pub frequency: Option<u32>,
+    pub channel: Option<u32>,
+    /// in dBm
+    pub signal: Option<f64>,
+    /// e.g. `Open`, `WEP`, `WPA`, `WPA2-PSK`, `WPA3-SAE`, `WPA2-802.1X`
+    pub security: String,
+    /// whether the scanning interface is associated with this access point
+    pub associated: bool,
+}
+
+impl AccessPoint {
+    /// Parses the output of `iw dev <interface> scan` or `iw dev <interface> scan dump`.
+    pub fn parse_scan(output: &str) -> Vec<Self> {
+        let mut access_points = Vec::new();
+        let mut current: Option<(AccessPoint, ScanFlags)> = None;
+
+        for line in output.lines() {
+            if let Some(bss) = line.strip_prefix("BSS ") {
+                access_points.extend(current.take().map(|(ap, flags)| ap.finish(flags)));
+                let bssid: String = bss
+                    .chars()
+                    .take_while(|c| c.is_ascii_hexdigit() || *c == ':')
+                    .collect();
+                current = Some((
+                    AccessPoint {
+                        bssid,
+                        ssid: String::new(),
+                        frequency: None,
+                        channel: None,
+                        signal: None,
+                        security: String::new(),
+                        associated: bss.ends_with("-- associated"),
+                    },
+                    ScanFlags::default(),
+                ));
+                continue;
+            }
+            let Some((ap, flags)) = current.as_mut() else {
+                continue;
+            };
+
+            let line = line.trim();
+            if let Some(suites) = line.strip_prefix("* Authentication suites:") {
+                flags.authentication = suites.split_whitespace().map(String::from).collect();
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                "freq" => ap.frequency = value.parse::<f64>().ok().map(|freq| freq as u32),
+                "signal" => {
+                    ap.signal = value.split_whitespace().next().and_then(|s| s.parse().ok())
+                }
+                "SSID" => ap.ssid = value.to_string(),
+                "capability" => flags.privacy = value.split_whitespace().any(|c| c == "Privacy"),
+                "RSN" => flags.rsn = true,
+                "WPA" => flags.wpa = true,
+                _ => {}
+            }
+        }
+        access_points.extend(current.map(|(ap, flags)| ap.finish(flags)));
+        access_points
+    }
+
+    fn finish(mut self, flags: ScanFlags) -> Self {
+        self.channel = self.frequency.and_then(channel);
+        self.security = flags.security();
+        self
+    }
+}
+
+/// Security related elements collected while parsing one BSS.
+#[derive(Default)]
+struct ScanFlags {
+    privacy: bool,
+    rsn: bool,
+    wpa: bool,
+    authentication: Vec<String>,
+}
+
+impl ScanFlags {
+    fn security(&self) -> String {
+        let suite = |name: &str| self.authentication.iter().any(|s| s == name);
+        if self.rsn {
+            if suite("SAE") && !suite("PSK") {
+                "WPA3-SAE".to_string()
+            } else if suite("PSK") {
+                "WPA2-PSK".to_string()
+            } else if suite("IEEE") || suite("802.1X") {
+                "WPA2-802.1X".to_string()
+            } else {
+                "WPA2".to_string()
+            }
+        } else if self.wpa {
+            "WPA".to_string()
+        } else if self.privacy {
+            "WEP".to_string()
+        } else {
+            "Open".to_string()
+        }
+    }
+}
+
+/// IEEE 802.11 channel number of a frequency in MHz.
+fn channel(frequency: u32) -> Option<u32> {
+    match frequency {
+        2484 => Some(14),
+        2412..=2472 => Some((frequency - 2407) / 5),
+        5955..=7115 => Some((frequency - 5950) / 5),
+        5000..=5925 => Some((frequency - 5000) / 5),
+        _ => None,
+    }
+}
+
+/// Access points visible at one position.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScanRecord {
+    pub position: Position,
+    pub interface: String,
+    pub access_points: Vec<AccessPoint>,
+}
+
+/// Periodically scans for access points and records them with the current position.
+pub struct Scanner {
+    interface: String,
+    interval: Duration,
+    position: Arc<Mutex<Position>>,
+}
+
+impl Scanner {
+    pub fn new(interface: String, interval: Duration, position: Arc<Mutex<Position>>) -> Self {
+        Scanner {
+            interface,
+            interval,
+            position,
+        }
+    }
+
+    /// Scans until `shutdown` is triggered. Must be called from within a Tokio runtime.
+    pub fn run(self, output: RecordWriter, shutdown: Shutdown) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(
+                "Scanning for access points on {} every {:?}",
+                self.interface, self.interval
+            );
+            loop {
+                let access_points = tokio::select! {
+                    access_points = self.scan() => access_points,
+                    _ = shutdown.wait() => break,
+                };
+                match access_points {
+                    Some(access_points) => {
+                        info!("{} access points visible", access_points.len());
+                        let record = ScanRecord {
+                            position: self.position.lock().unwrap().clone(),
+                            interface: self.interface.clone(),
+                            access_points,
+                        };
+                        if let Err(e) = output.write(record) {
+                            warn!("Couldn't write scan record: {}", e);
+                        }
+                    }
+                    None => warn!("Scanning on {} failed", self.interface),
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(self.interval) => {}
+                    _ = shutdown.wait() => break,
+                }
+            }
+            debug!("Stopped scanning on {}", self.interface);
+        })
+    }
+
+    /// Triggers a scan, falling back to the cached results if the interface is busy or scanning isn't permitted.
+    async fn scan(&self) -> Option<Vec<AccessPoint>> {
+        let output = match self.iw(&["scan"]).await {
+            Some(output) => output,
+            None => self.iw(&["scan", "dump"]).await?,
+        };
+        Some(AccessPoint::parse_scan(&output))
+    }
+
+    async fn iw(&self, args: &[&str]) -> Option<String> {
+        let output = Command::new("iw")
+            .arg("dev")
+            .arg(&self.interface)
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .ok()?;
+        if !output.status.success() {
+            debug!(
+                "iw {:?} failed: {}",
+                args,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scan_dump() {
+        let access_points = AccessPoint::parse_scan(include_str!("fixtures/iw_scan.txt"));
+        assert_eq!(access_points.len(), 4);
+
+        let campus = &access_points[0];
+        assert_eq!(campus.bssid, "64:d1:54:9a:3b:10");
+        assert_eq!(campus.ssid, "campus-net");
+        assert_eq!(campus.frequency, Some(5180));
+        assert_eq!(campus.channel, Some(36));
+        assert_eq!(campus.signal, Some(-58.0));
+        assert_eq!(campus.security, "WPA2-802.1X");
+        assert!(campus.associated);
+
+        let guest = &access_points[1];
+        assert_eq!(guest.channel, Some(6));
+        assert_eq!(guest.security, "WPA2-PSK");
+        assert!(!guest.associated);
+
+        let hidden = &access_points[2];
+        assert_eq!(hidden.ssid, "");
+        assert_eq!(hidden.frequency, Some(2412));
+        assert_eq!(hidden.channel, Some(1));
+        assert_eq!(hidden.security, "Open");
+
+        let lab = &access_points[3];
+        assert_eq!(lab.channel, Some(1));
+        assert_eq!(lab.security, "WPA3-SAE");
+    }
+
+    #[test]
+    fn maps_frequencies_to_channels() {
+        assert_eq!(channel(2412), Some(1));
+        assert_eq!(channel(2484), Some(14));
+        assert_eq!(channel(5500), Some(100));
+        assert_eq!(channel(6115), Some(33));
+        assert_eq!(channel(900), None);
+    }
+
+    #[tokio::test]
+    async fn scanner_stops_with_the_shutdown() {
+        let shutdown = Shutdown::new();
+        let scanner = Scanner::new(
+            "netmap-test0".to_string(),
+            Duration::from_secs(60),
+            Arc::new(Mutex::new(Position::default())),
+        );
+        let task = scanner.run(RecordWriter::default(), shutdown.clone());
+        // Waits out the first scan, then stops during the interval.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .expect("scanner still running")
+            .unwrap();
+    }
+}
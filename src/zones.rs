@@ -0,0 +1,216 @@
+//! Named areas, such as buildings, floors or rooms, that measurements are grouped by.
+//!
+//! Zones are read from GeoJSON polygons. Outdoors their coordinates are longitude and latitude as usual.
+//! Features with a `floor` property are on the floor plan of that floor instead, with `x` and `y` in meters,
+//! and only hold positions entered on the floor plan.
+
+use crate::error::NetMapError;
+use crate::gps::Position;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Ring of a polygon as `[x, y]` points, longitude and latitude outdoors.
+type Ring = Vec<[f64; 2]>;
+
+/// An area with a name. Positions on the boundary may or may not be inside.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    /// `name` property of the feature, else its `id`
+    pub name: String,
+    /// floor of the floor plan the polygons are on, `None` for coordinates
+    pub floor: Option<i32>,
+    /// polygons as an outer ring followed by its holes
+    polygons: Vec<Vec<Ring>>,
+}
+
+impl Zone {
+    pub fn contains(&self, position: &Position) -> bool {
+        if !position.has_fix() {
+            return false;
+        }
+        let point = match (self.floor, position.indoor) {
+            (None, None) => [position.lon, position.lat],
+            (Some(floor), Some(indoor)) if indoor.floor == floor => [indoor.x, indoor.y],
+            _ => return false,
+        };
+        self.polygons.iter().any(|rings| {
+            // inside the outer ring and not in a hole
+            rings
+                .iter()
+                .enumerate()
+                .all(|(i, ring)| in_ring(ring, point) == (i == 0))
+        })
+    }
+}
+
+/// Whether `point` is inside `ring`, by counting the edges a ray from it to the east crosses.
+fn in_ring(ring: &[[f64; 2]], [x, y]: [f64; 2]) -> bool {
+    let mut inside = false;
+    for (i, &[x1, y1]) in ring.iter().enumerate() {
+        let [x0, y0] = ring[(i + ring.len() - 1) % ring.len()];
+        if (y1 > y) != (y0 > y) && x < x0 + (y - y0) / (y1 - y0) * (x1 - x0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Zones that the intervals of a run are labeled with, see [`crate::RecordWriter::zones`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Zones {
+    pub zones: Vec<Zone>,
+}
+
+impl Zones {
+    /// Reads the polygons of a GeoJSON feature collection, feature or geometry.
+    pub fn load(path: &Path) -> Result<Self, NetMapError> {
+        let error =
+            |message: String| NetMapError::Zones(format!("{}: {}", path.display(), message));
+        let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let geojson = serde_json::from_str(&text).map_err(|e| error(e.to_string()))?;
+        Zones::from_geojson(&geojson).map_err(error)
+    }
+
+    pub fn from_geojson(geojson: &Value) -> Result<Self, String> {
+        let features = match geojson["type"].as_str() {
+            Some("FeatureCollection") => geojson["features"]
+                .as_array()
+                .ok_or("features isn't an array")?
+                .iter()
+                .collect(),
+            Some("Feature") => vec![geojson],
+            Some(_) => {
+                return Ok(Zones {
+                    zones: vec![zone(None, geojson, 0)?],
+                })
+            }
+            None => return Err("not a GeoJSON object".to_string()),
+        };
+        let zones = features
+            .into_iter()
+            .enumerate()
+            .map(|(i, feature)| zone(Some(feature), &feature["geometry"], i))
+            .collect::<Result<_, _>>()?;
+        Ok(Zones { zones })
+    }
+
+    /// Names of the zones that `position` is in, in the order of the file.
+    pub fn names(&self, position: &Position) -> Vec<String> {
+        self.zones
+            .iter()
+            .filter(|zone| zone.contains(position))
+            .map(|zone| zone.name.clone())
+            .collect()
+    }
+}
+
+/// Zone of the `index`th feature with `geometry`.
+fn zone(feature: Option<&Value>, geometry: &Value, index: usize) -> Result<Zone, String> {
+    let properties = feature.map_or(&Value::Null, |feature| &feature["properties"]);
+    let name = match (&properties["name"], feature.map(|feature| &feature["id"])) {
+        (Value::String(name), _) => name.clone(),
+        (Value::Null, Some(Value::String(id))) => id.clone(),
+        (Value::Null, Some(Value::Number(id))) => id.to_string(),
+        _ => format!("zone {}", index + 1),
+    };
+    let floor = match &properties["floor"] {
+        Value::Null => None,
+        floor => Some(
+            floor
+                .as_i64()
+                .and_then(|floor| i32::try_from(floor).ok())
+                .ok_or_else(|| format!("{}: invalid floor {}", name, floor))?,
+        ),
+    };
+    let polygons = match geometry["type"].as_str() {
+        Some("Polygon") => vec![polygon(&geometry["coordinates"])],
+        Some("MultiPolygon") => geometry["coordinates"]
+            .as_array()
+            .map(|polygons| polygons.iter().map(polygon).collect())
+            .unwrap_or_default(),
+        other => {
+            return Err(format!(
+                "{}: expected a Polygon or MultiPolygon, not {}",
+                name,
+                other.unwrap_or("nothing")
+            ))
+        }
+    };
+    let polygons = polygons
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .filter(|polygons| !polygons.is_empty())
+        .ok_or_else(|| format!("{}: invalid coordinates", name))?;
+    Ok(Zone {
+        name,
+        floor,
+        polygons,
+    })
+}
+
+/// Rings of a polygon's coordinates, `None` unless every ring has at least three points.
+fn polygon(coordinates: &Value) -> Option<Vec<Ring>> {
+    coordinates
+        .as_array()
+        .filter(|rings| !rings.is_empty())?
+        .iter()
+        .map(|ring| {
+            let ring = ring
+                .as_array()?
+                .iter()
+                .map(|point| Some([point[0].as_f64()?, point[1].as_f64()?]))
+                .collect::<Option<Ring>>()?;
+            (ring.len() >= 3).then_some(ring)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gps::IndoorPosition;
+    use serde_json::json;
+
+    #[test]
+    fn labels_positions_with_their_zones() {
+        let zones = Zones::from_geojson(&json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": {"name": "campus"},
+                    "geometry": {"type": "Polygon", "coordinates": [
+                        [[13.0, 52.0], [14.0, 52.0], [14.0, 53.0], [13.0, 53.0], [13.0, 52.0]],
+                        [[13.4, 52.4], [13.6, 52.4], [13.6, 52.6], [13.4, 52.6], [13.4, 52.4]]
+                    ]}
+                },
+                {
+                    "type": "Feature",
+                    "id": "lab",
+                    "properties": {"floor": 1},
+                    "geometry": {"type": "Polygon", "coordinates": [
+                        [[0.0, 0.0], [10.0, 0.0], [10.0, 5.0], [0.0, 5.0]]
+                    ]}
+                }
+            ]
+        }))
+        .unwrap();
+        let time = "2023-04-01T12:00:00.000Z".to_string();
+        let outdoor = |lat, lon| Position::new(time.clone(), lat, lon);
+        let indoor = |x, y, floor| Position::indoor(time.clone(), IndoorPosition { x, y, floor });
+
+        assert_eq!(zones.names(&outdoor(52.2, 13.2)), ["campus"]);
+        // in the courtyard
+        assert!(zones.names(&outdoor(52.5, 13.5)).is_empty());
+        assert!(zones.names(&outdoor(54.0, 13.5)).is_empty());
+        assert!(zones.names(&Position::default()).is_empty());
+        assert_eq!(zones.names(&indoor(2.0, 3.0, 1)), ["lab"]);
+        assert!(zones.names(&indoor(2.0, 3.0, 0)).is_empty());
+        assert!(zones.names(&indoor(12.0, 3.0, 1)).is_empty());
+
+        assert!(
+            Zones::from_geojson(&json!({"type": "Point", "coordinates": [13.0, 52.0]})).is_err()
+        );
+    }
+}